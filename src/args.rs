@@ -1,17 +1,368 @@
-use crate::models::AppConfig;
+use crate::constants::defaults::AUDIO_FALLBACK_MAX_HEIGHT;
+use crate::constants::PLAYABLE_AUDIO_CODECS;
+use crate::models::{AppConfig, FormatSelection, ProtocolPreference, QualityConfig, QualityOverrides};
 use crate::logger::Logger;
+use crate::rules;
+
+/// Comparison operators accepted inside `[...]` format filters
+const FILTER_OPERATORS: [&str; 10] = ["<=", ">=", "!=", "^=", "$=", "*=", "~=", "=", "<", ">"];
 
 pub struct ArgumentParser;
 
 impl ArgumentParser {
-    pub fn filter_arguments(args: &[String], config: &AppConfig) -> Vec<String> {
-        Self::filter_arguments_with_logger(args, config, None)
+    /// Finds the media URL among the arguments passed by VRChat
+    pub fn requested_url(args: &[String]) -> Option<&str> {
+        args.iter().map(String::as_str).find(|arg| Self::is_http_url(arg))
+    }
+
+    /// Checks for an http(s) scheme, in any case as URL schemes are case-insensitive
+    pub fn is_http_url(arg: &str) -> bool {
+        arg.split_once("://")
+            .is_some_and(|(scheme, _)| scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+    }
+
+    /// Values passed on to yt-dlp after allowed options, such as the URL after `--get-url`
+    pub fn forwarded_values<'a>(args: &'a [String], config: &AppConfig) -> Vec<&'a str> {
+        let mut values = Vec::new();
+        let mut i = 0;
+        // Mirrors the value detection in filter_arguments
+        while i < args.len() {
+            if config.allowed_args.contains(&args[i]) {
+                if let Some(next) = args.get(i + 1).filter(|next| !next.starts_with('-')) {
+                    values.push(next.as_str());
+                    i += 1;
+                }
+            }
+            i += 1;
+        }
+        values
+    }
+
+    /// Finds the `-f` format selector in the configured custom args
+    pub fn format_selector(config: &AppConfig) -> Option<&str> {
+        Self::format_selector_in(&config.custom_args)
+    }
+
+    /// Finds the value following `-f`/`--format` in an argument list
+    pub fn format_selector_in(args: &[String]) -> Option<&str> {
+        args.iter()
+            .position(|arg| arg == "-f" || arg == "--format")
+            .and_then(|i| args.get(i + 1))
+            .map(String::as_str)
+    }
+
+    /// Returns the arguments with any `-f`/`--format` option and its value removed
+    pub fn without_format_selector(args: &[String]) -> Vec<String> {
+        let mut kept = Vec::new();
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            if arg == "-f" || arg == "--format" {
+                iter.next();
+            } else {
+                kept.push(arg.clone());
+            }
+        }
+
+        kept
+    }
+
+    /// Extracts the height limit from a format selector such as `best[height<=1080]`
+    pub fn max_height(selector: &str) -> Option<u32> {
+        let start = selector.find("height<=")? + "height<=".len();
+        let digits: String = selector[start..]
+            .trim_start_matches('?')
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        digits.parse().ok()
+    }
+
+    /// Returns the quality settings for a request, with live overrides when a live rule
+    /// matches and the player profile's constraints on top
+    pub fn quality_for(args: &[String], config: &AppConfig, player: Option<&str>) -> QualityConfig {
+        let rule = Self::requested_url(args).and_then(|url| rules::find_rule(&config.rules, url));
+
+        let mut quality = if rule.is_some_and(|rule| rule.live) {
+            config.quality.for_live()
+        } else {
+            config.quality.clone()
+        };
+        if rule.is_some_and(|rule| rule.audio_only) {
+            quality.audio_only = true;
+        }
+
+        match player.and_then(|name| config.players.profile(name)) {
+            Some(profile) => quality.with_overrides(&profile.quality),
+            None => quality,
+        }
+    }
+
+    /// Compiles the structured quality settings into a yt-dlp `-f` selector.
+    ///
+    /// Preferred codecs and protocol become a fallback chain, so a video lacking
+    /// them still resolves within the height and fps limits.
+    pub fn compile_format_selector(quality: &QualityConfig) -> String {
+        if quality.audio_only {
+            return Self::compile_audio_selector(quality);
+        }
+
+        let mut limits = String::new();
+        if let Some(height) = quality.max_height {
+            limits.push_str(&format!("[height<={}]", height));
+        }
+        if let Some(fps) = quality.max_fps {
+            limits.push_str(&format!("[fps<={}]", fps));
+        }
+
+        let protocol = Self::protocol_filter(quality.protocol);
+
+        // `best` only picks formats carrying both audio and video, as VRChat plays a single URL
+        let base = |filters: &str| format!("best{}", filters);
+
+        let mut chain: Vec<String> = quality
+            .preferred_codecs
+            .iter()
+            .map(|codec| base(&format!("{}{}[vcodec^={}]", limits, protocol, codec)))
+            .collect();
+        chain.push(base(&format!("{}{}", limits, protocol)));
+        if !quality.protocol_required {
+            chain.push(base(&limits));
+        }
+        chain.dedup();
+
+        chain.join("/")
+    }
+
+    /// Applies a player profile's constraints on top of a hand-written selector.
+    ///
+    /// The profile's protocol replaces any protocol filter in the selector, falling back to
+    /// the unfiltered selector unless required; its height and fps limits narrow it further.
+    pub fn constrain_selector(selector: &str, overrides: &QualityOverrides) -> String {
+        let mut limits = String::new();
+        if let Some(height) = overrides.max_height {
+            limits.push_str(&format!("[height<={}]", height));
+        }
+        if let Some(fps) = overrides.max_fps {
+            limits.push_str(&format!("[fps<={}]", fps));
+        }
+
+        let (base, protocol) = match overrides.protocol {
+            Some(protocol) => (Self::without_filters(selector, "protocol"), Self::protocol_filter(protocol)),
+            None => (selector.to_string(), ""),
+        };
+        if limits.is_empty() && protocol.is_empty() {
+            return selector.to_string();
+        }
+
+        let constrained = format!("({}){}{}", base, limits, protocol);
+        if protocol.is_empty() || overrides.protocol_required == Some(true) {
+            constrained
+        } else {
+            format!("{}/({}){}", constrained, base, limits)
+        }
+    }
+
+    /// Removes the `[...]` filters on a field, e.g. every `[protocol^=m3u8]`
+    fn without_filters(selector: &str, field: &str) -> String {
+        let mut kept = String::new();
+        let mut rest = selector;
+
+        while let Some(start) = rest.find('[') {
+            let Some(len) = rest[start..].find(']') else { break };
+            let filter = &rest[start + 1..start + len];
+            kept.push_str(&rest[..start]);
+            let on_field = filter
+                .trim_start_matches('!')
+                .strip_prefix(field)
+                .is_some_and(|op| !op.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'));
+            if !on_field {
+                kept.push_str(&rest[start..=start + len]);
+            }
+            rest = &rest[start + len + 1..];
+        }
+
+        kept.push_str(rest);
+        kept
+    }
+
+    fn protocol_filter(protocol: ProtocolPreference) -> &'static str {
+        match protocol {
+            ProtocolPreference::Hls => "[protocol^=m3u8]",
+            ProtocolPreference::Progressive => "[protocol^=http][protocol!*=dash]",
+            ProtocolPreference::Any => "",
+        }
+    }
+
+    /// Builds an audio-only selector limited to codecs VRChat can decode.
+    ///
+    /// Sites without standalone audio fall back to the smallest reasonable
+    /// combined format rather than failing.
+    fn compile_audio_selector(quality: &QualityConfig) -> String {
+        // Audio is rarely offered over HLS, so the protocol only filters when required
+        let protocol = if quality.protocol_required {
+            Self::protocol_filter(quality.protocol)
+        } else {
+            ""
+        };
+        let fallback_height = quality
+            .max_height
+            .unwrap_or(AUDIO_FALLBACK_MAX_HEIGHT)
+            .min(AUDIO_FALLBACK_MAX_HEIGHT);
+
+        let mut chain: Vec<String> = PLAYABLE_AUDIO_CODECS
+            .iter()
+            .map(|codec| format!("bestaudio[acodec^={}]{}", codec, protocol))
+            .collect();
+        chain.push(format!("best[height<={}]{}", fallback_height, protocol));
+        chain.push(format!("worst{}", protocol));
+
+        chain.join("/")
+    }
+
+    /// Checks a hand-written `-f` expression for syntax errors yt-dlp would reject
+    pub fn validate_format_selector(selector: &str) -> std::result::Result<(), String> {
+        if selector.trim().is_empty() {
+            return Err("format selector is empty".to_string());
+        }
+
+        let mut depth = 0i32;
+        let mut token = String::new();
+        let mut chars = selector.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '[' => {
+                    let mut filter = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) => filter.push(c),
+                            None => return Err(format!("unmatched '[' in '{}'", selector)),
+                        }
+                    }
+                    Self::validate_format_filter(&filter)?;
+                    token.push('[');
+                }
+                ']' => return Err(format!("unmatched ']' in '{}'", selector)),
+                '(' => {
+                    if !token.is_empty() {
+                        return Err(format!("unexpected '(' after '{}'", token));
+                    }
+                    depth += 1;
+                }
+                ')' => {
+                    if token.is_empty() {
+                        return Err(format!("empty group or alternative in '{}'", selector));
+                    }
+                    depth -= 1;
+                    if depth < 0 {
+                        return Err(format!("unmatched ')' in '{}'", selector));
+                    }
+                }
+                '/' | ',' | '+' => {
+                    if token.is_empty() {
+                        return Err(format!("missing format before '{}' in '{}'", c, selector));
+                    }
+                    token.clear();
+                }
+                c if c.is_ascii_alphanumeric() || matches!(c, '*' | '-' | '_' | '.') => token.push(c),
+                c if c.is_whitespace() => {}
+                c => return Err(format!("unexpected character '{}' in '{}'", c, selector)),
+            }
+        }
+
+        if depth != 0 {
+            return Err(format!("unmatched '(' in '{}'", selector));
+        }
+        if token.is_empty() {
+            return Err(format!("selector '{}' ends without a format", selector));
+        }
+        Ok(())
+    }
+
+    /// Checks a single `[...]` filter such as `height<=?1080` or `!is_live`
+    fn validate_format_filter(filter: &str) -> std::result::Result<(), String> {
+        let filter = filter.trim();
+        let field_len = filter
+            .trim_start_matches('!')
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(filter.trim_start_matches('!').len());
+        let (field, rest) = filter.trim_start_matches('!').split_at(field_len);
+
+        if field.is_empty() {
+            return Err(format!("filter '[{}]' has no field name", filter));
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let rest = rest.trim_start().trim_start_matches('!');
+        let value = FILTER_OPERATORS
+            .iter()
+            .find_map(|op| rest.strip_prefix(op))
+            .ok_or_else(|| format!("filter '[{}]' has an unknown operator", filter))?;
+
+        if value.starts_with(['<', '>', '=', '!']) {
+            return Err(format!("filter '[{}]' has an unknown operator", filter));
+        }
+        if value.trim().trim_start_matches('?').is_empty() {
+            return Err(format!("filter '[{}]' has no value", filter));
+        }
+        Ok(())
+    }
+
+    pub fn filter_arguments(args: &[String], config: &AppConfig, player: Option<&str>) -> Vec<String> {
+        Self::filter_arguments_with_logger(args, config, player, None)
+    }
+
+    /// Picks the `-f` selector for a request.
+    ///
+    /// The quality settings are compiled unless `custom_args` has a hand-written selector;
+    /// player constraints still apply on top of that, and audio-only requests replace it.
+    fn selector_for(args: &[String], config: &AppConfig, player: Option<&str>, logger: Option<&Logger>) -> Option<String> {
+        let hand_written = Self::format_selector(config);
+        if config.quality.selection != FormatSelection::YtDlp {
+            return hand_written.map(str::to_string);
+        }
+
+        let quality = Self::quality_for(args, config, player);
+        let selector = match hand_written {
+            Some(selector) if !quality.audio_only => {
+                let profile = player.and_then(|name| config.players.profile(name));
+                let constrained = match profile {
+                    Some(profile) => Self::constrain_selector(selector, &profile.quality),
+                    None => selector.to_string(),
+                };
+                if let Some(logger) = logger {
+                    logger.log_debug(&format!(
+                        "Using -f from custom_args with player {} constraints: {}",
+                        player.unwrap_or("(none)"),
+                        constrained
+                    ));
+                }
+                constrained
+            }
+            _ => {
+                let compiled = Self::compile_format_selector(&quality);
+                if let Some(logger) = logger {
+                    logger.log_debug(&format!(
+                        "Compiled quality settings for player {} to: -f {}",
+                        player.unwrap_or("(none)"),
+                        compiled
+                    ));
+                }
+                compiled
+            }
+        };
+        Some(selector)
     }
 
     /// Builds the complete argument list with optional logging
     pub fn filter_arguments_with_logger(
         args: &[String], 
         config: &AppConfig, 
+        player: Option<&str>,
         logger: Option<&Logger>
     ) -> Vec<String> {
         if let Some(logger) = logger {
@@ -56,18 +407,22 @@ impl ArgumentParser {
             logger.log_debug(&format!("After filtering: {} args kept", yt_dlp_args.len()));
         }
 
-        // Step 2: Add custom args from config (always passed to yt-dlp)
+        // Step 2: Add custom args from config (always passed to yt-dlp); -f is added in step 2b
         if !config.custom_args.is_empty() {
             if let Some(logger) = logger {
                 logger.log_debug(&format!("Adding {} custom args from config: {:?}", config.custom_args.len(), config.custom_args));
             }
-            for custom_arg in &config.custom_args {
-                yt_dlp_args.push(custom_arg.clone());
-            }
+            yt_dlp_args.extend(Self::without_format_selector(&config.custom_args));
         } else if let Some(logger) = logger {
             logger.log_debug("No custom args in config");
         }
 
+        // Step 2b: Choose the format selector
+        if let Some(selector) = Self::selector_for(args, config, player, logger) {
+            yt_dlp_args.push("-f".to_string());
+            yt_dlp_args.push(selector);
+        }
+
         // Step 3: Add cookies flag if enabled in config
         if config.cookies {
             let cookie_arg = format!("--cookies-from-browser={}", config.cookies_browser);
@@ -89,3 +444,135 @@ impl ArgumentParser {
         yt_dlp_args
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compile_default_quality_chains_codecs_then_drops_protocol() {
+        let selector = ArgumentParser::compile_format_selector(&QualityConfig::default());
+
+        assert_eq!(
+            selector,
+            "best[height<=1080][protocol^=m3u8][vcodec^=avc1]\
+             /best[height<=1080][protocol^=m3u8][vcodec^=h264]\
+             /best[height<=1080][protocol^=m3u8]\
+             /best[height<=1080]"
+        );
+        assert!(ArgumentParser::validate_format_selector(&selector).is_ok());
+    }
+
+    #[test]
+    fn compile_required_protocol_never_merges_streams() {
+        let quality = QualityConfig {
+            max_height: Some(720),
+            max_fps: Some(30),
+            preferred_codecs: Vec::new(),
+            protocol: ProtocolPreference::Progressive,
+            protocol_required: true,
+            ..Default::default()
+        };
+
+        let selector = ArgumentParser::compile_format_selector(&quality);
+        assert_eq!(selector, "best[height<=720][fps<=30][protocol^=http][protocol!*=dash]");
+        // A merge would print separate video and audio URLs, which VRChat can't play
+        assert!(!selector.contains('+'));
+    }
+
+    #[test]
+    fn compile_audio_only_falls_back_to_small_combined_format() {
+        let quality = QualityConfig {
+            audio_only: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ArgumentParser::compile_format_selector(&quality),
+            "bestaudio[acodec^=mp4a]/bestaudio[acodec^=mp3]/best[height<=360]/worst"
+        );
+    }
+
+    #[test]
+    fn validate_accepts_yt_dlp_syntax() {
+        for selector in [
+            "best",
+            "bv*[height<=?1080]+ba/b",
+            "(bv[vcodec^=avc1]/bv)+ba",
+            "best[height<=1080][protocol^=m3u8]",
+            "bestaudio[!is_live]",
+            "18,22",
+        ] {
+            assert!(ArgumentParser::validate_format_selector(selector).is_ok(), "{}", selector);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_malformed_selectors() {
+        for selector in [
+            "",
+            "best[height<=1080",
+            "best]",
+            "(best",
+            "best)",
+            "best//worst",
+            "best/",
+            "best[<=1080]",
+            "best[height<<1080]",
+            "best[height<=]",
+            "best(worst)",
+            "best;worst",
+        ] {
+            assert!(ArgumentParser::validate_format_selector(selector).is_err(), "{}", selector);
+        }
+    }
+
+    #[test]
+    fn constrain_replaces_protocol_and_falls_back() {
+        let overrides = QualityOverrides {
+            max_height: Some(720),
+            protocol: Some(ProtocolPreference::Progressive),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ArgumentParser::constrain_selector("best[protocol^=m3u8]/worst", &overrides),
+            "(best/worst)[height<=720][protocol^=http][protocol!*=dash]/(best/worst)[height<=720]"
+        );
+    }
+
+    #[test]
+    fn constrain_required_protocol_has_no_fallback() {
+        let overrides = QualityOverrides {
+            protocol: Some(ProtocolPreference::Hls),
+            protocol_required: Some(true),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ArgumentParser::constrain_selector("best", &overrides),
+            "(best)[protocol^=m3u8]"
+        );
+    }
+
+    #[test]
+    fn constrain_without_overrides_keeps_selector() {
+        assert_eq!(
+            ArgumentParser::constrain_selector("bv+ba/b", &QualityOverrides::default()),
+            "bv+ba/b"
+        );
+    }
+
+    #[test]
+    fn without_filters_only_removes_the_field() {
+        assert_eq!(
+            ArgumentParser::without_filters("best[protocol^=m3u8][height<=720][protocol!*=dash]", "protocol"),
+            "best[height<=720]"
+        );
+        // Fields sharing a prefix are kept
+        assert_eq!(
+            ArgumentParser::without_filters("best[protocols=x][!protocol]", "protocol"),
+            "best[protocols=x]"
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{CACHE_FILE_NAME, CACHE_LOCK_FILE_NAME};
+use crate::error::Result;
+
+/// A resolved media URL remembered for later requests
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CacheEntry {
+    pub url: String,
+    pub resolved_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Fingerprint of the content policy the video passed, if one was enabled
+    #[serde(default)]
+    pub content_policy: Option<String>,
+}
+
+impl CacheEntry {
+    /// Checks if the entry is still usable, keeping a safety margin before expiry
+    pub fn is_fresh(&self, margin: Duration) -> bool {
+        Utc::now() + margin < self.expires_at
+    }
+}
+
+/// Resolution cache persisted as JSON next to the executable.
+///
+/// Entries are kept in memory for long-running processes such as the daemon;
+/// misses re-read the file so concurrent shim processes see each other's writes.
+/// Saves go through a temporary file and a rename to stay atomic, and the
+/// read-modify-write holds an exclusive lock on a sidecar file so concurrent
+/// processes don't drop each other's entries.
+pub struct ResolutionCache {
+    path: PathBuf,
+    lock_path: PathBuf,
+    ttl: Duration,
+    memory: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl ResolutionCache {
+    pub fn new(app_dir: PathBuf, ttl_secs: u64) -> Self {
+        Self {
+            path: app_dir.join(CACHE_FILE_NAME),
+            lock_path: app_dir.join(CACHE_LOCK_FILE_NAME),
+            ttl: Duration::seconds(ttl_secs as i64),
+            memory: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Looks up a fresh entry for the key
+    pub fn get(&self, key: &str) -> Option<CacheEntry> {
+        let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = memory.get(key).filter(|e| e.is_fresh(Duration::zero())) {
+            return Some(entry.clone());
+        }
+
+        let entry = self
+            .load()
+            .remove(key)
+            .filter(|entry| entry.is_fresh(Duration::zero()))?;
+        memory.insert(key.to_string(), entry.clone());
+        Some(entry)
+    }
+
+    /// Stores a resolved URL, expiring it at the configured TTL or the URL's own
+    /// `expire=` timestamp, whichever comes first
+    pub fn insert(&self, key: &str, url: &str, content_policy: Option<String>) -> Result<CacheEntry> {
+        let now = Utc::now();
+        let mut expires_at = now + self.ttl;
+        if let Some(url_expiry) = Self::url_expiry(url) {
+            expires_at = expires_at.min(url_expiry);
+        }
+
+        let entry = CacheEntry {
+            url: url.to_string(),
+            resolved_at: now,
+            expires_at,
+            content_policy,
+        };
+
+        // The lock also serialises file writes within this process
+        let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+        memory.retain(|_, e| e.is_fresh(Duration::zero()));
+        memory.insert(key.to_string(), entry.clone());
+
+        // Other processes write the same file; the lock is released when dropped
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.lock_path)?;
+        lock.lock()?;
+        let mut entries = self.load();
+        entries.retain(|_, e| e.is_fresh(Duration::zero()));
+        entries.insert(key.to_string(), entry.clone());
+        self.save(&entries)?;
+
+        Ok(entry)
+    }
+
+    /// Reads the `expire` query parameter that signed CDN URLs (e.g. googlevideo) carry
+    pub fn url_expiry(url: &str) -> Option<DateTime<Utc>> {
+        let parsed = reqwest::Url::parse(url).ok()?;
+        let expire = parsed
+            .query_pairs()
+            .find(|(k, _)| k == "expire")
+            .and_then(|(_, v)| v.parse::<i64>().ok())
+            .or_else(|| {
+                // Some CDNs put it in the path as /expire/<ts>/
+                let mut segments = parsed.path_segments()?;
+                segments.find(|s| *s == "expire")?;
+                segments.next()?.parse::<i64>().ok()
+            })?;
+
+        Utc.timestamp_opt(expire, 0).single()
+    }
+
+    fn load(&self) -> HashMap<String, CacheEntry> {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, entries: &HashMap<String, CacheEntry>) -> Result<()> {
+        let tmp_path = self.path.with_extension(format!("tmp.{}", std::process::id()));
+        fs::write(&tmp_path, serde_json::to_string(entries)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc-ytdlp-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reads_expiry_from_query_or_path() {
+        let expiry = Utc.timestamp_opt(1_900_000_000, 0).single();
+
+        assert_eq!(ResolutionCache::url_expiry("https://r1.googlevideo.com/videoplayback?expire=1900000000&sig=x"), expiry);
+        assert_eq!(ResolutionCache::url_expiry("https://manifest.googlevideo.com/api/expire/1900000000/ei/x"), expiry);
+        assert_eq!(ResolutionCache::url_expiry("https://cdn.example.com/video.mp4"), None);
+    }
+
+    #[test]
+    fn entries_expire_with_their_url() {
+        let cache = ResolutionCache::new(test_dir("expiry"), 600);
+        let soon = Utc::now() + Duration::seconds(60);
+
+        let entry = cache
+            .insert("a", &format!("https://cdn.example.com/v?expire={}", soon.timestamp()), None)
+            .unwrap();
+        assert_eq!(entry.expires_at.timestamp(), soon.timestamp());
+
+        cache.insert("b", "https://cdn.example.com/v?expire=1000", None).unwrap();
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+    }
+
+    #[test]
+    fn entries_are_shared_through_the_file() {
+        let dir = test_dir("shared");
+        let writer = ResolutionCache::new(dir.clone(), 600);
+        writer.insert("key", "https://cdn.example.com/v.mp4", Some("policy".to_string())).unwrap();
+
+        let entry = ResolutionCache::new(dir, 600).get("key").unwrap();
+        assert_eq!(entry.url, "https://cdn.example.com/v.mp4");
+        assert_eq!(entry.content_policy.as_deref(), Some("policy"));
+    }
+
+    #[test]
+    fn concurrent_writers_keep_every_entry() {
+        let dir = test_dir("concurrent");
+
+        // Separate instances stand in for separate shim processes
+        let writers: Vec<_> = (0..16)
+            .map(|i| {
+                let dir = dir.clone();
+                std::thread::spawn(move || {
+                    ResolutionCache::new(dir, 600)
+                        .insert(&format!("key{}", i), "https://cdn.example.com/v.mp4", None)
+                        .unwrap();
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(ResolutionCache::new(dir, 600).load().len(), 16);
+    }
+}
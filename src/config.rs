@@ -1,7 +1,8 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use crate::constants::CONFIG_FILE_NAME;
+use crate::args::ArgumentParser;
+use crate::constants::{CONFIG_FILE_NAME, LEGACY_FORMAT_SELECTOR};
 use crate::error::{AppError, Result};
 use crate::models::AppConfig;
 
@@ -18,8 +19,13 @@ impl ConfigManager {
     pub fn load_config(&self) -> Result<AppConfig> {
         if self.config_path.exists() {
             let content = fs::read_to_string(&self.config_path)?;
-            let config: AppConfig = serde_json::from_str(&content)
+            let mut config: AppConfig = serde_json::from_str(&content)
                 .map_err(|e| AppError::Config(format!("Failed to parse config.json: {}", e)))?;
+            if Self::migrate(&mut config) {
+                // Failing to save only means the migration is repeated on the next load
+                let _ = self.save_config(&config);
+            }
+            Self::validate(&config)?;
             Ok(config)
         } else {
             // Create default config file
@@ -29,6 +35,36 @@ impl ConfigManager {
         }
     }
 
+    /// Drops the `-f` older versions wrote by default, so the quality settings and player
+    /// profiles take effect. Returns whether anything changed.
+    fn migrate(config: &mut AppConfig) -> bool {
+        if ArgumentParser::format_selector(config) != Some(LEGACY_FORMAT_SELECTOR) {
+            return false;
+        }
+
+        config.custom_args = ArgumentParser::without_format_selector(&config.custom_args);
+        true
+    }
+
+    /// Rejects hand-written `-f` selectors that yt-dlp would fail to parse
+    fn validate(config: &AppConfig) -> Result<()> {
+        let arg_sets = std::iter::once(("custom_args", &config.custom_args)).chain(
+            config
+                .fallback_strategies
+                .iter()
+                .map(|strategy| (strategy.name.as_str(), &strategy.custom_args)),
+        );
+
+        for (name, args) in arg_sets {
+            if let Some(selector) = ArgumentParser::format_selector_in(args) {
+                ArgumentParser::validate_format_selector(selector)
+                    .map_err(|e| AppError::Config(format!("Invalid -f in {}: {}", name, e)))?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn save_config(&self, config: &AppConfig) -> Result<()> {
         let config_json = serde_json::to_string_pretty(config)
             .map_err(|e| AppError::Config(format!("Failed to serialize config: {}", e)))?;
@@ -40,16 +76,21 @@ impl ConfigManager {
     }
 
     pub fn get_ytdlp_path(&self, config: &AppConfig, app_dir: &Path) -> PathBuf {
-        let ytdlp_location = &config.ytdlp_location;
+        Self::resolve_tool_path(&config.ytdlp_location, app_dir)
+    }
 
-        if Path::new(ytdlp_location).is_absolute() {
-            PathBuf::from(ytdlp_location)
+    pub fn get_streamlink_path(&self, config: &AppConfig, app_dir: &Path) -> PathBuf {
+        Self::resolve_tool_path(&config.streamlink.location, app_dir)
+    }
+
+    /// Resolves a configured tool location, relative paths being relative to the app directory
+    fn resolve_tool_path(location: &str, app_dir: &Path) -> PathBuf {
+        if Path::new(location).is_absolute() {
+            PathBuf::from(location)
         } else {
             // Normalize path separators for the current platform
-            let normalized_location = ytdlp_location.replace('/', std::path::MAIN_SEPARATOR_STR);
+            let normalized_location = location.replace('/', std::path::MAIN_SEPARATOR_STR);
             app_dir.join(normalized_location)
         }
     }
-
-
 }
\ No newline at end of file
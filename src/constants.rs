@@ -1,11 +1,69 @@
 pub const CONFIG_FILE_NAME: &str = "config.json";
 pub const VERSION_FILE_NAME: &str = "version.txt";
+pub const CACHE_FILE_NAME: &str = "cache.json";
+pub const CACHE_LOCK_FILE_NAME: &str = "cache.json.lock";
+pub const LIBRARY_INDEX_FILE_NAME: &str = "library.json";
+pub const LIBRARY_LOCK_FILE_NAME: &str = "library.json.lock";
+pub const RELAY_SESSIONS_FILE_NAME: &str = "relay.json";
+pub const RELAY_SESSIONS_LOCK_FILE_NAME: &str = "relay.json.lock";
+pub const HISTORY_FILE_NAME: &str = "history.jsonl";
+pub const HISTORY_LOCK_FILE_NAME: &str = "history.jsonl.lock";
 pub const GITHUB_API_URL: &str = "https://api.github.com/repos/yt-dlp/yt-dlp/releases/latest";
+pub const GITHUB_RELEASE_TAG_URL: &str = "https://api.github.com/repos/yt-dlp/yt-dlp/releases/tags/";
 pub const YT_DLP_EXECUTABLE: &str = "yt-dlp.exe";
+pub const STREAMLINK_GITHUB_API_URL: &str = "https://api.github.com/repos/streamlink/windows-builds/releases/latest";
+pub const VRCHAT_LOG_PREFIX: &str = "output_log_";
+/// `-f` that older versions wrote into `custom_args`; the quality settings now cover it
+pub const LEGACY_FORMAT_SELECTOR: &str = "best[height<=1080][protocol^=m3u8]";
+/// Overrides the configured log levels, e.g. `debug` or `info,executor=trace`
+pub const LOG_LEVEL_ENV_VAR: &str = "VRC_YTDLP_LOG";
+/// Audio codecs both of VRChat's players can decode, best first
+pub const PLAYABLE_AUDIO_CODECS: [&str; 2] = ["mp4a", "mp3"];
+/// Sites yt-dlp has extractors for; their pages are never direct media, so passthrough doesn't probe them
+pub const YT_DLP_SITE_HOSTS: [&str; 14] = [
+    "youtube.com",
+    "youtu.be",
+    "twitch.tv",
+    "vimeo.com",
+    "dailymotion.com",
+    "nicovideo.jp",
+    "bilibili.com",
+    "soundcloud.com",
+    "twitter.com",
+    "x.com",
+    "tiktok.com",
+    "instagram.com",
+    "facebook.com",
+    "streamable.com",
+];
 
 /// Default configuration values
 pub mod defaults {
     pub const LOG_MAX_SIZE_MB: u32 = 10;
     pub const LOG_MAX_ARCHIVED: u32 = 5;
     pub const UPDATE_CHECK_DAYS: i64 = 1;
-}
\ No newline at end of file
+    pub const EXECUTION_TIMEOUT_SECS: u64 = 30;
+    pub const DEADLINE_SECS: u64 = 60;
+    pub const TERMINATION_GRACE_MS: u64 = 2000;
+    pub const PROBE_TIMEOUT_MS: u64 = 3000;
+    pub const PASSTHROUGH_PROBE_TIMEOUT_MS: u64 = 1500;
+    pub const CACHE_TTL_SECS: u64 = 600;
+    pub const STREAMLINK_TIMEOUT_SECS: u64 = 15;
+    pub const PREFETCH_POLL_SECS: u64 = 10;
+    pub const PREFETCH_REFRESH_MARGIN_SECS: u64 = 120;
+    pub const PREFETCH_RETRY_SECS: u64 = 300;
+    pub const PREFETCH_MAX_AGE_SECS: u64 = 3 * 60 * 60;
+    pub const LIBRARY_MAX_SIZE_MB: u64 = 20 * 1024;
+    pub const LIBRARY_DOWNLOAD_TIMEOUT_SECS: u64 = 1800;
+    pub const MEDIA_SERVER_PORT: u16 = 8790;
+    pub const MEDIA_SERVER_IDLE_SECS: u64 = 3600;
+    pub const RELAY_MAX_LIVE_SEGMENTS: usize = 30;
+    pub const RELAY_REFRESH_MARGIN_SECS: u64 = 60;
+    pub const RELAY_UPSTREAM_TIMEOUT_SECS: u64 = 10;
+    pub const RELAY_SESSION_TTL_HOURS: i64 = 24;
+    pub const HISTORY_RETENTION_DAYS: u32 = 30;
+    pub const HISTORY_LIST_LIMIT: usize = 20;
+    pub const AUDIO_FALLBACK_MAX_HEIGHT: u32 = 360;
+    pub const VRCHAT_LOG_SCAN_BYTES: u64 = 256 * 1024;
+    pub const VRCHAT_LOG_CONTEXT_LINES: usize = 20;
+}
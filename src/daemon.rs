@@ -0,0 +1,464 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::error::{AppError, Result};
+use crate::logger::Logger;
+use crate::media_server::MediaServer;
+use crate::models::{AppConfig, DaemonConfig, LibraryConfig, PrefetchConfig};
+use crate::prefetch::PrefetchQueue;
+use crate::relay::HlsRelay;
+use crate::service::ResolveService;
+
+/// Message sent by the shim to the daemon, one JSON object per line
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DaemonRequest {
+    /// Resolve VRChat's arguments exactly as the shim would
+    Resolve {
+        args: Vec<String>,
+        /// The shim's invocation ID, so both processes log the request under it
+        #[serde(default)]
+        invocation_id: Option<String>,
+    },
+    /// Resolve URLs into the cache in the background
+    Prefetch { urls: Vec<String> },
+}
+
+/// Reply from the daemon, one JSON object per line
+#[derive(Serialize, Deserialize)]
+pub struct DaemonResponse {
+    /// Lines the shim should print for VRChat
+    pub output: Vec<String>,
+    /// Error message when the request failed
+    pub error: Option<String>,
+    /// `AppError` variant of the failure, so the shim reports the same failure class
+    #[serde(default)]
+    pub error_kind: Option<String>,
+    /// Exit code of the tool behind the failure, if it exited non-zero
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+}
+
+/// Resident process serving resolution requests over a named pipe (Windows) or Unix socket
+pub struct Daemon {
+    service: Arc<ResolveService>,
+    endpoint: String,
+    prefetch: PrefetchConfig,
+    library: LibraryConfig,
+    app_dir: PathBuf,
+    logger: Logger,
+}
+
+impl Daemon {
+    pub fn new(service: ResolveService, config: &AppConfig, app_dir: &Path, logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
+        Self {
+            service: Arc::new(service),
+            endpoint: endpoint(&config.daemon, app_dir),
+            prefetch: config.prefetch.clone(),
+            library: config.library.clone(),
+            app_dir: app_dir.to_path_buf(),
+            logger,
+        }
+    }
+
+    /// Serves requests until the process is stopped
+    pub async fn run(self) -> Result<()> {
+        if self.prefetch.watch_queue_file {
+            let queue = PrefetchQueue::new(self.service.clone(), self.logger.clone());
+            let config = self.prefetch.clone();
+            let app_dir = self.app_dir.clone();
+            tokio::spawn(async move { queue.watch(config, &app_dir).await });
+        }
+
+        // Library copies and relayed streams are served from here while the daemon runs
+        let mut media_server = MediaServer::new(&self.library, &self.app_dir, self.logger.clone());
+        if self.service.config().relay.enabled {
+            let relay = HlsRelay::new(self.service.clone(), &self.app_dir, self.logger.clone())?;
+            media_server = media_server.with_relay(relay);
+        }
+        let logger = self.logger.clone();
+        tokio::spawn(async move {
+            if let Err(e) = media_server.run().await {
+                logger.log_warning(&format!("Media server not started: {}", e));
+            }
+        });
+
+        self.logger.log_info(&format!("Daemon listening on {}", self.endpoint));
+        transport::serve(&self.endpoint, |stream| {
+            let service = self.service.clone();
+            let logger = self.logger.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, service, &logger).await {
+                    logger.log_warning(&format!("Daemon connection failed: {}", e));
+                }
+            });
+        })
+        .await
+    }
+}
+
+/// Reads one request from the stream and writes the reply
+async fn handle_connection<S>(stream: S, service: Arc<ResolveService>, logger: &Logger) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    let response = match serde_json::from_str::<DaemonRequest>(&line)? {
+        DaemonRequest::Resolve { args, invocation_id } => {
+            let id = invocation_id.unwrap_or_else(Logger::new_invocation_id);
+            match Logger::scope_invocation(&id, service.handle(&args)).await {
+                Ok(output) => DaemonResponse::success(output),
+                Err(e) => {
+                    let (kind, message) = e.to_parts();
+                    DaemonResponse {
+                        output: Vec::new(),
+                        error: Some(message),
+                        error_kind: Some(kind.to_string()),
+                        exit_code: e.exit_code(),
+                    }
+                }
+            }
+        }
+        DaemonRequest::Prefetch { urls } => {
+            let count = urls.len();
+            let queue = PrefetchQueue::new(service, logger.clone());
+            let id = Logger::new_invocation_id();
+            tokio::spawn(async move { Logger::scope_invocation(&id, queue.prefetch_all(&urls)).await });
+            DaemonResponse::success(vec![format!("Queued {} URL(s) for prefetching", count)])
+        }
+    };
+
+    let mut reply = serde_json::to_string(&response)?;
+    reply.push('\n');
+    writer.write_all(reply.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Forwards requests from the shim to a running daemon
+pub struct DaemonClient {
+    endpoint: String,
+}
+
+impl DaemonClient {
+    pub fn new(config: &DaemonConfig, app_dir: &Path) -> Self {
+        Self {
+            endpoint: endpoint(config, app_dir),
+        }
+    }
+
+    /// Sends a request and waits for the reply.
+    ///
+    /// Returns `Ok(None)` when no daemon is listening, so the caller can fall back
+    /// to in-process execution. Once connected the daemon owns the request: a lost
+    /// or late reply is an error rather than a reason to resolve a second time.
+    pub async fn send(&self, request: &DaemonRequest, timeout: Duration) -> Result<Option<DaemonResponse>> {
+        let Ok(stream) = transport::connect(&self.endpoint).await else {
+            return Ok(None);
+        };
+        let (reader, mut writer) = tokio::io::split(stream);
+
+        let mut message = serde_json::to_string(request)?;
+        message.push('\n');
+        writer.write_all(message.as_bytes()).await?;
+        writer.flush().await?;
+
+        let mut line = String::new();
+        tokio::time::timeout(timeout, BufReader::new(reader).read_line(&mut line))
+            .await
+            .map_err(|_| AppError::Execution("daemon did not reply in time".to_string()))??;
+
+        Ok(Some(serde_json::from_str(&line)?))
+    }
+}
+
+impl DaemonResponse {
+    fn success(output: Vec<String>) -> Self {
+        Self {
+            output,
+            error: None,
+            error_kind: None,
+            exit_code: None,
+        }
+    }
+
+    /// Converts the reply back into the shim's own result
+    pub fn into_result(self) -> Result<Vec<String>> {
+        match self.error {
+            Some(error) => Err(AppError::from_parts(
+                self.error_kind.as_deref().unwrap_or_default(),
+                error,
+                self.exit_code,
+            )),
+            None => Ok(self.output),
+        }
+    }
+}
+
+#[cfg(windows)]
+fn endpoint(config: &DaemonConfig, _app_dir: &Path) -> String {
+    format!(r"\\.\pipe\{}", config.pipe_name)
+}
+
+#[cfg(unix)]
+fn endpoint(config: &DaemonConfig, app_dir: &Path) -> String {
+    app_dir
+        .join(format!("{}.sock", config.pipe_name))
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(windows)]
+mod transport {
+    use std::time::Duration;
+
+    use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient, NamedPipeServer, ServerOptions};
+
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Authorization::{
+        ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+    };
+    use windows_sys::Win32::Security::SECURITY_ATTRIBUTES;
+
+    use crate::error::Result;
+
+    const ERROR_PIPE_BUSY: i32 = 231;
+
+    /// Only the user who started the daemon and SYSTEM may connect; network logons are denied
+    const PIPE_SDDL: &str = "D:P(D;;GA;;;NU)(A;;GA;;;SY)(A;;GA;;;OW)";
+
+    pub async fn serve<F>(endpoint: &str, mut on_connection: F) -> Result<()>
+    where
+        F: FnMut(NamedPipeServer),
+    {
+        let mut server = create(endpoint, true)?;
+
+        loop {
+            server.connect().await?;
+            let connected = server;
+            server = create(endpoint, false)?;
+            on_connection(connected);
+        }
+    }
+
+    /// Creates a pipe instance with the restrictive DACL instead of the default one,
+    /// which lets other local accounts connect
+    fn create(endpoint: &str, first_instance: bool) -> std::io::Result<NamedPipeServer> {
+        let sddl: Vec<u16> = PIPE_SDDL.encode_utf16().chain(Some(0)).collect();
+        let mut descriptor = std::ptr::null_mut();
+        // SAFETY: the SDDL string is NUL-terminated and the descriptor is freed below
+        let converted = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                std::ptr::null_mut(),
+            )
+        };
+        if converted == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let mut attributes = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: descriptor,
+            bInheritHandle: 0,
+        };
+        // SAFETY: the attributes point at a valid descriptor for the duration of the call
+        let server = unsafe {
+            ServerOptions::new()
+                .first_pipe_instance(first_instance)
+                .reject_remote_clients(true)
+                .create_with_security_attributes_raw(endpoint, &mut attributes as *mut _ as *mut _)
+        };
+        // SAFETY: the descriptor was allocated by the conversion above and is no longer used
+        unsafe { LocalFree(descriptor as _) };
+        server
+    }
+
+    pub async fn connect(endpoint: &str) -> std::io::Result<NamedPipeClient> {
+        // A missing pipe fails immediately; a busy one is retried briefly
+        for _ in 0..10 {
+            match ClientOptions::new().open(endpoint) {
+                Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                result => return result,
+            }
+        }
+
+        ClientOptions::new().open(endpoint)
+    }
+}
+
+#[cfg(unix)]
+mod transport {
+    use std::path::Path;
+
+    use tokio::net::{UnixListener, UnixStream};
+
+    use crate::error::{AppError, Result};
+
+    pub async fn serve<F>(endpoint: &str, mut on_connection: F) -> Result<()>
+    where
+        F: FnMut(UnixStream),
+    {
+        if Path::new(endpoint).exists() {
+            if UnixStream::connect(endpoint).await.is_ok() {
+                return Err(AppError::Config(format!("A daemon is already listening on {}", endpoint)));
+            }
+            // Stale socket left by a daemon that didn't shut down cleanly
+            std::fs::remove_file(endpoint)?;
+        }
+
+        let listener = UnixListener::bind(endpoint)?;
+        loop {
+            let (stream, _) = listener.accept().await?;
+            on_connection(stream);
+        }
+    }
+
+    pub async fn connect(endpoint: &str) -> std::io::Result<UnixStream> {
+        UnixStream::connect(endpoint).await
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::net::UnixListener;
+
+    use super::*;
+    use crate::cache::ResolutionCache;
+    use crate::logger::LogConfig;
+    use crate::models::{ScriptedConfig, ScriptedResponse};
+    use crate::resolver::{ResolverContext, ScriptedResolver};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc-ytdlp-daemon-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config() -> AppConfig {
+        let mut config = AppConfig {
+            resolvers: vec![ScriptedResolver::NAME.to_string()],
+            scripted: ScriptedConfig {
+                responses: vec![ScriptedResponse {
+                    pattern: "example.com".to_string(),
+                    url: Some("https://cdn.example.com/v.mp4".to_string()),
+                    ..Default::default()
+                }],
+            },
+            ..Default::default()
+        };
+        config.policy.blocked_hosts = vec!["blocked.example.net".to_string()];
+        config
+    }
+
+    fn service(dir: &Path) -> Arc<ResolveService> {
+        let context = ResolverContext {
+            app_dir: dir.to_path_buf(),
+            ytdlp_path: dir.join("yt-dlp.exe"),
+            streamlink_path: dir.join("streamlink.exe"),
+            concurrent: true,
+            cache: Arc::new(ResolutionCache::new(dir.to_path_buf(), 600)),
+            logger: Logger::with_config(dir.join("test.log"), LogConfig::default()),
+        };
+        Arc::new(ResolveService::new(config(), context).unwrap())
+    }
+
+    fn resolve(url: &str) -> DaemonRequest {
+        DaemonRequest::Resolve {
+            args: vec!["--get-url".to_string(), url.to_string()],
+            invocation_id: None,
+        }
+    }
+
+    /// Starts a daemon endpoint in `dir` and returns a client for it
+    async fn serve(dir: &Path) -> DaemonClient {
+        let service = service(dir);
+        let logger = Logger::with_config(dir.join("test.log"), LogConfig::default());
+        let endpoint = endpoint(&config().daemon, dir);
+        tokio::spawn(async move {
+            transport::serve(&endpoint, |stream| {
+                let service = service.clone();
+                let logger = logger.clone();
+                tokio::spawn(async move { handle_connection(stream, service, &logger).await });
+            })
+            .await
+        });
+
+        let client = DaemonClient::new(&config().daemon, dir);
+        for _ in 0..50 {
+            if transport::connect(&client.endpoint).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        client
+    }
+
+    #[tokio::test]
+    async fn serves_resolutions() {
+        let client = serve(&test_dir("serve")).await;
+
+        let response = client
+            .send(&resolve("https://example.com/v"), Duration::from_secs(5))
+            .await
+            .unwrap()
+            .expect("daemon is listening");
+        assert_eq!(response.into_result().unwrap(), vec!["https://cdn.example.com/v.mp4"]);
+    }
+
+    #[tokio::test]
+    async fn failures_keep_their_variant() {
+        let client = serve(&test_dir("failure")).await;
+
+        let response = client
+            .send(&resolve("https://blocked.example.net/v"), Duration::from_secs(5))
+            .await
+            .unwrap()
+            .expect("daemon is listening");
+        assert!(matches!(response.into_result(), Err(AppError::Blocked(_))));
+    }
+
+    #[tokio::test]
+    async fn no_daemon_means_no_response() {
+        let client = DaemonClient::new(&config().daemon, &test_dir("absent"));
+
+        assert!(client.send(&resolve("https://example.com/v"), Duration::from_secs(1)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn silent_daemon_times_out_without_fallback() {
+        let dir = test_dir("silent");
+        let listener = UnixListener::bind(endpoint(&config().daemon, &dir)).unwrap();
+        // Accepts the connection and holds it open, as the task output, without replying
+        let held = tokio::spawn(async move { listener.accept().await });
+
+        let client = DaemonClient::new(&config().daemon, &dir);
+        let result = client.send(&resolve("https://example.com/v"), Duration::from_millis(200)).await;
+        assert!(matches!(result, Err(AppError::Execution(message)) if message.contains("did not reply")));
+        held.abort();
+    }
+
+    #[tokio::test]
+    async fn second_daemon_refuses_to_start() {
+        let dir = test_dir("second");
+        let _client = serve(&dir).await;
+
+        let result = transport::serve(&endpoint(&config().daemon, &dir), |_| {}).await;
+        assert!(matches!(result, Err(AppError::Config(_))));
+    }
+}
@@ -0,0 +1,38 @@
+use std::time::{Duration, Instant};
+
+/// Total time budget for a single request, shared by every phase that may block
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    started: Instant,
+    budget: Duration,
+}
+
+impl Deadline {
+    /// Starts a new deadline with the given budget
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            started: Instant::now(),
+            budget,
+        }
+    }
+
+    /// Time spent since the deadline was started
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Time left before the budget is exhausted
+    pub fn remaining(&self) -> Duration {
+        self.budget.saturating_sub(self.elapsed())
+    }
+
+    /// Checks if the budget has been used up
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// Limits a phase timeout to the remaining budget
+    pub fn clamp(&self, timeout: Duration) -> Duration {
+        timeout.min(self.remaining())
+    }
+}
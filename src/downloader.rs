@@ -2,11 +2,14 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use chrono::{Duration, Utc};
+use serde_json::json;
 
-use crate::constants::{GITHUB_API_URL, VERSION_FILE_NAME, YT_DLP_EXECUTABLE};
+use crate::constants::{
+    GITHUB_API_URL, GITHUB_RELEASE_TAG_URL, STREAMLINK_GITHUB_API_URL, VERSION_FILE_NAME, YT_DLP_EXECUTABLE,
+};
 use crate::error::{AppError, Result};
-use crate::logger::Logger;
-use crate::models::{GitHubRelease, VersionInfo};
+use crate::logger::{Level, Logger};
+use crate::models::{GitHubAsset, GitHubRelease, VersionInfo};
 
 /// Handles downloading and updating yt-dlp
 pub struct Downloader {
@@ -18,6 +21,7 @@ pub struct Downloader {
 impl Downloader {
     /// Creates a new downloader instance
     pub fn new(exe_path: PathBuf, logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
         let exe_dir = exe_path.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
 
         // Ensure the directory exists
@@ -45,24 +49,52 @@ impl Downloader {
         let release = self.get_latest_release().await?;
         let asset = self.find_windows_executable(&release)?;
 
-        self.logger.log_info(&format!("Downloading from: {}", asset.browser_download_url));
+        self.logger.event(
+            Level::Info,
+            &format!("Downloading from: {}", asset.browser_download_url),
+            &[("url", json!(asset.browser_download_url)), ("version", json!(release.tag_name))],
+        );
 
-        let bytes = self.download_file(&asset.browser_download_url).await?;
+        let bytes = Self::download_file(&asset.browser_download_url).await?;
         self.save_executable(&bytes)?;
         self.save_version_info(&release.tag_name)?;
 
-        self.logger.log_info(&format!("Successfully downloaded yt-dlp version: {}", release.tag_name));
+        self.logger.event(
+            Level::Info,
+            &format!("Successfully downloaded yt-dlp version: {}", release.tag_name),
+            &[("version", json!(release.tag_name))],
+        );
 
         Ok(())
     }
 
+    /// Makes the executable a specific release, downloading it unless already installed.
+    ///
+    /// The version is marked as just checked, so the pinned copy isn't updated when used.
+    pub async fn install_version(&self, version: &str) -> Result<()> {
+        if !self.executable_exists() || self.current_version().as_deref() != Some(version) {
+            self.logger.event(
+                Level::Info,
+                &format!("Downloading yt-dlp version {}", version),
+                &[("version", json!(version))],
+            );
+
+            let release = Self::get_release(&format!("{}{}", GITHUB_RELEASE_TAG_URL, version)).await?;
+            let asset = self.find_windows_executable(&release)?;
+            let bytes = Self::download_file(&asset.browser_download_url).await?;
+            self.save_executable(&bytes)?;
+        }
+
+        self.save_version_info(version)
+    }
+
     /// Checks for updates and downloads if necessary
     pub async fn check_and_update(&self) -> Result<()> {
         let version_path = self.exe_dir.join(VERSION_FILE_NAME);
 
-        let mut version_info = self.load_version_info(&version_path)?;
+        let mut version_info = Self::load_version_info(&version_path)?;
 
-        if !self.should_check_for_updates(&version_info) {
+        if !Self::should_check_for_updates(&version_info) {
             return Ok(());
         }
 
@@ -81,7 +113,7 @@ impl Downloader {
             self.download_latest().await?;
         } else {
             self.logger.log_info(&format!("yt-dlp is up to date: {}", version_info.version));
-            self.save_version_info_to_file(&version_info, &version_path)?;
+            Self::save_version_info_to_file(&version_info, &version_path)?;
         }
 
         Ok(())
@@ -95,26 +127,32 @@ impl Downloader {
 
     /// Fetches the latest release information from GitHub API
     async fn get_latest_release(&self) -> Result<GitHubRelease> {
+        Self::get_release(GITHUB_API_URL).await
+    }
+
+    /// Fetches release information from a GitHub API release URL
+    async fn get_release(url: &str) -> Result<GitHubRelease> {
         let client = reqwest::Client::new();
         let response = client
-            .get(GITHUB_API_URL)
+            .get(url)
             .header("User-Agent", "VRC-YtDlp")
             .send()
-            .await?;
+            .await?
+            .error_for_status()?;
 
         let release: GitHubRelease = response.json().await?;
         Ok(release)
     }
 
     /// Finds the Windows executable in the release assets
-    fn find_windows_executable<'a>(&self, release: &'a GitHubRelease) -> Result<&'a crate::models::GitHubAsset> {
+    fn find_windows_executable<'a>(&self, release: &'a GitHubRelease) -> Result<&'a GitHubAsset> {
         release.assets.iter()
             .find(|asset| asset.name == YT_DLP_EXECUTABLE)
             .ok_or_else(|| AppError::Download(format!("Could not find {} in release assets", YT_DLP_EXECUTABLE)))
     }
 
     /// Downloads a file from the given URL
-    async fn download_file(&self, url: &str) -> Result<bytes::Bytes> {
+    async fn download_file(url: &str) -> Result<bytes::Bytes> {
         let client = reqwest::Client::new();
         let response = client.get(url).send().await?;
         let bytes = response.bytes().await?;
@@ -127,6 +165,14 @@ impl Downloader {
         Ok(())
     }
 
+    /// Version recorded when the executable was last downloaded, if known
+    pub fn current_version(&self) -> Option<String> {
+        Self::load_version_info(&self.exe_dir.join(VERSION_FILE_NAME))
+            .ok()
+            .map(|info| info.version)
+            .filter(|version| !version.is_empty())
+    }
+
     /// Saves version information to disk
     fn save_version_info(&self, version: &str) -> Result<()> {
         let version_info = VersionInfo {
@@ -135,18 +181,18 @@ impl Downloader {
         };
 
         let version_path = self.exe_dir.join(VERSION_FILE_NAME);
-        self.save_version_info_to_file(&version_info, &version_path)
+        Self::save_version_info_to_file(&version_info, &version_path)
     }
 
     /// Saves version info to a specific file
-    fn save_version_info_to_file(&self, version_info: &VersionInfo, path: &Path) -> Result<()> {
+    fn save_version_info_to_file(version_info: &VersionInfo, path: &Path) -> Result<()> {
         let version_json = serde_json::to_string(version_info)?;
         fs::write(path, version_json)?;
         Ok(())
     }
 
     /// Loads version information from disk
-    fn load_version_info(&self, version_path: &Path) -> Result<VersionInfo> {
+    fn load_version_info(version_path: &Path) -> Result<VersionInfo> {
         if version_path.exists() {
             let content = fs::read_to_string(version_path)?;
             let version_info = serde_json::from_str::<VersionInfo>(&content)
@@ -158,7 +204,7 @@ impl Downloader {
     }
 
     /// Determines if we should check for updates (once per day)
-    fn should_check_for_updates(&self, version_info: &VersionInfo) -> bool {
+    fn should_check_for_updates(version_info: &VersionInfo) -> bool {
         if let Some(last_check) = version_info.last_check {
             Utc::now() - last_check > Duration::days(crate::constants::defaults::UPDATE_CHECK_DAYS)
         } else {
@@ -166,3 +212,273 @@ impl Downloader {
         }
     }
 }
+
+/// Handles downloading and updating the portable Windows build of streamlink.
+///
+/// The build is a zip holding one versioned folder with `bin/streamlink.exe` and its
+/// own Python; the folder's contents are unpacked into the install directory, two
+/// levels above the executable. An install without our version file was put there
+/// by hand and is never replaced.
+pub struct StreamlinkDownloader {
+    exe_path: PathBuf,
+    install_dir: PathBuf,
+    logger: Logger,
+}
+
+impl StreamlinkDownloader {
+    pub fn new(exe_path: PathBuf, logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
+        let install_dir = exe_path
+            .parent()
+            .and_then(Path::parent)
+            .unwrap_or_else(|| Path::new("."))
+            .to_path_buf();
+
+        Self { exe_path, install_dir, logger }
+    }
+
+    /// Gets the path to the streamlink executable
+    pub fn get_executable_path(&self) -> PathBuf {
+        self.exe_path.clone()
+    }
+
+    /// Checks if the streamlink executable exists
+    pub fn executable_exists(&self) -> bool {
+        self.exe_path.exists()
+    }
+
+    /// Checks whether the install was downloaded by us and may be updated
+    pub fn is_managed(&self) -> bool {
+        self.install_dir.join(VERSION_FILE_NAME).exists()
+    }
+
+    /// Downloads and unpacks the latest portable build
+    pub async fn download_latest(&self) -> Result<()> {
+        self.logger.log_info("Starting streamlink download...");
+
+        let release = Downloader::get_release(STREAMLINK_GITHUB_API_URL).await?;
+        let asset = Self::find_portable_build(&release)?;
+
+        self.logger.event(
+            Level::Info,
+            &format!("Downloading from: {}", asset.browser_download_url),
+            &[("url", json!(asset.browser_download_url)), ("version", json!(release.tag_name))],
+        );
+
+        let bytes = Downloader::download_file(&asset.browser_download_url).await?;
+        let (exe_path, install_dir) = (self.exe_path.clone(), self.install_dir.clone());
+        // Unpacking writes a few thousand files, so it stays off the runtime threads
+        tokio::task::spawn_blocking(move || Self::install(&bytes, &exe_path, &install_dir))
+            .await
+            .map_err(|e| AppError::Download(format!("streamlink install task failed: {}", e)))??;
+
+        let version_info = VersionInfo {
+            version: release.tag_name.clone(),
+            last_check: Some(Utc::now()),
+        };
+        Downloader::save_version_info_to_file(&version_info, &self.install_dir.join(VERSION_FILE_NAME))?;
+
+        self.logger.event(
+            Level::Info,
+            &format!("Successfully installed streamlink version: {}", release.tag_name),
+            &[("version", json!(release.tag_name))],
+        );
+
+        Ok(())
+    }
+
+    /// Checks for updates once a day and installs the latest build if it changed
+    pub async fn check_and_update(&self) -> Result<()> {
+        let version_path = self.install_dir.join(VERSION_FILE_NAME);
+        let mut version_info = Downloader::load_version_info(&version_path)?;
+
+        if !Downloader::should_check_for_updates(&version_info) {
+            return Ok(());
+        }
+
+        self.logger.log_info("Checking for streamlink updates...");
+
+        let latest_version = Downloader::get_release(STREAMLINK_GITHUB_API_URL).await?.tag_name;
+        if version_info.version != latest_version {
+            self.logger.log_info(&format!(
+                "New streamlink version available: {} (current: {})",
+                latest_version,
+                version_info.version
+            ));
+
+            self.download_latest().await?;
+        } else {
+            self.logger.log_info(&format!("streamlink is up to date: {}", version_info.version));
+            version_info.last_check = Some(Utc::now());
+            Downloader::save_version_info_to_file(&version_info, &version_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finds the 64-bit portable zip in the release assets
+    fn find_portable_build(release: &GitHubRelease) -> Result<&GitHubAsset> {
+        release
+            .assets
+            .iter()
+            .find(|asset| asset.name.starts_with("streamlink-") && asset.name.ends_with("-x86_64.zip"))
+            .ok_or_else(|| AppError::Download("Could not find a portable x86_64 streamlink build in release assets".to_string()))
+    }
+
+    /// Unpacks the build next to the install directory, then swaps it in
+    fn install(bytes: &[u8], exe_path: &Path, install_dir: &Path) -> Result<()> {
+        if install_dir.exists() && !install_dir.join(VERSION_FILE_NAME).exists() {
+            return Err(AppError::Download(format!(
+                "Not replacing {}, it holds files that weren't installed by us",
+                install_dir.display()
+            )));
+        }
+
+        let staging = install_dir.with_extension("new");
+        let _ = fs::remove_dir_all(&staging);
+        Self::unpack(bytes, &staging)?;
+
+        let relative_exe = exe_path.strip_prefix(install_dir).unwrap_or(exe_path);
+        if !staging.join(relative_exe).exists() {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(AppError::Download(format!(
+                "streamlink build has no {}",
+                relative_exe.display()
+            )));
+        }
+
+        match fs::remove_dir_all(install_dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        fs::rename(&staging, install_dir)?;
+        Ok(())
+    }
+
+    /// Extracts a zip into `target`, dropping the archive's top-level folder
+    fn unpack(bytes: &[u8], target: &Path) -> Result<()> {
+        let invalid = |e: zip::result::ZipError| AppError::Download(format!("Invalid streamlink archive: {}", e));
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).map_err(invalid)?;
+
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index).map_err(invalid)?;
+            // Entries escaping the archive root are skipped rather than trusted
+            let Some(path) = entry.enclosed_name() else { continue };
+            let relative: PathBuf = path.components().skip(1).collect();
+            if relative.as_os_str().is_empty() {
+                continue;
+            }
+
+            let out_path = target.join(relative);
+            if entry.is_dir() {
+                fs::create_dir_all(&out_path)?;
+                continue;
+            }
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            std::io::copy(&mut entry, &mut fs::File::create(&out_path)?)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc-ytdlp-downloader-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Builds a zip laid out like the portable streamlink build
+    fn portable_build(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn release(assets: &[&str]) -> GitHubRelease {
+        GitHubRelease {
+            tag_name: "7.0.0-1".to_string(),
+            assets: assets
+                .iter()
+                .map(|name| GitHubAsset {
+                    name: name.to_string(),
+                    browser_download_url: format!("https://example.com/{}", name),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn picks_the_64_bit_portable_build() {
+        let builds = release(&[
+            "streamlink-7.0.0-1-py313-x86.zip",
+            "streamlink-7.0.0-1-py313-x86_64.exe",
+            "streamlink-7.0.0-1-py313-x86_64.zip",
+        ]);
+
+        assert_eq!(
+            StreamlinkDownloader::find_portable_build(&builds).unwrap().name,
+            "streamlink-7.0.0-1-py313-x86_64.zip"
+        );
+        assert!(StreamlinkDownloader::find_portable_build(&release(&["notes.txt"])).is_err());
+    }
+
+    #[test]
+    fn install_unpacks_without_the_top_level_folder() {
+        let install_dir = test_dir("install").join("streamlink");
+        let exe_path = install_dir.join("bin").join("streamlink.exe");
+        let build = portable_build(&[
+            ("streamlink-7.0.0-1/bin/streamlink.exe", "new"),
+            ("streamlink-7.0.0-1/pkgs/streamlink/__init__.py", ""),
+            ("../escape.txt", "outside"),
+        ]);
+
+        StreamlinkDownloader::install(&build, &exe_path, &install_dir).unwrap();
+        assert_eq!(fs::read_to_string(&exe_path).unwrap(), "new");
+        assert!(install_dir.join("pkgs/streamlink/__init__.py").exists());
+        assert!(!install_dir.parent().unwrap().join("escape.txt").exists());
+    }
+
+    #[test]
+    fn install_only_replaces_managed_directories() {
+        let install_dir = test_dir("manual").join("streamlink");
+        let exe_path = install_dir.join("bin").join("streamlink.exe");
+        fs::create_dir_all(exe_path.parent().unwrap()).unwrap();
+        fs::write(&exe_path, "manual").unwrap();
+        let build = portable_build(&[("streamlink-7.0.0-1/bin/streamlink.exe", "new")]);
+
+        assert!(StreamlinkDownloader::install(&build, &exe_path, &install_dir).is_err());
+        assert_eq!(fs::read_to_string(&exe_path).unwrap(), "manual");
+
+        fs::write(install_dir.join(VERSION_FILE_NAME), "{}").unwrap();
+        StreamlinkDownloader::install(&build, &exe_path, &install_dir).unwrap();
+        assert_eq!(fs::read_to_string(&exe_path).unwrap(), "new");
+    }
+
+    #[test]
+    fn install_rejects_builds_without_the_executable() {
+        let install_dir = test_dir("empty").join("streamlink");
+        let exe_path = install_dir.join("bin").join("streamlink.exe");
+        let build = portable_build(&[("streamlink-7.0.0-1/README.md", "")]);
+
+        assert!(matches!(
+            StreamlinkDownloader::install(&build, &exe_path, &install_dir),
+            Err(AppError::Download(_))
+        ));
+        assert!(!install_dir.exists());
+    }
+}
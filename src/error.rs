@@ -7,6 +7,11 @@ pub enum AppError {
     Serde(serde_json::Error),
     Download(String),
     Execution(String),
+    /// A tool ran but failed; `code` is `None` when it was killed by a signal
+    ProcessFailed { code: Option<i32>, message: String },
+    Validation(String),
+    HealthCheck(String),
+    Blocked(String),
     Config(String),
     FileNotFound(String),
     PermissionDenied(String),
@@ -22,6 +27,10 @@ impl fmt::Display for AppError {
             AppError::Serde(err) => write!(f, "JSON serialization error: {}", err),
             AppError::Download(msg) => write!(f, "Download error: {}", msg),
             AppError::Execution(msg) => write!(f, "Execution error: {}", msg),
+            AppError::ProcessFailed { message, .. } => write!(f, "Execution error: {}", message),
+            AppError::Validation(msg) => write!(f, "Output validation error: {}", msg),
+            AppError::HealthCheck(msg) => write!(f, "Health check failed: {}", msg),
+            AppError::Blocked(msg) => write!(f, "Blocked by policy: {}", msg),
             AppError::Config(msg) => write!(f, "Configuration error: {}", msg),
             AppError::FileNotFound(msg) => write!(f, "File not found: {}", msg),
             AppError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
@@ -33,6 +42,109 @@ impl fmt::Display for AppError {
 
 impl std::error::Error for AppError {}
 
+/// Broad reason a request failed, used to pick a placeholder for VRChat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureClass {
+    Blocked,
+    Unavailable,
+    BotCheck,
+    Timeout,
+    Other,
+}
+
+impl FailureClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureClass::Blocked => "blocked",
+            FailureClass::Unavailable => "unavailable",
+            FailureClass::BotCheck => "bot_check",
+            FailureClass::Timeout => "timeout",
+            FailureClass::Other => "other",
+        }
+    }
+}
+
+impl AppError {
+    /// Exit code of a tool that exited with a non-zero status
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            AppError::ProcessFailed { code, .. } => *code,
+            _ => None,
+        }
+    }
+
+    /// Variant name and message, so the daemon can hand the error to the shim intact
+    /// along with [`AppError::exit_code`]
+    pub fn to_parts(&self) -> (&'static str, String) {
+        match self {
+            AppError::Io(err) => ("io", err.to_string()),
+            AppError::Reqwest(err) => ("network", err.to_string()),
+            AppError::Serde(err) => ("serde", err.to_string()),
+            AppError::Download(msg) => ("download", msg.clone()),
+            AppError::Execution(msg) => ("execution", msg.clone()),
+            AppError::ProcessFailed { message, .. } => ("process_failed", message.clone()),
+            AppError::Validation(msg) => ("validation", msg.clone()),
+            AppError::HealthCheck(msg) => ("health_check", msg.clone()),
+            AppError::Blocked(msg) => ("blocked", msg.clone()),
+            AppError::Config(msg) => ("config", msg.clone()),
+            AppError::FileNotFound(msg) => ("file_not_found", msg.clone()),
+            AppError::PermissionDenied(msg) => ("permission_denied", msg.clone()),
+            AppError::NetworkError(msg) => ("network", msg.clone()),
+        }
+    }
+
+    /// Rebuilds an error from [`AppError::to_parts`]; unknown kinds become execution errors
+    pub fn from_parts(kind: &str, message: String, code: Option<i32>) -> Self {
+        match kind {
+            "io" => AppError::Io(std::io::Error::other(message)),
+            "process_failed" => AppError::ProcessFailed { code, message },
+            "serde" => AppError::Serde(<serde_json::Error as serde::de::Error>::custom(message)),
+            "download" => AppError::Download(message),
+            "validation" => AppError::Validation(message),
+            "health_check" => AppError::HealthCheck(message),
+            "blocked" => AppError::Blocked(message),
+            "config" => AppError::Config(message),
+            "file_not_found" => AppError::FileNotFound(message),
+            "permission_denied" => AppError::PermissionDenied(message),
+            "network" => AppError::NetworkError(message),
+            _ => AppError::Execution(message),
+        }
+    }
+
+    /// Classifies the error, reading yt-dlp's message for execution failures
+    pub fn failure_class(&self) -> FailureClass {
+        let message = match self {
+            AppError::Blocked(_) => return FailureClass::Blocked,
+            AppError::Execution(msg) | AppError::ProcessFailed { message: msg, .. } | AppError::NetworkError(msg) => {
+                msg.to_ascii_lowercase()
+            }
+            _ => return FailureClass::Other,
+        };
+
+        const BOT_CHECK: [&str; 4] = ["not a bot", "sign in to confirm", "captcha", "http error 429"];
+        const UNAVAILABLE: [&str; 7] = [
+            "video unavailable",
+            "private video",
+            "has been removed",
+            "is not available",
+            "does not exist",
+            "http error 404",
+            "this live event will begin",
+        ];
+        const TIMEOUT: [&str; 4] = ["did not respond within", "deadline exhausted", "timed out", "did not reply in time"];
+
+        if BOT_CHECK.iter().any(|pattern| message.contains(pattern)) {
+            FailureClass::BotCheck
+        } else if UNAVAILABLE.iter().any(|pattern| message.contains(pattern)) {
+            FailureClass::Unavailable
+        } else if TIMEOUT.iter().any(|pattern| message.contains(pattern)) {
+            FailureClass::Timeout
+        } else {
+            FailureClass::Other
+        }
+    }
+}
+
 impl From<std::io::Error> for AppError {
     fn from(err: std::io::Error) -> Self {
         match err.kind() {
@@ -60,3 +172,78 @@ impl From<serde_json::Error> for AppError {
 }
 
 pub type Result<T> = std::result::Result<T, AppError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execution(message: &str) -> AppError {
+        AppError::Execution(message.to_string())
+    }
+
+    #[test]
+    fn classifies_yt_dlp_messages() {
+        let cases = [
+            ("ERROR: [youtube] x: Sign in to confirm you're not a bot", FailureClass::BotCheck),
+            ("ERROR: unable to download webpage: HTTP Error 429: Too Many Requests", FailureClass::BotCheck),
+            ("ERROR: [youtube] x: Video unavailable", FailureClass::Unavailable),
+            ("ERROR: [youtube] x: Private video", FailureClass::Unavailable),
+            ("ERROR: [youtube] x: This live event will begin in 3 hours", FailureClass::Unavailable),
+            ("yt-dlp.exe did not respond within 30 seconds and was terminated", FailureClass::Timeout),
+            ("Request deadline exhausted before execution", FailureClass::Timeout),
+            ("daemon did not reply in time", FailureClass::Timeout),
+            ("ERROR: Unsupported URL: https://example.com", FailureClass::Other),
+        ];
+
+        for (message, class) in cases {
+            assert_eq!(execution(message).failure_class(), class, "{}", message);
+        }
+    }
+
+    #[test]
+    fn classifies_by_variant() {
+        assert_eq!(AppError::Blocked("video unavailable".to_string()).failure_class(), FailureClass::Blocked);
+        assert_eq!(
+            AppError::NetworkError("operation timed out".to_string()).failure_class(),
+            FailureClass::Timeout
+        );
+        // Only tool and network failures are read for patterns
+        assert_eq!(AppError::Validation("timed out".to_string()).failure_class(), FailureClass::Other);
+    }
+
+    #[test]
+    fn reads_exit_code() {
+        let failed = |code| AppError::ProcessFailed {
+            code,
+            message: "yt-dlp.exe exited (ERROR: x)".to_string(),
+        };
+        assert_eq!(failed(Some(2)).exit_code(), Some(2));
+        assert_eq!(failed(None).exit_code(), None);
+        // Only the status the tool exited with counts, not text in a message
+        assert_eq!(execution("yt-dlp.exe exited with non-zero status code: 2").exit_code(), None);
+        assert_eq!(AppError::Config("non-zero status code: 2".to_string()).exit_code(), None);
+    }
+
+    #[test]
+    fn parts_round_trip_keeps_the_variant() {
+        let errors = [
+            AppError::Blocked("example.com is on the blocked host list".to_string()),
+            execution("ERROR: Video unavailable"),
+            AppError::NetworkError("connection refused".to_string()),
+            AppError::Validation("yt-dlp did not print a URL".to_string()),
+            AppError::ProcessFailed {
+                code: Some(1),
+                message: "yt-dlp.exe exited with non-zero status code: 1 (ERROR: Private video)".to_string(),
+            },
+        ];
+
+        for error in errors {
+            let (kind, message) = error.to_parts();
+            let rebuilt = AppError::from_parts(kind, message, error.exit_code());
+            assert_eq!(rebuilt.to_string(), error.to_string());
+            assert_eq!(rebuilt.exit_code(), error.exit_code());
+            assert_eq!(rebuilt.failure_class(), error.failure_class());
+        }
+        assert!(matches!(AppError::from_parts("unknown", "x".to_string(), None), AppError::Execution(_)));
+    }
+}
@@ -1,36 +1,73 @@
 use std::path::{Path, PathBuf};
-use std::process::{Child, Command, Stdio};
+use std::process::{ExitStatus, Stdio};
 use std::process as std_process;
 use std::time::{Duration, Instant};
-use std::thread::sleep;
+
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
 
 use crate::error::{AppError, Result};
-use crate::logger::Logger;
+use crate::logger::{Level, Logger};
 use crate::constants::YT_DLP_EXECUTABLE;
-use sysinfo::System;
+use sysinfo::{Pid, System};
+
+/// Error lines from the tool's stderr kept in failure messages
+const STDERR_TAIL_LINES: usize = 3;
+/// How long output is still read after the tool exited; a grandchild that
+/// inherited the pipes can otherwise keep them open indefinitely
+const PIPE_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
 
 pub struct Executor {
     exe_dir: PathBuf,
+    termination_grace: Duration,
+    allow_concurrent: bool,
     pub logger: Logger,
 }
 
 impl Executor {
-    pub fn new(exe_dir: PathBuf, logger: Logger) -> Self {
-        Self { exe_dir, logger }
+    pub fn new(exe_dir: PathBuf, termination_grace: Duration, logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
+        Self { exe_dir, termination_grace, allow_concurrent: false, logger }
+    }
+
+    /// Allows running while another yt-dlp process exists, as the daemon does for concurrent requests
+    pub fn with_concurrency(mut self, allow_concurrent: bool) -> Self {
+        self.allow_concurrent = allow_concurrent;
+        self
     }
 
-    pub fn execute(&self, executable_path: &Path, args: &[String]) -> Result<()> {
-        if Self::is_yt_dlp_running(executable_path) {
+    /// Runs yt-dlp and returns the lines it printed to stdout; stderr is streamed to the log.
+    ///
+    /// Dropping the returned future terminates the process tree, so callers may
+    /// race or cancel executions freely.
+    pub async fn execute(&self, executable_path: &Path, args: &[String], timeout: Duration) -> Result<Vec<String>> {
+        let target = executable_path.to_path_buf();
+        let already_running = !self.allow_concurrent
+            && tokio::task::spawn_blocking(move || Self::is_yt_dlp_running(&target))
+                .await
+                .unwrap_or(false);
+        if already_running {
             self.logger
                 .log_warning("Detected an existing yt-dlp process. Skipping new invocation.");
-            return Ok(());
+            return Ok(Vec::new());
         }
 
         if args.is_empty() {
             self.logger.log_warning("No arguments provided for yt-dlp");
-            return Ok(());
+            return Ok(Vec::new());
         }
 
+        self.run(executable_path, args, timeout).await
+    }
+
+    /// Runs any tool with the same isolation, timeout and output handling as yt-dlp
+    pub async fn run(&self, executable_path: &Path, args: &[String], timeout: Duration) -> Result<Vec<String>> {
+        let program = executable_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| YT_DLP_EXECUTABLE.to_string());
+
         if !executable_path.exists() {
             return Err(AppError::FileNotFound(format!(
                 "Executable not found: {:?}",
@@ -40,7 +77,7 @@ impl Executor {
 
         self.logger.log_info(&format!(
             "Executing {} with {} arguments",
-            crate::constants::YT_DLP_EXECUTABLE,
+            program,
             args.len()
         ));
         self.logger
@@ -56,65 +93,179 @@ impl Executor {
             .env("TEMP", &temp_dir)
             .env("TMP", &temp_dir)
             .stdin(Stdio::null())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit());
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        // Run the tool in its own process group so a graceful termination signal
+        // reaches it and its children without hitting this process
+        #[cfg(windows)]
+        {
+            const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+            cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+        }
+        #[cfg(unix)]
+        cmd.process_group(0);
 
         self.logger
             .log_debug(&format!("Spawning process: {:?}", executable_path));
 
-        let child = cmd.spawn().map_err(|e| {
+        let mut child = cmd.spawn().map_err(|e| {
             let msg = format!(
                 "Failed to spawn {}: {}",
-                YT_DLP_EXECUTABLE, e
+                program, e
             );
             self.logger.log_error(&msg);
             AppError::Execution(msg)
         })?;
 
-        self.logger
-            .log_debug(&format!("Spawned with PID: {}", child.id()));
-
-        let guard = ChildGuard::new(&self.logger, child);
-
-        // Wait for completion with timeout
-        let status = guard
-            .wait_with_timeout(Duration::from_secs(30))
-            .map_err(|e| {
-                let msg = if e.kind() == std::io::ErrorKind::TimedOut {
-                    format!(
-                        "{} did not respond within 30 seconds and was terminated",
-                        YT_DLP_EXECUTABLE
-                    )
-                } else {
-                    format!(
-                        "Failed while waiting for {}: {}",
-                        YT_DLP_EXECUTABLE, e
-                    )
-                };
-                self.logger.log_error(&msg);
-                AppError::Execution(msg)
-            })?;
+        let started = Instant::now();
+        let pid = child.id().unwrap_or_default();
+        self.logger.event(
+            Level::Debug,
+            &format!("Spawned with PID: {}", pid),
+            &[("program", json!(program)), ("child_pid", json!(pid))],
+        );
+
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let mut guard = ChildGuard::new(&self.logger, child, self.termination_grace);
+
+        // Wait for completion with timeout while both pipes are drained concurrently
+        let mut stdout_lines = Vec::new();
+        let mut stderr_tail = Vec::new();
+        let status = {
+            let readers = async {
+                tokio::join!(
+                    self.capture_stdout(&program, stdout, !Self::dumps_json(args), &mut stdout_lines),
+                    self.log_stderr(&program, stderr, &mut stderr_tail),
+                )
+            };
+            let wait = guard.wait_with_timeout(timeout);
+            tokio::pin!(readers, wait);
 
+            tokio::select! {
+                _ = &mut readers => wait.await,
+                status = &mut wait => {
+                    if tokio::time::timeout(PIPE_DRAIN_TIMEOUT, &mut readers).await.is_err() {
+                        self.logger.log_debug("Output pipes still open after exit; a child process inherited them");
+                    }
+                    status
+                }
+            }
+        };
+
+        let status = status.map_err(|e| {
+            let msg = if e.kind() == std::io::ErrorKind::TimedOut {
+                format!(
+                    "{} did not respond within {} seconds and was terminated",
+                    program,
+                    timeout.as_secs_f32()
+                )
+            } else {
+                format!(
+                    "Failed while waiting for {}: {}",
+                    program, e
+                )
+            };
+            self.logger.event(
+                Level::Error,
+                &msg,
+                &[
+                    ("program", json!(program)),
+                    ("child_pid", json!(pid)),
+                    ("duration_ms", json!(started.elapsed().as_millis() as u64)),
+                ],
+            );
+            AppError::Execution(msg)
+        })?;
+
+        let fields = [
+            ("program", json!(program)),
+            ("child_pid", json!(pid)),
+            ("exit_code", json!(status.code())),
+            ("duration_ms", json!(started.elapsed().as_millis() as u64)),
+        ];
         if !status.success() {
-            let error_msg = if let Some(code) = status.code() {
+            let mut error_msg = if let Some(code) = status.code() {
                 format!(
                     "{} exited with non-zero status code: {}",
-                    YT_DLP_EXECUTABLE, code
+                    program, code
                 )
             } else {
                 format!(
                     "{} terminated by signal/unknown status",
-                    YT_DLP_EXECUTABLE
+                    program
                 )
             };
-            self.logger.log_error(&error_msg);
-            return Err(AppError::Execution(error_msg));
+            // Keep the tool's own explanation so the failure can be classified
+            if !stderr_tail.is_empty() {
+                error_msg = format!("{} ({})", error_msg, stderr_tail.join(" | "));
+            }
+            self.logger.event(Level::Error, &error_msg, &fields);
+            return Err(AppError::ProcessFailed {
+                code: status.code(),
+                message: error_msg,
+            });
+        }
+
+        self.logger.event(Level::Info, "Process completed successfully", &fields);
+        Ok(stdout_lines)
+    }
+
+    /// Collects the tool's stdout line by line, logging each line unless `log_lines` is false.
+    ///
+    /// Lines are collected into `captured` so they survive the reader being abandoned.
+    async fn capture_stdout(
+        &self,
+        program: &str,
+        stdout: Option<impl AsyncRead + Unpin>,
+        log_lines: bool,
+        captured: &mut Vec<String>,
+    ) {
+        let Some(stdout) = stdout else { return };
+        let mut lines = BufReader::new(stdout).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            if log_lines {
+                self.logger.log_trace(&format!("{} stdout: {}", program, line));
+            }
+            captured.push(line);
         }
 
-        self.logger.log_info("Process completed successfully");
-        Ok(())
+        self.logger.log_debug(&format!("Captured {} stdout line(s)", captured.len()));
+    }
+
+    /// Checks whether yt-dlp was asked to dump its JSON metadata, which can run to
+    /// megabytes and would flood the log
+    fn dumps_json(args: &[String]) -> bool {
+        args.iter()
+            .any(|arg| matches!(arg.as_str(), "-J" | "--dump-single-json" | "-j" | "--dump-json"))
     }
 
+    /// Logs the tool's stderr line by line and passes it through to ours.
+    ///
+    /// Keeps the last few error lines in `tail` for the failure message.
+    async fn log_stderr(&self, program: &str, stderr: Option<impl AsyncRead + Unpin>, tail: &mut Vec<String>) {
+        let Some(stderr) = stderr else { return };
+        let mut lines = BufReader::new(stderr).lines();
+        let mut err = tokio::io::stderr();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.starts_with("ERROR") {
+                self.logger.log_error(&format!("{}: {}", program, line));
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.remove(0);
+                }
+                tail.push(line.clone());
+            } else if line.starts_with("WARNING") {
+                self.logger.log_warning(&format!("{}: {}", program, line));
+            } else {
+                self.logger.log_trace(&format!("{}: {}", program, line));
+            }
+            let _ = err.write_all(format!("{}\n", line).as_bytes()).await;
+        }
+    }
 }
 
 impl Executor {
@@ -149,56 +300,41 @@ impl Executor {
     }
 }
 
+/// Owns a running child and terminates its process tree if dropped before it exits
 struct ChildGuard<'a> {
     child: Option<Child>,
     logger: &'a Logger,
+    grace: Duration,
 }
 
 impl<'a> ChildGuard<'a> {
-    fn new(logger: &'a Logger, child: Child) -> Self {
+    fn new(logger: &'a Logger, child: Child, grace: Duration) -> Self {
         Self {
             child: Some(child),
             logger,
+            grace,
         }
     }
 
-    /// Waits for the child to exit and consumes the guard.
-    fn wait(mut self) -> std::io::Result<std::process::ExitStatus> {
-        if let Some(mut child) = self.child.take() {
-            child.wait()
-        } else {
-            // Should never happen
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "child already taken",
-            ))
-        }
-    }
+    /// Waits for the child to exit up to a timeout; terminates it on timeout and returns TimedOut.
+    async fn wait_with_timeout(&mut self, timeout: Duration) -> std::io::Result<ExitStatus> {
+        // The child stays in the guard while waiting, so dropping the wait still terminates the tree
+        let Some(child) = self.child.as_mut() else {
+            return Err(std::io::Error::other("child already taken"));
+        };
 
-    /// Waits for the child to exit up to a timeout; kills it on timeout and returns TimedOut.
-    fn wait_with_timeout(mut self, timeout: Duration) -> std::io::Result<std::process::ExitStatus> {
-        if let Some(mut child) = self.child.take() {
-            let start = Instant::now();
-            loop {
-                match child.try_wait()? {
-                    Some(status) => return Ok(status),
-                    None => {
-                        if start.elapsed() >= timeout {
-                            // Timeout reached: try to kill and wait, then return TimedOut error
-                            self.logger.log_warning("Timeout waiting for child; terminating process...");
-                            let _ = child.kill();
-                            let _ = child.wait();
-                            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "process timeout"));
-                        }
-                        sleep(Duration::from_millis(200));
-                    }
-                }
+        match tokio::time::timeout(timeout, child.wait()).await {
+            Ok(Ok(status)) => {
+                self.child = None;
+                Ok(status)
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                self.logger.log_warning("Timeout waiting for child; terminating process tree...");
+                terminate_tree(child, self.grace, self.logger).await;
+                self.child = None;
+                Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "process timeout"))
             }
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "child already taken",
-            ))
         }
     }
 }
@@ -211,14 +347,23 @@ impl<'a> Drop for ChildGuard<'a> {
                     // already exited
                 }
                 Ok(None) => {
-                    // Still running -> try to terminate and wait
+                    // Still running and we can't wait here -> kill the whole tree right away
                     self.logger
                         .log_warning("Child process still running, attempting to terminate...");
-                    if let Err(e) = child.kill() {
-                        self.logger
-                            .log_warning(&format!("Failed to terminate child: {}", e));
+                    let Some(mut child) = self.child.take() else { return };
+                    let mut kill = move || {
+                        if let Some(pid) = child.id() {
+                            let mut sys = System::new();
+                            sys.refresh_processes();
+                            kill_tree(&sys, &collect_tree(&sys, Pid::from_u32(pid)));
+                        }
+                        let _ = child.start_kill();
+                    };
+                    // Keep the slow process refresh off the runtime thread dropping us
+                    match tokio::runtime::Handle::try_current() {
+                        Ok(handle) => drop(handle.spawn_blocking(kill)),
+                        Err(_) => kill(),
                     }
-                    let _ = child.wait();
                 }
                 Err(e) => {
                     self.logger
@@ -228,3 +373,190 @@ impl<'a> Drop for ChildGuard<'a> {
         }
     }
 }
+
+/// Terminates a child and all of its descendants.
+///
+/// The tree is captured before signalling because descendants are re-parented
+/// once the child exits. A graceful signal is sent first (SIGTERM on Unix,
+/// CTRL_BREAK on Windows); anything still alive after `grace` is killed.
+async fn terminate_tree(child: &mut Child, grace: Duration, logger: &Logger) {
+    let Some(root) = child.id() else {
+        // Already reaped
+        return;
+    };
+
+    // Refreshing the process list is slow blocking work, so it stays off the runtime threads
+    let snapshot = tokio::task::spawn_blocking(move || {
+        let mut sys = System::new();
+        sys.refresh_processes();
+        let tree = collect_tree(&sys, Pid::from_u32(root));
+        (sys, tree)
+    })
+    .await;
+    let Ok((sys, tree)) = snapshot else {
+        let _ = child.kill().await;
+        return;
+    };
+    logger.log_debug(&format!("Terminating process tree of {} processes", tree.len()));
+
+    send_graceful_signal(&sys, &tree, logger);
+    let _ = tokio::time::timeout(grace, child.wait()).await;
+
+    let killed = tokio::task::spawn_blocking(move || {
+        let mut sys = sys;
+        sys.refresh_processes();
+        kill_tree(&sys, &tree)
+    })
+    .await
+    .unwrap_or_default();
+    if killed > 0 {
+        logger.log_warning(&format!("Killed {} process(es) that ignored the termination signal", killed));
+    }
+
+    let _ = child.kill().await;
+}
+
+/// Collects the PIDs of a process and all of its descendants, root first
+fn collect_tree(sys: &System, root: Pid) -> Vec<Pid> {
+    let mut tree = vec![root];
+    let mut index = 0;
+
+    while index < tree.len() {
+        let parent = tree[index];
+        for (pid, process) in sys.processes() {
+            if process.parent() == Some(parent) && !tree.contains(pid) {
+                tree.push(*pid);
+            }
+        }
+        index += 1;
+    }
+
+    tree
+}
+
+/// Kills every process of the tree that is still alive, returning how many were killed
+fn kill_tree(sys: &System, tree: &[Pid]) -> usize {
+    tree.iter()
+        .filter_map(|pid| sys.process(*pid))
+        .filter(|process| process.kill())
+        .count()
+}
+
+#[cfg(unix)]
+fn send_graceful_signal(sys: &System, tree: &[Pid], logger: &Logger) {
+    for pid in tree {
+        if let Some(process) = sys.process(*pid) {
+            if process.kill_with(sysinfo::Signal::Term) != Some(true) {
+                logger.log_debug(&format!("Failed to send SIGTERM to PID {}", pid));
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+fn send_graceful_signal(_sys: &System, tree: &[Pid], logger: &Logger) {
+    use windows_sys::Win32::System::Console::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT};
+
+    // The child leads its own process group, so one event reaches every
+    // descendant that shares our console
+    let root = tree[0].as_u32();
+    let sent = unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, root) };
+    if sent == 0 {
+        logger.log_debug(&format!("Failed to send CTRL_BREAK to process group {}", root));
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::logger::LogConfig;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc-ytdlp-executor-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn executor(dir: &Path) -> Executor {
+        let logger = Logger::with_config(dir.join("test.log"), LogConfig::default());
+        Executor::new(dir.to_path_buf(), Duration::from_millis(200), logger)
+    }
+
+    /// Writes a shell script that starts a long-running grandchild and records its PID
+    fn script(dir: &Path, body: &str) -> PathBuf {
+        let path = dir.join("tool.sh");
+        fs::write(&path, format!("#!/bin/sh\nsleep 30 &\necho $! > grandchild.pid\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    async fn grandchild(dir: &Path) -> Pid {
+        for _ in 0..50 {
+            if let Ok(pid) = fs::read_to_string(dir.join("grandchild.pid")) {
+                if let Ok(pid) = pid.trim().parse() {
+                    return Pid::from_u32(pid);
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("grandchild did not start");
+    }
+
+    /// Waits until a process has exited; an unreaped zombie counts as gone
+    async fn exited(pid: Pid) -> bool {
+        for _ in 0..50 {
+            let mut sys = System::new();
+            sys.refresh_processes();
+            if sys.process(pid).is_none_or(|process| process.status() == sysinfo::ProcessStatus::Zombie) {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn returns_stdout_lines() {
+        let dir = test_dir("stdout");
+        let tool = script(&dir, "kill $!\necho first\necho second");
+
+        let lines = executor(&dir).run(&tool, &["-g".to_string()], Duration::from_secs(5)).await.unwrap();
+        assert_eq!(lines, vec!["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn failures_carry_the_exit_code() {
+        let dir = test_dir("exit-code");
+        let tool = script(&dir, "kill $!\necho 'ERROR: Private video' >&2\nexit 3");
+
+        let error = executor(&dir).run(&tool, &["-g".to_string()], Duration::from_secs(5)).await.unwrap_err();
+        assert_eq!(error.exit_code(), Some(3));
+        assert!(error.to_string().contains("ERROR: Private video"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn timeout_terminates_the_tree() {
+        let dir = test_dir("timeout");
+        let tool = script(&dir, "sleep 30");
+
+        let result = executor(&dir).run(&tool, &["-g".to_string()], Duration::from_millis(500)).await;
+        assert!(matches!(result, Err(AppError::Execution(message)) if message.contains("did not respond")));
+        assert!(exited(grandchild(&dir).await).await);
+    }
+
+    #[tokio::test]
+    async fn dropping_the_future_terminates_the_tree() {
+        let dir = test_dir("drop");
+        let tool = script(&dir, "sleep 30");
+        let executor = executor(&dir);
+        let args = vec!["-g".to_string()];
+
+        let run = executor.run(&tool, &args, Duration::from_secs(30));
+        assert!(tokio::time::timeout(Duration::from_millis(500), run).await.is_err());
+        assert!(exited(grandchild(&dir).await).await);
+    }
+}
@@ -0,0 +1,459 @@
+use std::cmp::Ordering;
+
+use serde::Deserialize;
+
+use crate::args::ArgumentParser;
+use crate::constants::defaults::AUDIO_FALLBACK_MAX_HEIGHT;
+use crate::constants::PLAYABLE_AUDIO_CODECS;
+use crate::error::{AppError, Result};
+use crate::logger::Logger;
+use crate::models::{ProtocolPreference, QualityConfig};
+
+/// Video metadata printed by `yt-dlp -J`
+#[derive(Deserialize)]
+pub struct MediaInfo {
+    #[serde(default)]
+    pub is_live: Option<bool>,
+    #[serde(default)]
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub age_limit: Option<u32>,
+    #[serde(default)]
+    pub channel_id: Option<String>,
+    #[serde(default)]
+    pub uploader: Option<String>,
+    #[serde(default)]
+    pub uploader_id: Option<String>,
+    #[serde(default)]
+    pub formats: Vec<MediaFormat>,
+    /// Extractors with a single format put its fields on the video itself
+    #[serde(flatten)]
+    pub format: MediaFormat,
+}
+
+impl MediaInfo {
+    /// Parses the JSON printed by `yt-dlp -J`
+    pub fn parse(lines: &[String]) -> Result<Self> {
+        serde_json::from_str(&lines.join("\n"))
+            .map_err(|e| AppError::Validation(format!("could not parse yt-dlp metadata: {}", e)))
+    }
+}
+
+/// One entry of the `formats` list, limited to the fields used for ranking
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct MediaFormat {
+    pub format_id: Option<String>,
+    pub url: Option<String>,
+    pub protocol: Option<String>,
+    pub vcodec: Option<String>,
+    pub acodec: Option<String>,
+    pub height: Option<u32>,
+    pub fps: Option<f64>,
+    pub tbr: Option<f64>,
+}
+
+impl MediaFormat {
+    fn id(&self) -> &str {
+        self.format_id.as_deref().unwrap_or("?")
+    }
+
+    fn protocol(&self) -> &str {
+        self.protocol.as_deref().unwrap_or("")
+    }
+
+    fn is_hls(&self) -> bool {
+        self.protocol().starts_with("m3u8")
+    }
+
+    fn is_progressive(&self) -> bool {
+        matches!(self.protocol(), "http" | "https")
+    }
+
+    /// yt-dlp reports a missing stream as the codec "none"; unknown codecs are assumed present
+    fn has_video(&self) -> bool {
+        self.vcodec.as_deref() != Some("none")
+    }
+
+    fn has_audio(&self) -> bool {
+        self.acodec.as_deref() != Some("none")
+    }
+
+    /// Short description used in logs, e.g. `96 (1080p30, avc1.640028/mp4a.40.2, m3u8_native)`
+    fn describe(&self) -> String {
+        let height = self.height.map(|h| format!("{}p", h)).unwrap_or_else(|| "?p".to_string());
+        let fps = self.fps.map(|f| format!("{}", f.round() as u32)).unwrap_or_default();
+        format!(
+            "{} ({}{}, {}/{}, {})",
+            self.id(),
+            height,
+            fps,
+            self.vcodec.as_deref().unwrap_or("?"),
+            self.acodec.as_deref().unwrap_or("?"),
+            self.protocol.as_deref().unwrap_or("?")
+        )
+    }
+}
+
+/// Ranking criteria in order of importance
+const CRITERIA: [&str; 5] = ["protocol", "codec", "height", "fps", "bitrate"];
+
+/// Chooses a format from yt-dlp's metadata according to the quality settings.
+///
+/// Height, fps and missing audio/video are hard limits; the remaining formats are
+/// ranked by protocol, codec preference, height, fps and finally bitrate.
+pub struct FormatSelector<'a> {
+    config: &'a QualityConfig,
+}
+
+impl<'a> FormatSelector<'a> {
+    pub fn new(config: &'a QualityConfig) -> Self {
+        Self { config }
+    }
+
+    /// Turns a `--get-url` argument list into one that dumps the metadata instead
+    pub fn metadata_args(args: &[String]) -> Vec<String> {
+        // The selector picks the format itself
+        let mut metadata_args: Vec<String> = ArgumentParser::without_format_selector(args)
+            .into_iter()
+            .filter(|arg| arg != "--get-url" && arg != "-g")
+            .collect();
+
+        // A playlist URL would otherwise dump the metadata of every entry
+        if !metadata_args.iter().any(|arg| arg == "--no-playlist") {
+            metadata_args.push("--no-playlist".to_string());
+        }
+        metadata_args.push("-J".to_string());
+        metadata_args
+    }
+
+    /// Parses the `-J` output and returns the URL of the best format
+    pub fn select_from_output(&self, lines: &[String], logger: &Logger) -> Result<String> {
+        self.select_media(&MediaInfo::parse(lines)?, logger)
+    }
+
+    /// Picks the best format, applying the live overrides to live streams
+    pub fn select_media(&self, info: &MediaInfo, logger: &Logger) -> Result<String> {
+        if info.is_live == Some(true) {
+            logger.log_debug("Live stream, applying live quality overrides");
+            return FormatSelector::new(&self.config.for_live()).select(info, logger);
+        }
+        self.select(info, logger)
+    }
+
+    /// Picks the best format and logs why it won
+    pub fn select(&self, info: &MediaInfo, logger: &Logger) -> Result<String> {
+        let formats: Vec<&MediaFormat> = if info.formats.is_empty() {
+            vec![&info.format]
+        } else {
+            info.formats.iter().collect()
+        };
+
+        let mut eligible = Vec::new();
+
+        for format in &formats {
+            match self.rejection(format) {
+                Some(reason) => logger.log_debug(&format!("Skipping format {}: {}", format.describe(), reason)),
+                None => eligible.push(*format),
+            }
+        }
+
+        if eligible.is_empty() && self.config.audio_only {
+            logger.log_info("No standalone audio format, falling back to a small combined format");
+            let mut fallback = self.config.clone();
+            fallback.audio_only = false;
+            fallback.max_height = Some(fallback.max_height.unwrap_or(AUDIO_FALLBACK_MAX_HEIGHT).min(AUDIO_FALLBACK_MAX_HEIGHT));
+            return FormatSelector::new(&fallback).select(info, logger);
+        }
+
+        if eligible.is_empty() {
+            return Err(AppError::Validation(format!(
+                "none of the {} formats matched the quality settings",
+                formats.len()
+            )));
+        }
+
+        // Stable sort, so equally ranked formats keep yt-dlp's order
+        eligible.sort_by(|a, b| self.compare(b, a));
+        let winner = eligible[0];
+
+        let reason = match eligible.get(1) {
+            Some(runner_up) => match self.deciding_criterion(winner, runner_up) {
+                Some(criterion) => format!("beat {} on {}", runner_up.describe(), criterion),
+                None => format!("tied with {}; kept yt-dlp's order", runner_up.describe()),
+            },
+            None => "only eligible format".to_string(),
+        };
+
+        logger.log_info(&format!(
+            "Selected format {} ({} eligible of {} formats): {}",
+            winner.describe(),
+            eligible.len(),
+            formats.len(),
+            reason
+        ));
+
+        winner
+            .url
+            .clone()
+            .ok_or_else(|| AppError::Validation("selected format has no URL".to_string()))
+    }
+
+    /// Explains why a format can't be chosen at all
+    fn rejection(&self, format: &MediaFormat) -> Option<String> {
+        if format.url.is_none() {
+            return Some("missing URL".to_string());
+        }
+        if !format.has_video() && !format.has_audio() || format.protocol() == "mhtml" {
+            return Some("storyboard or non-media format".to_string());
+        }
+        if self.config.audio_only {
+            return format.has_video().then(|| "carries video, audio only requested".to_string());
+        }
+        // VRChat plays a single URL, so a separate stream would play silent or blank
+        if !format.has_audio() {
+            return Some("video-only stream".to_string());
+        }
+        if !format.has_video() {
+            return Some("audio-only stream".to_string());
+        }
+        if self.config.protocol_required && !self.matches_protocol(format) {
+            return Some(format!("protocol {} not allowed", format.protocol()));
+        }
+        if let (Some(max), Some(height)) = (self.config.max_height, format.height) {
+            if height > max {
+                return Some(format!("height {}p above max {}p", height, max));
+            }
+        }
+        if let (Some(max), Some(fps)) = (self.config.max_fps, format.fps) {
+            if fps > max as f64 {
+                return Some(format!("fps {} above max {}", fps, max));
+            }
+        }
+        None
+    }
+
+    fn matches_protocol(&self, format: &MediaFormat) -> bool {
+        match self.config.protocol {
+            ProtocolPreference::Hls => format.is_hls(),
+            ProtocolPreference::Progressive => format.is_progressive(),
+            ProtocolPreference::Any => true,
+        }
+    }
+
+    /// Scores each criterion, higher is better
+    fn scores(&self, format: &MediaFormat) -> [f64; 5] {
+        let protocol = self.matches_protocol(format);
+
+        // Audio-only requests rank by audio codec, everything else by video codec
+        let (codecs, codec): (Vec<String>, String) = if self.config.audio_only {
+            (
+                PLAYABLE_AUDIO_CODECS.iter().map(|c| c.to_string()).collect(),
+                format.acodec.as_deref().unwrap_or("").to_ascii_lowercase(),
+            )
+        } else {
+            (
+                self.config.preferred_codecs.clone(),
+                format.vcodec.as_deref().unwrap_or("").to_ascii_lowercase(),
+            )
+        };
+        let codec = codecs
+            .iter()
+            .position(|preferred| codec.starts_with(&preferred.to_ascii_lowercase()))
+            .map(|index| codecs.len() - index)
+            .unwrap_or(0);
+
+        [
+            protocol as u8 as f64,
+            codec as f64,
+            format.height.unwrap_or(0) as f64,
+            format.fps.unwrap_or(0.0),
+            format.tbr.unwrap_or(0.0),
+        ]
+    }
+
+    fn compare(&self, a: &MediaFormat, b: &MediaFormat) -> Ordering {
+        self.scores(a)
+            .iter()
+            .zip(self.scores(b).iter())
+            .map(|(x, y)| x.partial_cmp(y).unwrap_or(Ordering::Equal))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+
+    /// Names the first criterion on which the winner ranked higher
+    fn deciding_criterion(&self, winner: &MediaFormat, runner_up: &MediaFormat) -> Option<&'static str> {
+        self.scores(winner)
+            .iter()
+            .zip(self.scores(runner_up).iter())
+            .position(|(x, y)| x != y)
+            .map(|index| CRITERIA[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::logger::LogConfig;
+    use crate::models::QualityOverrides;
+
+    fn logger() -> Logger {
+        Logger::with_config(std::env::temp_dir().join("vrc-ytdlp-format-test.log"), LogConfig::default())
+    }
+
+    /// A format entry; the URL names the format so tests can tell which one won
+    fn format(id: &str, protocol: &str, vcodec: &str, acodec: &str, height: u32, fps: f64, tbr: f64) -> Value {
+        json!({
+            "format_id": id,
+            "url": format!("https://cdn.example.com/{}", id),
+            "protocol": protocol,
+            "vcodec": vcodec,
+            "acodec": acodec,
+            "height": height,
+            "fps": fps,
+            "tbr": tbr,
+        })
+    }
+
+    fn info(formats: Vec<Value>) -> MediaInfo {
+        MediaInfo::parse(&[json!({ "formats": formats }).to_string()]).unwrap()
+    }
+
+    fn select(quality: &QualityConfig, info: &MediaInfo) -> Result<String> {
+        FormatSelector::new(quality).select_media(info, &logger())
+    }
+
+    fn youtube() -> MediaInfo {
+        info(vec![
+            format("sb0", "mhtml", "none", "none", 90, 0.0, 0.0),
+            format("18", "https", "avc1.42001E", "mp4a.40.2", 360, 30.0, 500.0),
+            format("137", "https", "avc1.640028", "none", 1080, 30.0, 4000.0),
+            format("140", "https", "none", "mp4a.40.2", 0, 0.0, 128.0),
+            format("95", "m3u8_native", "avc1.4d401f", "mp4a.40.2", 720, 30.0, 2500.0),
+            format("96", "m3u8_native", "avc1.640028", "mp4a.40.2", 1080, 30.0, 5000.0),
+            format("301", "m3u8_native", "avc1.640028", "mp4a.40.2", 1080, 60.0, 6000.0),
+            format("614", "m3u8_native", "vp09.00.40.08", "mp4a.40.2", 1080, 30.0, 3000.0),
+            format("400", "m3u8_native", "av01.0.08M.08", "mp4a.40.2", 1440, 30.0, 8000.0),
+        ])
+    }
+
+    #[test]
+    fn ranks_protocol_then_codec_then_height_fps_and_bitrate() {
+        let quality = QualityConfig::default();
+
+        // 400 is over the height limit, 614 loses on codec, 301 wins on fps
+        assert_eq!(select(&quality, &youtube()).unwrap(), "https://cdn.example.com/301");
+
+        let capped = QualityConfig {
+            max_fps: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(select(&capped, &youtube()).unwrap(), "https://cdn.example.com/96");
+
+        let vp9 = QualityConfig {
+            preferred_codecs: vec!["vp09".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(select(&vp9, &youtube()).unwrap(), "https://cdn.example.com/614");
+
+        let progressive = QualityConfig {
+            protocol: ProtocolPreference::Progressive,
+            ..Default::default()
+        };
+        assert_eq!(select(&progressive, &youtube()).unwrap(), "https://cdn.example.com/18");
+    }
+
+    #[test]
+    fn separate_streams_are_never_chosen() {
+        // Only the video-only 137 and the audio-only 140 satisfy the protocol
+        let quality = QualityConfig {
+            protocol: ProtocolPreference::Progressive,
+            protocol_required: true,
+            max_height: None,
+            ..Default::default()
+        };
+        let info = info(vec![
+            format("137", "https", "avc1.640028", "none", 1080, 30.0, 4000.0),
+            format("140", "https", "none", "mp4a.40.2", 0, 0.0, 128.0),
+            format("96", "m3u8_native", "avc1.640028", "mp4a.40.2", 1080, 30.0, 5000.0),
+        ]);
+
+        assert!(matches!(select(&quality, &info), Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn unknown_codecs_count_as_present() {
+        let info = MediaInfo::parse(&[json!({
+            "url": "https://cdn.example.com/live.m3u8",
+            "protocol": "m3u8_native",
+        })
+        .to_string()])
+        .unwrap();
+
+        assert_eq!(select(&QualityConfig::default(), &info).unwrap(), "https://cdn.example.com/live.m3u8");
+    }
+
+    #[test]
+    fn audio_only_prefers_playable_audio_codecs() {
+        let quality = QualityConfig {
+            audio_only: true,
+            ..Default::default()
+        };
+        let info = info(vec![
+            format("18", "https", "avc1.42001E", "mp4a.40.2", 360, 30.0, 500.0),
+            format("251", "https", "none", "opus", 0, 0.0, 160.0),
+            format("140", "https", "none", "mp4a.40.2", 0, 0.0, 128.0),
+        ]);
+
+        assert_eq!(select(&quality, &info).unwrap(), "https://cdn.example.com/140");
+    }
+
+    #[test]
+    fn audio_only_falls_back_to_a_small_combined_format() {
+        let quality = QualityConfig {
+            audio_only: true,
+            ..Default::default()
+        };
+
+        // 95 would win on height if the fallback weren't capped at 360p
+        assert_eq!(
+            select(&quality, &info(vec![
+                format("18", "https", "avc1.42001E", "mp4a.40.2", 360, 30.0, 500.0),
+                format("95", "m3u8_native", "avc1.4d401f", "mp4a.40.2", 720, 30.0, 2500.0),
+            ]))
+            .unwrap(),
+            "https://cdn.example.com/18"
+        );
+    }
+
+    #[test]
+    fn live_streams_use_the_live_overrides() {
+        let quality = QualityConfig {
+            live: QualityOverrides {
+                max_height: Some(720),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut live = youtube();
+        live.is_live = Some(true);
+
+        assert_eq!(select(&quality, &live).unwrap(), "https://cdn.example.com/95");
+        assert_eq!(select(&quality, &youtube()).unwrap(), "https://cdn.example.com/301");
+    }
+
+    #[test]
+    fn metadata_args_dump_json_for_one_video() {
+        let args: Vec<String> = ["--no-warnings", "-f", "best", "--get-url", "https://example.com/v"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+
+        assert_eq!(
+            FormatSelector::metadata_args(&args),
+            vec!["--no-warnings", "https://example.com/v", "--no-playlist", "-J"]
+        );
+    }
+}
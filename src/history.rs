@@ -0,0 +1,486 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{HISTORY_FILE_NAME, HISTORY_LOCK_FILE_NAME};
+use crate::error::{AppError, Result};
+use crate::logger::Logger;
+use crate::models::HistoryConfig;
+use crate::resolver::CacheResolver;
+use crate::rules;
+
+/// One invocation as stored in the history file
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct HistoryRecord {
+    pub timestamp: DateTime<Utc>,
+    /// ID tagging the invocation's log lines
+    pub invocation_id: Option<String>,
+    /// Arguments VRChat passed to the shim
+    pub args: Vec<String>,
+    pub requested_url: Option<String>,
+    /// The requested URL after rewrite rules
+    pub canonical_url: Option<String>,
+    /// Name of the domain rule matching the canonical URL
+    pub rule: Option<String>,
+    pub player: Option<String>,
+    /// Resolver that produced the URL
+    pub resolver: Option<String>,
+    /// Last yt-dlp strategy tried
+    pub strategy: Option<String>,
+    /// Arguments of the last yt-dlp run
+    pub ytdlp_args: Vec<String>,
+    pub ytdlp_version: Option<String>,
+    pub success: bool,
+    /// Exit code of the last yt-dlp run, when it ran to completion
+    pub exit_code: Option<i32>,
+    pub failure_class: Option<String>,
+    pub error: Option<String>,
+    /// Host of the resolved URL, before any relay
+    pub resolved_host: Option<String>,
+    /// Milliseconds spent per phase, e.g. `content_check`, `update_check`, `yt-dlp`
+    pub phases: BTreeMap<String, u64>,
+    pub total_ms: u64,
+}
+
+impl HistoryRecord {
+    /// Host the request was for, after rewrite rules
+    pub fn domain(&self) -> Option<String> {
+        self.canonical_url
+            .as_deref()
+            .or(self.requested_url.as_deref())
+            .and_then(rules::host_of)
+    }
+}
+
+#[derive(Default)]
+struct TraceData {
+    strategy: Option<String>,
+    ytdlp_args: Vec<String>,
+    exit_code: Option<i32>,
+    phases: BTreeMap<String, u64>,
+}
+
+/// Details collected by the resolvers while a request runs, shared by every clone
+#[derive(Clone, Default)]
+pub struct InvocationTrace {
+    data: Arc<Mutex<TraceData>>,
+}
+
+impl InvocationTrace {
+    pub fn set_strategy(&self, strategy: &str) {
+        self.lock().strategy = Some(strategy.to_string());
+    }
+
+    pub fn set_ytdlp_args(&self, args: &[String]) {
+        self.lock().ytdlp_args = args.to_vec();
+    }
+
+    pub fn set_exit_code(&self, code: Option<i32>) {
+        self.lock().exit_code = code;
+    }
+
+    /// Adds time to a phase; phases entered more than once accumulate
+    pub fn add_phase(&self, phase: &str, elapsed: Duration) {
+        *self.lock().phases.entry(phase.to_string()).or_default() += elapsed.as_millis() as u64;
+    }
+
+    /// Copies the collected details into a record
+    pub fn fill(&self, record: &mut HistoryRecord) {
+        let data = self.lock();
+        record.strategy = data.strategy.clone();
+        record.ytdlp_args = data.ytdlp_args.clone();
+        record.exit_code = data.exit_code;
+        record.phases = data.phases.clone();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TraceData> {
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Invocation history, one JSON record per line in the app directory.
+///
+/// Appends are single writes to a file opened in append mode and hold a shared
+/// lock on a sidecar file; pruning rewrites the file under the exclusive lock, so
+/// it never replaces the file while another shim or the daemon is appending.
+pub struct HistoryStore {
+    path: PathBuf,
+    lock_path: PathBuf,
+    retention: chrono::Duration,
+    logger: Logger,
+}
+
+impl HistoryStore {
+    pub fn new(config: &HistoryConfig, app_dir: &Path, logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
+        Self {
+            path: app_dir.join(HISTORY_FILE_NAME),
+            lock_path: app_dir.join(HISTORY_LOCK_FILE_NAME),
+            retention: chrono::Duration::days(config.retention_days as i64),
+            logger,
+        }
+    }
+
+    /// Appends a record, then drops records past the retention period unless
+    /// another process is already pruning
+    pub fn append(&self, record: &HistoryRecord) {
+        let lock = match OpenOptions::new().create(true).truncate(false).write(true).open(&self.lock_path) {
+            Ok(lock) => lock,
+            Err(e) => {
+                self.logger.log_warning(&format!("Failed to record history: {}", e));
+                return;
+            }
+        };
+
+        let written = lock.lock_shared().map_err(AppError::from).and_then(|_| self.write(record));
+        if let Err(e) = written {
+            self.logger.log_warning(&format!("Failed to record history: {}", e));
+        }
+        let _ = lock.unlock();
+
+        if lock.try_lock().is_ok() {
+            if let Err(e) = self.prune() {
+                self.logger.log_warning(&format!("Failed to prune history: {}", e));
+            }
+        }
+    }
+
+    /// Reads every record, oldest first; lines that don't parse are skipped
+    pub fn load(&self) -> Vec<HistoryRecord> {
+        let Ok(file) = fs::File::open(&self.path) else {
+            return Vec::new();
+        };
+
+        BufReader::new(file)
+            .lines()
+            .map_while(|line| line.ok())
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect()
+    }
+
+    fn write(&self, record: &HistoryRecord) -> Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Rewrites the file without expired records; only the first line is read unless one expired.
+    /// Must run under the exclusive lock.
+    fn prune(&self) -> Result<()> {
+        let cutoff = Utc::now() - self.retention;
+        let Ok(file) = fs::File::open(&self.path) else {
+            return Ok(());
+        };
+
+        let mut first = String::new();
+        BufReader::new(file).read_line(&mut first)?;
+        let expired = serde_json::from_str::<HistoryRecord>(&first).is_ok_and(|record| record.timestamp < cutoff);
+        if !expired {
+            return Ok(());
+        }
+
+        let kept: Vec<String> = self
+            .load()
+            .into_iter()
+            .filter(|record| record.timestamp >= cutoff)
+            .map(|record| serde_json::to_string(&record))
+            .collect::<std::result::Result<_, _>>()?;
+
+        let tmp_path = self.path.with_extension(format!("tmp.{}", std::process::id()));
+        let mut content = kept.join("\n");
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Filters and output options shared by the `history` and `stats` subcommands
+#[derive(Default)]
+pub struct HistoryQuery {
+    /// Host pattern, matching subdomains like domain rules do
+    pub domain: Option<String>,
+    pub failed_only: bool,
+    pub since: Option<DateTime<Utc>>,
+    /// Most recent records to list (default: 20); statistics always use every match
+    pub limit: Option<usize>,
+    pub json: bool,
+}
+
+impl HistoryQuery {
+    pub const USAGE: &'static str = "[--domain <host>] [--failed] [--since <30m|24h|7d|YYYY-MM-DD>] [--limit <n>] [--json]";
+
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut query = Self::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| AppError::Config(format!("{} needs a value", arg)))
+            };
+            match arg.as_str() {
+                "--domain" => query.domain = Some(value()?.to_ascii_lowercase()),
+                "--failed" => query.failed_only = true,
+                "--since" => query.since = Some(Self::parse_since(value()?)?),
+                "--limit" => {
+                    let limit = value()?;
+                    query.limit = Some(
+                        limit
+                            .parse()
+                            .map_err(|_| AppError::Config(format!("Invalid --limit: {}", limit)))?,
+                    );
+                }
+                "--json" => query.json = true,
+                other => return Err(AppError::Config(format!("Unknown option: {}", other))),
+            }
+        }
+
+        Ok(query)
+    }
+
+    /// Accepts a relative age (`30m`, `24h`, `7d`), a local date or an RFC 3339 timestamp
+    fn parse_since(value: &str) -> Result<DateTime<Utc>> {
+        let invalid = || AppError::Config(format!("Invalid --since: {}", value));
+
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+            return Ok(timestamp.with_timezone(&Utc));
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            return date
+                .and_hms_opt(0, 0, 0)
+                .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+                .map(|midnight| midnight.with_timezone(&Utc))
+                .ok_or_else(invalid);
+        }
+
+        let unit = value.chars().last().ok_or_else(invalid)?;
+        let amount: i64 = value[..value.len() - unit.len_utf8()].parse().map_err(|_| invalid())?;
+        let age = match unit {
+            'm' => chrono::Duration::minutes(amount),
+            'h' => chrono::Duration::hours(amount),
+            'd' => chrono::Duration::days(amount),
+            _ => return Err(invalid()),
+        };
+        Ok(Utc::now() - age)
+    }
+
+    pub fn matches(&self, record: &HistoryRecord) -> bool {
+        if self.failed_only && record.success {
+            return false;
+        }
+        if self.since.is_some_and(|since| record.timestamp < since) {
+            return false;
+        }
+        match &self.domain {
+            Some(pattern) => record.domain().is_some_and(|host| rules::host_matches(&host, pattern)),
+            None => true,
+        }
+    }
+
+    /// Matching records, oldest first, limited to the most recent ones
+    pub fn select(&self, records: Vec<HistoryRecord>) -> Vec<HistoryRecord> {
+        let mut selected: Vec<_> = records.into_iter().filter(|record| self.matches(record)).collect();
+        let limit = self.limit.unwrap_or(crate::constants::defaults::HISTORY_LIST_LIMIT);
+        selected.drain(..selected.len().saturating_sub(limit));
+        selected
+    }
+}
+
+/// Success rate and latency for a group of records
+#[derive(Serialize)]
+pub struct RequestStats {
+    pub requests: usize,
+    pub succeeded: usize,
+    pub success_rate: f64,
+    pub p50_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+}
+
+impl RequestStats {
+    fn compute<'r>(records: impl Iterator<Item = &'r HistoryRecord>) -> Self {
+        let mut latencies = Vec::new();
+        let mut succeeded = 0;
+        for record in records {
+            latencies.push(record.total_ms);
+            succeeded += record.success as usize;
+        }
+        latencies.sort_unstable();
+
+        Self {
+            requests: latencies.len(),
+            succeeded,
+            success_rate: Self::ratio(succeeded, latencies.len()),
+            p50_ms: Self::percentile(&latencies, 50),
+            p95_ms: Self::percentile(&latencies, 95),
+        }
+    }
+
+    /// Nearest-rank percentile of sorted values
+    fn percentile(sorted: &[u64], percent: usize) -> Option<u64> {
+        let rank = (sorted.len() * percent).div_ceil(100).max(1);
+        sorted.get(rank - 1).copied()
+    }
+
+    fn ratio(part: usize, whole: usize) -> f64 {
+        if whole == 0 {
+            0.0
+        } else {
+            part as f64 / whole as f64
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DomainStats {
+    pub domain: String,
+    #[serde(flatten)]
+    pub stats: RequestStats,
+}
+
+#[derive(Serialize)]
+pub struct FailureCount {
+    pub class: String,
+    pub count: usize,
+}
+
+/// Aggregates reported by the `stats` subcommand
+#[derive(Serialize)]
+pub struct HistoryStats {
+    #[serde(flatten)]
+    pub overall: RequestStats,
+    /// Share of URL lookups answered from the resolution cache
+    pub cache_hit_ratio: f64,
+    /// Busiest domains first
+    pub domains: Vec<DomainStats>,
+    /// Most common first
+    pub failure_classes: Vec<FailureCount>,
+}
+
+impl HistoryStats {
+    pub fn compute(records: &[HistoryRecord]) -> Self {
+        let lookups = records.iter().filter(|record| record.requested_url.is_some()).count();
+        let cache_hits = records
+            .iter()
+            .filter(|record| record.resolver.as_deref() == Some(CacheResolver::NAME))
+            .count();
+
+        let mut by_domain: BTreeMap<String, Vec<&HistoryRecord>> = BTreeMap::new();
+        for record in records {
+            if let Some(domain) = record.domain() {
+                by_domain.entry(domain).or_default().push(record);
+            }
+        }
+        let mut domains: Vec<DomainStats> = by_domain
+            .into_iter()
+            .map(|(domain, records)| DomainStats {
+                domain,
+                stats: RequestStats::compute(records.into_iter()),
+            })
+            .collect();
+        domains.sort_by_key(|domain| std::cmp::Reverse(domain.stats.requests));
+
+        let mut by_class: BTreeMap<&str, usize> = BTreeMap::new();
+        for class in records.iter().filter_map(|record| record.failure_class.as_deref()) {
+            *by_class.entry(class).or_default() += 1;
+        }
+        let mut failure_classes: Vec<FailureCount> = by_class
+            .into_iter()
+            .map(|(class, count)| FailureCount {
+                class: class.to_string(),
+                count,
+            })
+            .collect();
+        failure_classes.sort_by_key(|failure| std::cmp::Reverse(failure.count));
+
+        Self {
+            overall: RequestStats::compute(records.iter()),
+            cache_hit_ratio: RequestStats::ratio(cache_hits, lookups),
+            domains,
+            failure_classes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveTime, TimeZone};
+
+    use super::*;
+
+    #[test]
+    fn since_accepts_timestamps_and_dates() {
+        assert_eq!(
+            HistoryQuery::parse_since("2026-01-02T03:04:05+02:00").unwrap(),
+            Utc.with_ymd_and_hms(2026, 1, 2, 1, 4, 5).unwrap()
+        );
+
+        // Dates mean local midnight
+        let since = HistoryQuery::parse_since("2026-01-02").unwrap().with_timezone(&Local);
+        assert_eq!(since.date_naive(), NaiveDate::from_ymd_opt(2026, 1, 2).unwrap());
+        assert_eq!(since.time(), NaiveTime::MIN);
+    }
+
+    #[test]
+    fn since_accepts_relative_ages() {
+        for (value, age) in [
+            ("30m", chrono::Duration::minutes(30)),
+            ("24h", chrono::Duration::hours(24)),
+            ("7d", chrono::Duration::days(7)),
+        ] {
+            let expected = Utc::now() - age;
+            let since = HistoryQuery::parse_since(value).unwrap();
+            assert!((since - expected).num_seconds().abs() < 5, "{}", value);
+        }
+    }
+
+    #[test]
+    fn since_rejects_other_values() {
+        for value in ["", "m", "7", "7w", "soon", "2026-13-01", "1.5h"] {
+            assert!(HistoryQuery::parse_since(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn percentile_uses_nearest_rank() {
+        assert_eq!(RequestStats::percentile(&[], 50), None);
+        assert_eq!(RequestStats::percentile(&[42], 50), Some(42));
+        assert_eq!(RequestStats::percentile(&[42], 95), Some(42));
+        assert_eq!(RequestStats::percentile(&[1, 2, 3, 4], 50), Some(2));
+        assert_eq!(RequestStats::percentile(&[1, 2, 3, 4], 95), Some(4));
+
+        let hundred: Vec<u64> = (1..=100).collect();
+        assert_eq!(RequestStats::percentile(&hundred, 50), Some(50));
+        assert_eq!(RequestStats::percentile(&hundred, 95), Some(95));
+    }
+
+    #[test]
+    fn stats_count_successes_and_latency() {
+        let records: Vec<HistoryRecord> = [(true, 100), (false, 300), (true, 200)]
+            .into_iter()
+            .map(|(success, total_ms)| HistoryRecord {
+                success,
+                total_ms,
+                ..Default::default()
+            })
+            .collect();
+
+        let stats = RequestStats::compute(records.iter());
+        assert_eq!(stats.requests, 3);
+        assert_eq!(stats.succeeded, 2);
+        assert!((stats.success_rate - 2.0 / 3.0).abs() < f64::EPSILON);
+        assert_eq!(stats.p50_ms, Some(200));
+        assert_eq!(stats.p95_ms, Some(300));
+    }
+}
@@ -1,15 +1,53 @@
 pub mod args;
+pub mod cache;
 pub mod config;
 pub mod constants;
+pub mod daemon;
+pub mod deadline;
 pub mod downloader;
 pub mod error;
 pub mod executor;
+pub mod format;
+pub mod history;
+pub mod library;
 pub mod logger;
+pub mod media_server;
 pub mod models;
+pub mod output;
+pub mod passthrough;
+pub mod player;
+pub mod policy;
+pub mod prefetch;
+pub mod probe;
+pub mod relay;
+pub mod replay;
+pub mod resolver;
+pub mod rewrite;
+pub mod rules;
+pub mod service;
 
 pub use args::ArgumentParser;
+pub use cache::ResolutionCache;
 pub use config::ConfigManager;
+pub use daemon::{Daemon, DaemonClient};
+pub use deadline::Deadline;
 pub use downloader::Downloader;
 pub use error::{AppError, Result};
 pub use executor::Executor;
+pub use format::FormatSelector;
+pub use history::HistoryStore;
+pub use library::MediaLibrary;
 pub use logger::Logger;
+pub use media_server::MediaServer;
+pub use output::OutputValidator;
+pub use passthrough::Passthrough;
+pub use player::PlayerDetector;
+pub use policy::DomainPolicy;
+pub use prefetch::PrefetchQueue;
+pub use probe::UrlProbe;
+pub use relay::HlsRelay;
+pub use replay::Replay;
+pub use resolver::{ResolveRequest, Resolution, Resolver, ResolverPipeline};
+pub use rewrite::UrlRewriter;
+
+pub use service::ResolveService;
\ No newline at end of file
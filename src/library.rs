@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::constants::{LIBRARY_INDEX_FILE_NAME, LIBRARY_LOCK_FILE_NAME};
+use crate::error::{AppError, Result};
+use crate::logger::Logger;
+use crate::models::LibraryConfig;
+use crate::resolver::YtDlpResolver;
+
+/// A downloaded file in the library
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LibraryEntry {
+    pub url: String,
+    pub file_name: String,
+    pub size_bytes: u64,
+    pub added_at: DateTime<Utc>,
+    pub last_played: Option<DateTime<Utc>>,
+}
+
+impl LibraryEntry {
+    /// When the entry was last useful, for LRU eviction
+    pub fn last_used(&self) -> DateTime<Utc> {
+        self.last_played.unwrap_or(self.added_at)
+    }
+}
+
+/// Full media files downloaded ahead of time for offline playback.
+///
+/// The index is a JSON file inside the library directory, keyed by the
+/// (rewritten) requested URL, and saved atomically under a sidecar lock like the
+/// resolution cache, so the shim, the daemon and CLI commands don't lose updates.
+pub struct MediaLibrary {
+    config: LibraryConfig,
+    dir: PathBuf,
+    logger: Logger,
+}
+
+impl MediaLibrary {
+    pub fn new(config: &LibraryConfig, app_dir: &Path, logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
+        let dir = Self::directory_for(config, app_dir);
+        Self {
+            config: config.clone(),
+            dir,
+            logger,
+        }
+    }
+
+    /// Resolves the library directory, relative paths being relative to the app directory
+    pub fn directory_for(config: &LibraryConfig, app_dir: &Path) -> PathBuf {
+        let path = PathBuf::from(&config.directory);
+        if path.is_absolute() {
+            path
+        } else {
+            app_dir.join(path)
+        }
+    }
+
+    /// Looks up the entry for a URL whose file is still on disk
+    pub fn get(&self, url: &str) -> Option<LibraryEntry> {
+        self.load()
+            .remove(url)
+            .filter(|entry| self.dir.join(&entry.file_name).is_file())
+    }
+
+    /// Path of a file listed in the index, for the media server; anything else in the
+    /// directory, or a name that isn't a single plain path component, is not served
+    pub fn file_path(&self, file_name: &str) -> Option<PathBuf> {
+        let mut components = Path::new(file_name).components();
+        if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+            return None;
+        }
+
+        self.load()
+            .values()
+            .any(|entry| entry.file_name == file_name)
+            .then(|| self.dir.join(file_name))
+    }
+
+    /// Records that an entry was played, so it is evicted last
+    pub fn touch(&self, url: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut entries = self.load();
+        if let Some(entry) = entries.get_mut(url) {
+            entry.last_played = Some(Utc::now());
+            self.save(&entries)?;
+        }
+        Ok(())
+    }
+
+    /// Lists the entries, most recently used first
+    pub fn list(&self) -> Vec<LibraryEntry> {
+        let mut entries: Vec<LibraryEntry> = self.load().into_values().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_used()));
+        entries
+    }
+
+    /// Downloads a URL into the library, evicting older entries to stay under the size cap
+    pub async fn add(&self, url: &str, ytdlp: &YtDlpResolver) -> Result<LibraryEntry> {
+        fs::create_dir_all(&self.dir)?;
+
+        let template = self.dir.join(format!("{}.%(ext)s", Self::file_stem(url)));
+        let timeout = Duration::from_secs(self.config.download_timeout_secs);
+        self.logger.log_info(&format!("Downloading {} into the library", url));
+        let path = ytdlp.download(url, &self.config.format, &template, timeout).await?;
+
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| AppError::Execution(format!("unexpected download path {}", path.display())))?;
+        let entry = LibraryEntry {
+            url: url.to_string(),
+            file_name,
+            size_bytes: fs::metadata(&path)?.len(),
+            added_at: Utc::now(),
+            last_played: None,
+        };
+
+        let _lock = self.lock()?;
+        let mut entries = self.load();
+        if let Some(previous) = entries.insert(url.to_string(), entry.clone()) {
+            if previous.file_name != entry.file_name {
+                self.delete_file(&previous);
+            }
+        }
+        self.evict(&mut entries, url);
+        self.save(&entries)?;
+
+        self.logger.log_info(&format!(
+            "Added {} to the library as {} ({} MB)",
+            url,
+            entry.file_name,
+            entry.size_bytes / (1024 * 1024)
+        ));
+        Ok(entry)
+    }
+
+    /// Removes a URL and its file; returns `false` when it wasn't in the library
+    pub fn remove(&self, url: &str) -> Result<bool> {
+        let _lock = self.lock()?;
+        let mut entries = self.load();
+        let Some(entry) = entries.remove(url) else {
+            return Ok(false);
+        };
+
+        self.delete_file(&entry);
+        self.save(&entries)?;
+        self.logger.log_info(&format!("Removed {} from the library", url));
+        Ok(true)
+    }
+
+    /// Drops least recently used entries until the library fits its size cap
+    fn evict(&self, entries: &mut HashMap<String, LibraryEntry>, keep: &str) {
+        let max_bytes = self.config.max_size_mb * 1024 * 1024;
+        let mut total: u64 = entries.values().map(|entry| entry.size_bytes).sum();
+
+        while total > max_bytes {
+            let Some(oldest) = entries
+                .values()
+                .filter(|entry| entry.url != keep)
+                .min_by_key(|entry| entry.last_used())
+                .map(|entry| entry.url.clone())
+            else {
+                self.logger.log_warning("Library is over its size cap but nothing else can be evicted");
+                break;
+            };
+
+            if let Some(entry) = entries.remove(&oldest) {
+                self.logger.log_info(&format!("Evicting {} from the library", entry.url));
+                self.delete_file(&entry);
+                total -= entry.size_bytes;
+            }
+        }
+    }
+
+    fn delete_file(&self, entry: &LibraryEntry) {
+        if let Err(e) = fs::remove_file(self.dir.join(&entry.file_name)) {
+            self.logger
+                .log_warning(&format!("Failed to delete library file {}: {}", entry.file_name, e));
+        }
+    }
+
+    /// File name for a URL that stays the same across builds and Rust releases
+    fn file_stem(url: &str) -> String {
+        Sha256::digest(url)[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Locks the index against other processes until the handle is dropped
+    fn lock(&self) -> Result<File> {
+        fs::create_dir_all(&self.dir)?;
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join(LIBRARY_LOCK_FILE_NAME))?;
+        lock.lock()?;
+        Ok(lock)
+    }
+
+    fn load(&self) -> HashMap<String, LibraryEntry> {
+        fs::read_to_string(self.dir.join(LIBRARY_INDEX_FILE_NAME))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, entries: &HashMap<String, LibraryEntry>) -> Result<()> {
+        let path = self.dir.join(LIBRARY_INDEX_FILE_NAME);
+        let tmp_path = path.with_extension(format!("tmp.{}", std::process::id()));
+        fs::write(&tmp_path, serde_json::to_string_pretty(entries)?)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logger::LogConfig;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc-ytdlp-library-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn library(dir: &Path) -> MediaLibrary {
+        let config = LibraryConfig {
+            directory: dir.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        MediaLibrary::new(&config, dir, Logger::with_config(dir.join("test.log"), LogConfig::default()))
+    }
+
+    fn entry(url: &str, file_name: &str) -> LibraryEntry {
+        LibraryEntry {
+            url: url.to_string(),
+            file_name: file_name.to_string(),
+            size_bytes: 1,
+            added_at: Utc::now(),
+            last_played: None,
+        }
+    }
+
+    #[test]
+    fn concurrent_updates_keep_every_change() {
+        let dir = test_dir("concurrent");
+        let entries = (0..16)
+            .map(|i| {
+                let url = format!("https://example.com/{}", i);
+                (url.clone(), entry(&url, &format!("{}.mp4", i)))
+            })
+            .collect();
+        library(&dir).save(&entries).unwrap();
+
+        // Separate instances stand in for separate processes
+        let writers: Vec<_> = (0..16)
+            .map(|i| {
+                let dir = dir.clone();
+                std::thread::spawn(move || library(&dir).touch(&format!("https://example.com/{}", i)).unwrap())
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert!(library(&dir).load().values().all(|entry| entry.last_played.is_some()));
+    }
+
+    #[test]
+    fn serves_only_indexed_files() {
+        let dir = test_dir("serve");
+        let library = library(&dir);
+        fs::write(dir.join("abc.mp4"), "video").unwrap();
+        fs::write(dir.join("other.mp4"), "video").unwrap();
+        library
+            .save(&HashMap::from([("https://example.com/v".to_string(), entry("https://example.com/v", "abc.mp4"))]))
+            .unwrap();
+
+        assert_eq!(library.file_path("abc.mp4"), Some(dir.join("abc.mp4")));
+        for name in [
+            "other.mp4",
+            LIBRARY_INDEX_FILE_NAME,
+            "",
+            "..",
+            "../abc.mp4",
+            "./abc.mp4",
+            "C:abc.mp4",
+            "C:\\abc.mp4",
+            "/abc.mp4",
+        ] {
+            assert_eq!(library.file_path(name), None, "{}", name);
+        }
+    }
+}
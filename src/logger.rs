@@ -1,16 +1,141 @@
+use std::collections::hash_map::DefaultHasher;
 use std::fs::{self, OpenOptions};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
 use std::io::Write;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use chrono::Local;
+use chrono::{Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::models::LogFormat;
+
+tokio::task_local! {
+    /// Invocation being handled by the current task, for processes serving several
+    static INVOCATION_ID: Arc<str>;
+}
+
+/// Severity of a log entry, most severe first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    #[serde(alias = "warning")]
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            other => Err(format!("unknown log level: {}", other)),
+        }
+    }
+}
+
+impl Level {
+    /// Label used in text logs
+    fn label(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARNING",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// Minimum levels written, overall and per module
+#[derive(Debug, Clone, Default)]
+pub struct LevelFilter {
+    default: Level,
+    /// Overrides by module path; `resolver` also covers `resolver::ytdlp`
+    modules: Vec<(String, Level)>,
+}
+
+impl LevelFilter {
+    pub fn new(default: Level) -> Self {
+        Self {
+            default,
+            modules: Vec::new(),
+        }
+    }
+
+    /// Sets the minimum level for a module and its submodules
+    pub fn set_module(&mut self, module: &str, level: Level) {
+        self.modules.retain(|(name, _)| name != module);
+        self.modules.push((module.to_string(), level));
+    }
+
+    /// Applies directives like `debug` or `info,executor=trace,resolver=debug`.
+    ///
+    /// Valid directives are applied even when others are rejected.
+    pub fn apply_directives(&mut self, directives: &str) -> std::result::Result<(), String> {
+        let mut invalid = Vec::new();
+        for directive in directives.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let applied = match directive.split_once('=') {
+                Some((module, level)) => level.parse().map(|level| self.set_module(module.trim(), level)),
+                None => directive.parse().map(|level| self.default = level),
+            };
+            if applied.is_err() {
+                invalid.push(directive);
+            }
+        }
+
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("ignored invalid log directives: {}", invalid.join(", ")))
+        }
+    }
+
+    /// Lowers every minimum so at least `level` is written everywhere
+    pub fn at_least(&self, level: Level) -> Self {
+        Self {
+            default: self.default.max(level),
+            modules: self.modules.iter().map(|(module, l)| (module.clone(), (*l).max(level))).collect(),
+        }
+    }
+
+    /// Minimum level for a module; the most specific override wins
+    fn level_for(&self, module: &str) -> Level {
+        self.modules
+            .iter()
+            .filter(|(name, _)| {
+                module
+                    .strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(name, _)| name.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+}
 
 /// Configuration for log rotation
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct LogConfig {
     /// Maximum size of a log file in bytes before rotation (default: 10MB)
     pub max_file_size: u64,
     /// Maximum number of archived log files to keep (default: 5)
     pub max_archived_logs: u32,
+    /// Layout of each entry (default: text)
+    pub format: LogFormat,
+    /// Minimum levels written (default: info)
+    pub filter: LevelFilter,
 }
 
 impl Default for LogConfig {
@@ -18,6 +143,8 @@ impl Default for LogConfig {
         Self {
             max_file_size: 10 * 1024 * 1024, // 10MB
             max_archived_logs: 5,
+            format: LogFormat::Text,
+            filter: LevelFilter::default(),
         }
     }
 }
@@ -27,14 +154,23 @@ impl From<&crate::models::LoggingConfig> for LogConfig {
         Self {
             max_file_size: (config.max_file_size_mb as u64) * 1024 * 1024,
             max_archived_logs: config.max_archived_logs,
+            format: config.format,
+            filter: config.level_filter(),
         }
     }
 }
 
 /// Logger for writing messages to a log file with rotation support
+#[derive(Clone)]
 pub struct Logger {
     log_path: PathBuf,
-    config: LogConfig,
+    config: Arc<LogConfig>,
+    /// Minimum level written for this logger's module
+    level: Level,
+    /// Module entries are attributed to, e.g. `executor` or `resolver::ytdlp`
+    module: &'static str,
+    /// Short ID shared by the entries of one invocation, unless the task has its own
+    invocation_id: Option<Arc<str>>,
 }
 
 impl Logger {
@@ -42,21 +178,90 @@ impl Logger {
 
     /// Creates a new logger instance with custom configuration
     pub fn with_config(log_path: PathBuf, config: LogConfig) -> Self {
-        Self { log_path, config }
+        let module = "main";
+        Self {
+            log_path,
+            level: config.filter.level_for(module),
+            config: Arc::new(config),
+            module,
+            invocation_id: None,
+        }
+    }
+
+    /// A logger attributing its entries to a module; pass `module_path!()`
+    pub fn for_module(&self, module: &'static str) -> Self {
+        // The crate name is the same for every entry
+        let module = module.split_once("::").map_or(module, |(_, module)| module);
+        Self {
+            module,
+            level: self.config.filter.level_for(module),
+            ..self.clone()
+        }
     }
 
-    /// Logs a message to the log file
-    pub fn log(&self, message: &str) {
-        self.write_log_entry(message);
+    /// A logger, and the loggers derived from it, writing at least `level` in every module
+    pub fn with_level_at_least(&self, level: Level) -> Self {
+        let config = LogConfig {
+            filter: self.config.filter.at_least(level),
+            ..(*self.config).clone()
+        };
+        Self {
+            level: config.filter.level_for(self.module),
+            config: Arc::new(config),
+            ..self.clone()
+        }
+    }
+
+    /// Whether entries of a level are written, to skip building expensive messages
+    pub fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    /// A logger tagging its entries with an invocation ID
+    pub fn with_invocation_id(&self, id: &str) -> Self {
+        Self {
+            invocation_id: Some(Arc::from(id)),
+            ..self.clone()
+        }
+    }
+
+    /// Generates a short ID that is unique enough to tell invocations apart in one log
+    pub fn new_invocation_id() -> String {
+        let mut hasher = DefaultHasher::new();
+        (std::process::id(), std::time::SystemTime::now()).hash(&mut hasher);
+        format!("{:08x}", hasher.finish() as u32)
+    }
+
+    /// Runs a future with every entry it logs, through any logger, tagged with `id`.
+    ///
+    /// The daemon handles each request this way, since its loggers are shared between requests.
+    pub async fn scope_invocation<F: Future>(id: &str, future: F) -> F::Output {
+        INVOCATION_ID.scope(Arc::from(id), future).await
+    }
+
+    /// ID of the invocation entries are currently written for
+    pub fn invocation_id(&self) -> Option<Arc<str>> {
+        INVOCATION_ID
+            .try_with(Arc::clone)
+            .ok()
+            .or_else(|| self.invocation_id.clone())
+    }
+
+    /// Logs a message with structured fields, e.g. `("exit_code", json!(1))`.
+    ///
+    /// Fields only appear in the jsonl format; text entries show the message alone.
+    pub fn event(&self, level: Level, message: &str, fields: &[(&str, Value)]) {
+        if self.enabled(level) {
+            self.write_log_entry(Some(level), message, fields);
+        }
     }
 
     /// Internal method to write log entries
-    fn write_log_entry(&self, message: &str) {
+    fn write_log_entry(&self, level: Option<Level>, message: &str, fields: &[(&str, Value)]) {
         // Check if rotation is needed before writing
         self.rotate_if_needed();
 
-        let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
-        let formatted_message = format!("[{}] {}\n", timestamp, message);
+        let formatted_message = self.format_entry(level, message, fields);
 
         if let Ok(mut file) = OpenOptions::new()
             .create(true)
@@ -115,10 +320,46 @@ impl Logger {
         self.log_path.with_file_name(archived_name)
     }
 
+    /// Formats one entry, including the trailing newline
+    fn format_entry(&self, level: Option<Level>, message: &str, fields: &[(&str, Value)]) -> String {
+        let invocation_id = self.invocation_id();
+        match self.config.format {
+            LogFormat::Text => {
+                let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+                // Lines of concurrent shims interleave, so each names its invocation and process
+                let source = match &invocation_id {
+                    Some(id) => format!("{} {}", id, std::process::id()),
+                    None => std::process::id().to_string(),
+                };
+                match level {
+                    Some(level) => format!("[{}] [{}] {}: {}\n", timestamp, source, level.label(), message),
+                    None => format!("[{}] [{}] {}\n", timestamp, source, message),
+                }
+            }
+            LogFormat::Jsonl => {
+                let mut entry = serde_json::json!({
+                    "timestamp": Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+                    "level": level.unwrap_or(Level::Info),
+                    "pid": std::process::id(),
+                    "invocation_id": invocation_id.as_deref(),
+                    "module": self.module,
+                    "message": message,
+                });
+                if !fields.is_empty() {
+                    let fields = fields
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.clone()))
+                        .collect::<serde_json::Map<_, _>>();
+                    entry["fields"] = Value::Object(fields);
+                }
+                format!("{}\n", entry)
+            }
+        }
+    }
+
     /// Internal logging method that doesn't trigger rotation (to avoid infinite recursion)
     fn log_internal(&self, message: &str) {
-        let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
-        let formatted_message = format!("[{}] {}\n", timestamp, message);
+        let formatted_message = self.format_entry(None, message, &[]);
 
         if let Ok(mut file) = OpenOptions::new()
             .create(true)
@@ -132,22 +373,27 @@ impl Logger {
 
     /// Logs an error message
     pub fn log_error(&self, error: &str) {
-        self.log(&format!("ERROR: {}", error));
+        self.event(Level::Error, error, &[]);
     }
 
     /// Logs an info message
     pub fn log_info(&self, info: &str) {
-        self.log(&format!("INFO: {}", info));
+        self.event(Level::Info, info, &[]);
     }
 
     /// Logs debug information
     pub fn log_debug(&self, debug: &str) {
-        self.log(&format!("DEBUG: {}", debug));
+        self.event(Level::Debug, debug, &[]);
     }
 
     /// Logs a warning message
     pub fn log_warning(&self, warning: &str) {
-        self.log(&format!("WARNING: {}", warning));
+        self.event(Level::Warn, warning, &[]);
+    }
+
+    /// Logs fine-grained detail, such as each line a tool prints
+    pub fn log_trace(&self, trace: &str) {
+        self.event(Level::Trace, trace, &[]);
     }
 
     /// Gets the current log file size in bytes
@@ -210,3 +456,62 @@ impl LogInfo {
         self.current_size >= threshold
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directives_set_default_and_modules() {
+        let mut filter = LevelFilter::default();
+        filter.apply_directives("warn, executor=trace,resolver=debug").unwrap();
+
+        assert_eq!(filter.level_for("service"), Level::Warn);
+        assert_eq!(filter.level_for("executor"), Level::Trace);
+        assert_eq!(filter.level_for("resolver"), Level::Debug);
+    }
+
+    #[test]
+    fn invalid_directives_do_not_block_valid_ones() {
+        let mut filter = LevelFilter::new(Level::Info);
+        let error = filter.apply_directives("debug,relay=loud,bogus,cache=error").unwrap_err();
+
+        assert!(error.contains("relay=loud"));
+        assert!(error.contains("bogus"));
+        assert_eq!(filter.level_for("service"), Level::Debug);
+        assert_eq!(filter.level_for("cache"), Level::Error);
+        assert_eq!(filter.level_for("relay"), Level::Debug);
+    }
+
+    #[test]
+    fn most_specific_module_wins() {
+        let mut filter = LevelFilter::new(Level::Info);
+        filter.apply_directives("resolver=debug,resolver::ytdlp=trace").unwrap();
+
+        assert_eq!(filter.level_for("resolver"), Level::Debug);
+        assert_eq!(filter.level_for("resolver::cache"), Level::Debug);
+        assert_eq!(filter.level_for("resolver::ytdlp"), Level::Trace);
+        // A shared prefix is not a parent module
+        assert_eq!(filter.level_for("resolvers"), Level::Info);
+    }
+
+    #[test]
+    fn later_directives_replace_earlier_ones() {
+        let mut filter = LevelFilter::new(Level::Info);
+        filter.apply_directives("relay=trace").unwrap();
+        filter.apply_directives("relay=warn").unwrap();
+
+        assert_eq!(filter.level_for("relay"), Level::Warn);
+    }
+
+    #[test]
+    fn at_least_only_raises_verbosity() {
+        let mut filter = LevelFilter::new(Level::Warn);
+        filter.apply_directives("executor=trace,cache=error").unwrap();
+        let filter = filter.at_least(Level::Debug);
+
+        assert_eq!(filter.level_for("service"), Level::Debug);
+        assert_eq!(filter.level_for("executor"), Level::Trace);
+        assert_eq!(filter.level_for("cache"), Level::Debug);
+    }
+}
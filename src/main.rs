@@ -1,21 +1,50 @@
 use std::env;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 mod args;
+mod cache;
 mod config;
 mod constants;
+mod daemon;
+mod deadline;
 mod downloader;
 mod error;
 mod executor;
+mod format;
+mod history;
+mod library;
 mod logger;
+mod media_server;
 mod models;
+mod output;
+mod passthrough;
+mod player;
+mod policy;
+mod prefetch;
+mod probe;
+mod relay;
+mod replay;
+mod resolver;
+mod rewrite;
+mod rules;
+mod service;
 
-use args::ArgumentParser;
 use config::ConfigManager;
-use downloader::Downloader;
+use daemon::{Daemon, DaemonClient, DaemonRequest};
 use error::Result;
-use executor::Executor;
+use cache::ResolutionCache;
+use library::MediaLibrary;
 use logger::{LogConfig, Logger};
+use history::{HistoryQuery, HistoryRecord, HistoryStats, HistoryStore};
+use media_server::MediaServer;
+use prefetch::PrefetchQueue;
+use relay::HlsRelay;
+use replay::{ArgDiff, Replay};
+use resolver::{ResolverContext, YtDlpResolver};
+use rewrite::UrlRewriter;
+use service::ResolveService;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -26,8 +55,15 @@ async fn main() -> Result<()> {
     let app_config = config_manager.load_config()?;
 
     // Create logger with configuration from app config
-    let log_config = LogConfig::from(&app_config.logging);
-    let logger = Logger::with_config(runtime_config.log_path.clone(), log_config);
+    let mut log_config = LogConfig::from(&app_config.logging);
+    let env_levels = env::var(constants::LOG_LEVEL_ENV_VAR)
+        .map(|directives| log_config.filter.apply_directives(&directives))
+        .unwrap_or(Ok(()));
+    let logger = Logger::with_config(runtime_config.log_path.clone(), log_config)
+        .with_invocation_id(&Logger::new_invocation_id());
+    if let Err(e) = env_levels {
+        logger.log_warning(&format!("{}: {}", constants::LOG_LEVEL_ENV_VAR, e));
+    }
 
     // Log configuration information
     let log_info = logger.get_log_info();
@@ -42,47 +78,327 @@ async fn main() -> Result<()> {
 
     logger.log_info(&format!("yt-dlp location: {}", app_config.ytdlp_location));
 
-    // Get the full path to yt-dlp executable
-    let ytdlp_path = config_manager.get_ytdlp_path(&app_config, &runtime_config.app_dir);
-    logger.log_info(&format!("yt-dlp full path: {}", ytdlp_path.display()));
-
-    // Create downloader with same logger configuration
-    let downloader_logger = Logger::with_config(runtime_config.log_path.clone(), log_config);
-    let downloader = Downloader::new(ytdlp_path.clone(), downloader_logger);
-
-    // Ensure yt-dlp is available and up-to-date
-    if !downloader.executable_exists() {
-        logger.log_info(&format!("{} not found, downloading...", ytdlp_path.display()));
-        downloader.download_latest().await?;
-    } else {
-        if let Err(e) = downloader.check_and_update().await {
-            logger.log_error(&format!("Failed to check for updates: {}", e));
-            // Continue anyway, we have a working version
+    let context = ResolverContext {
+        app_dir: runtime_config.app_dir.clone(),
+        ytdlp_path: config_manager.get_ytdlp_path(&app_config, &runtime_config.app_dir),
+        streamlink_path: config_manager.get_streamlink_path(&app_config, &runtime_config.app_dir),
+        concurrent: false,
+        cache: Arc::new(ResolutionCache::new(runtime_config.app_dir.clone(), app_config.cache.ttl_secs)),
+        logger: logger.clone(),
+    };
+
+    match runtime_config.subcommand() {
+        Some("daemon") => {
+            let context = ResolverContext { concurrent: true, ..context };
+            let daemon_config = app_config.clone();
+            let service = ResolveService::new(app_config, context)?;
+            return Daemon::new(service, &daemon_config, &runtime_config.app_dir, logger).run().await;
+        }
+        Some("prefetch") => {
+            let urls = runtime_config.yt_dlp_args[1..].to_vec();
+            return prefetch(urls, app_config, context, &runtime_config.app_dir).await;
+        }
+        Some("library") => return library(&runtime_config.yt_dlp_args[1..], &app_config, &context).await,
+        Some("history") => return history(&runtime_config.yt_dlp_args[1..], &app_config, &context),
+        Some("stats") => return stats(&runtime_config.yt_dlp_args[1..], &app_config, &context),
+        Some("replay") => return replay(&runtime_config.yt_dlp_args[1..], &app_config, &context).await,
+        Some("serve") => {
+            let idle = Duration::from_secs(app_config.library.server_idle_secs);
+            let mut server = MediaServer::new(&app_config.library, &runtime_config.app_dir, logger.clone())
+                .with_idle_timeout(idle);
+            if app_config.relay.enabled {
+                // Re-resolutions may overlap with shims resolving other requests
+                let context = ResolverContext { concurrent: true, ..context };
+                let service = Arc::new(ResolveService::new(app_config, context)?);
+                server = server.with_relay(HlsRelay::new(service, &runtime_config.app_dir, logger)?);
+            }
+            return server.run().await;
+        }
+        _ => {}
+    }
+
+    // Hand the request to a resident daemon if one is running
+    if app_config.daemon.enabled {
+        let client = DaemonClient::new(&app_config.daemon, &runtime_config.app_dir);
+        let request = DaemonRequest::Resolve {
+            args: runtime_config.yt_dlp_args.clone(),
+            invocation_id: logger.invocation_id().map(|id| id.to_string()),
+        };
+        let reply_timeout = Duration::from_secs(app_config.execution.deadline_secs + 5);
+
+        match client.send(&request, reply_timeout).await {
+            Ok(Some(response)) => {
+                logger.log_debug("Request served by daemon");
+                return print_output(response.into_result());
+            }
+            Ok(None) => logger.log_debug("Daemon not running; resolving in-process"),
+            Err(e) => {
+                logger.log_error(&format!("Daemon failed to serve the request: {}", e));
+                return Err(e);
+            }
         }
     }
 
-    // Build complete argument list for yt-dlp
-    let yt_dlp_args = if app_config.logging.debug_enabled {
-        ArgumentParser::filter_arguments_with_logger(&runtime_config.yt_dlp_args, &app_config, Some(&logger))
-    } else {
-        ArgumentParser::filter_arguments(&runtime_config.yt_dlp_args, &app_config)
+    let service = ResolveService::new(app_config, context)?;
+    print_output(service.handle(&runtime_config.yt_dlp_args).await)
+}
+
+/// Queues URLs on the daemon, or resolves them right away when no daemon is running
+async fn prefetch(urls: Vec<String>, app_config: models::AppConfig, context: ResolverContext, app_dir: &Path) -> Result<()> {
+    if urls.is_empty() {
+        return Err(error::AppError::Config("Usage: prefetch <url>...".to_string()));
+    }
+
+    if app_config.daemon.enabled {
+        let client = DaemonClient::new(&app_config.daemon, app_dir);
+        let request = DaemonRequest::Prefetch { urls: urls.clone() };
+        if let Some(response) = client.send(&request, Duration::from_secs(5)).await? {
+            return print_output(response.into_result());
+        }
+    }
+
+    let logger = context.logger.clone();
+    let service = Arc::new(ResolveService::new(app_config, context)?);
+    let resolved = PrefetchQueue::new(service, logger).prefetch_all(&urls).await;
+    println!("Prefetched {} of {} URL(s)", resolved, urls.len());
+    Ok(())
+}
+
+/// Manages the local media library: `library add|remove <url>...` and `library list`
+async fn library(args: &[String], app_config: &models::AppConfig, context: &ResolverContext) -> Result<()> {
+    let logger = context.logger.clone();
+    let library = MediaLibrary::new(&app_config.library, &context.app_dir, logger.clone());
+    // Entries are keyed like requests, so links are normalised the same way
+    let rewriter = UrlRewriter::new(&app_config.rewrite, logger.clone())?;
+
+    let urls = args.get(1..).unwrap_or_default();
+    match args.first().map(String::as_str) {
+        Some("add") if !urls.is_empty() => {
+            let ytdlp = YtDlpResolver::new(app_config.clone(), context);
+            for url in urls {
+                match library.add(&rewriter.rewrite(url), &ytdlp).await {
+                    Ok(entry) => println!("Added {} ({} MB)", entry.url, entry.size_bytes / (1024 * 1024)),
+                    Err(e) => println!("Failed to add {}: {}", url, e),
+                }
+            }
+        }
+        Some("remove") if !urls.is_empty() => {
+            for url in urls {
+                let url = rewriter.rewrite(url);
+                if library.remove(&url)? {
+                    println!("Removed {}", url);
+                } else {
+                    println!("Not in library: {}", url);
+                }
+            }
+        }
+        Some("list") => {
+            let entries = library.list();
+            for entry in &entries {
+                println!(
+                    "{:>8.1} MB  {}  {}",
+                    entry.size_bytes as f64 / (1024.0 * 1024.0),
+                    entry.last_used().format("%Y-%m-%d %H:%M"),
+                    entry.url
+                );
+            }
+            let total: u64 = entries.iter().map(|entry| entry.size_bytes).sum();
+            println!(
+                "{} item(s), {:.1} of {} MB",
+                entries.len(),
+                total as f64 / (1024.0 * 1024.0),
+                app_config.library.max_size_mb
+            );
+        }
+        _ => {
+            return Err(error::AppError::Config(
+                "Usage: library add <url>... | library remove <url>... | library list".to_string(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists recent invocations: `history [--domain <host>] [--failed] [--since <age>] [--limit <n>] [--json]`
+fn history(args: &[String], app_config: &models::AppConfig, context: &ResolverContext) -> Result<()> {
+    let query = HistoryQuery::parse(args)
+        .map_err(|e| error::AppError::Config(format!("{}\nUsage: history {}", e, HistoryQuery::USAGE)))?;
+    let store = HistoryStore::new(&app_config.history, &context.app_dir, context.logger.clone());
+    let records = query.select(store.load());
+
+    if query.json {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+
+    println!("{:<19}  {:<8}  {:<11}  {:>7}  {:<11}  URL", "TIME", "ID", "RESULT", "MS", "RESOLVER");
+    for record in &records {
+        // Raw yt-dlp calls have no URL, so their arguments are shown instead
+        let target = record.canonical_url.clone().unwrap_or_else(|| record.args.join(" "));
+        println!(
+            "{:<19}  {:<8}  {:<11}  {:>7}  {:<11}  {}",
+            record.timestamp.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"),
+            record.invocation_id.as_deref().unwrap_or("-"),
+            record.failure_class.as_deref().unwrap_or(if record.success { "ok" } else { "failed" }),
+            record.total_ms,
+            record.resolver.as_deref().unwrap_or("-"),
+            target,
+        );
+    }
+    Ok(())
+}
+
+/// Summarises the history: `stats [--domain <host>] [--failed] [--since <age>] [--json]`
+fn stats(args: &[String], app_config: &models::AppConfig, context: &ResolverContext) -> Result<()> {
+    let query = HistoryQuery::parse(args)
+        .map_err(|e| error::AppError::Config(format!("{}\nUsage: stats {}", e, HistoryQuery::USAGE)))?;
+    let store = HistoryStore::new(&app_config.history, &context.app_dir, context.logger.clone());
+    let records: Vec<_> = store.load().into_iter().filter(|record| query.matches(record)).collect();
+    let stats = HistoryStats::compute(&records);
+
+    if query.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    let millis = |ms: Option<u64>| ms.map_or_else(|| "-".to_string(), |ms| ms.to_string());
+    println!(
+        "Requests: {} ({:.1}% succeeded)",
+        stats.overall.requests,
+        stats.overall.success_rate * 100.0
+    );
+    println!(
+        "Latency: p50 {} ms, p95 {} ms",
+        millis(stats.overall.p50_ms),
+        millis(stats.overall.p95_ms)
+    );
+    println!("Cache hit ratio: {:.1}%", stats.cache_hit_ratio * 100.0);
+
+    println!();
+    println!("{:<32}  {:>8}  {:>7}  {:>7}  {:>7}", "DOMAIN", "REQUESTS", "SUCCESS", "P50 MS", "P95 MS");
+    for domain in &stats.domains {
+        println!(
+            "{:<32}  {:>8}  {:>6.1}%  {:>7}  {:>7}",
+            domain.domain,
+            domain.stats.requests,
+            domain.stats.success_rate * 100.0,
+            millis(domain.stats.p50_ms),
+            millis(domain.stats.p95_ms)
+        );
+    }
+
+    if !stats.failure_classes.is_empty() {
+        println!();
+        println!("{:<16}  {:>5}", "FAILURE CLASS", "COUNT");
+        for failure in &stats.failure_classes {
+            println!("{:<16}  {:>5}", failure.class, failure.count);
+        }
+    }
+    Ok(())
+}
+
+/// Re-runs a recorded invocation: `replay <last|id|timestamp> [--recorded-version]`
+async fn replay(args: &[String], app_config: &models::AppConfig, context: &ResolverContext) -> Result<()> {
+    let recorded_version = args.iter().any(|arg| arg == "--recorded-version");
+    // Timestamps like `2024-05-01 21:04` arrive as two arguments when unquoted
+    let selector = args
+        .iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if selector.is_empty() {
+        return Err(error::AppError::Config(
+            "Usage: replay <last|id|timestamp> [--recorded-version]".to_string(),
+        ));
+    }
+
+    let store = HistoryStore::new(&app_config.history, &context.app_dir, context.logger.clone());
+    let then = Replay::find(store.load(), &selector)?;
+    println!(
+        "Replaying {} from {}",
+        then.requested_url.as_deref().unwrap_or("a raw yt-dlp call"),
+        then.timestamp.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S")
+    );
+
+    let replay = Replay::new(app_config, context.clone());
+    let (result, now) = replay.run(&then, None).await?;
+    let mut runs = vec![("THEN".to_string(), then.clone()), ("NOW".to_string(), now.clone())];
+    if recorded_version {
+        let path = replay.install_recorded_version(&then).await?;
+        let (_, pinned) = replay.run(&then, Some(path)).await?;
+        let label = format!("NOW @ {}", pinned.ytdlp_version.as_deref().unwrap_or("?"));
+        runs.push((label, pinned));
+    }
+
+    let field = |record: &HistoryRecord, name: &str| -> String {
+        let value = match name {
+            "result" => Some(
+                record
+                    .failure_class
+                    .clone()
+                    .unwrap_or_else(|| if record.success { "ok" } else { "failed" }.to_string()),
+            ),
+            "resolver" => record.resolver.clone(),
+            "strategy" => record.strategy.clone(),
+            "rule" => record.rule.clone(),
+            "player" => record.player.clone(),
+            "yt-dlp" => record.ytdlp_version.clone(),
+            "exit code" => record.exit_code.map(|code| code.to_string()),
+            "host" => record.resolved_host.clone(),
+            "total ms" => Some(record.total_ms.to_string()),
+            _ => None,
+        };
+        value.unwrap_or_else(|| "-".to_string())
     };
 
-    // Log the actual arguments that will be passed to yt-dlp
-    logger.log_info(&format!("Arguments: {:?}", yt_dlp_args));
+    println!();
+    print!("{:<10}", "");
+    for (label, _) in &runs {
+        print!("  {:<24}", label);
+    }
+    println!();
+    for name in ["result", "resolver", "strategy", "rule", "player", "yt-dlp", "exit code", "host", "total ms"] {
+        print!("{:<10}", name);
+        for (_, record) in &runs {
+            print!("  {:<24}", field(record, name));
+        }
+        println!();
+    }
+    for (label, record) in &runs {
+        if let Some(error) = &record.error {
+            println!("{} error: {}", label, error);
+        }
+    }
 
-    // Execute yt-dlp with process isolation
-    let executor = Executor::new(runtime_config.app_dir, logger);
-    let executable_path = downloader.get_executable_path();
-    let result = executor.execute(&executable_path, &yt_dlp_args);
+    println!();
+    println!("yt-dlp arguments:");
+    let width = then.ytdlp_args.iter().map(String::len).max().unwrap_or(0).max("THEN".len());
+    println!("  {:<width$} | NOW", "THEN", width = width);
+    for line in Replay::diff_args(&then.ytdlp_args, &now.ytdlp_args) {
+        match line {
+            ArgDiff::Same(arg) => println!("  {:<width$} | {}", arg, arg, width = width),
+            ArgDiff::Removed(arg) => println!("- {:<width$} |", arg, width = width),
+            ArgDiff::Added(arg) => println!("+ {:<width$} | {}", "", arg, width = width),
+        }
+    }
 
-    // Log completion
-    match &result {
-        Ok(_) => executor.logger.log_info("Success"),
-        Err(e) => executor.logger.log_error(&format!("Failed: {}", e)),
+    println!();
+    match result {
+        Ok(lines) => lines.iter().for_each(|line| println!("Output: {}", line)),
+        Err(e) => println!("Output: none ({})", e),
     }
+    println!("Debug log: {}", context.app_dir.join("logs.log").display());
+    Ok(())
+}
 
-    result
+/// Prints the cleaned output for VRChat
+fn print_output(result: Result<Vec<String>>) -> Result<()> {
+    for line in result? {
+        println!("{}", line);
+    }
+    Ok(())
 }
 
 /// Runtime configuration derived from environment
@@ -93,6 +409,14 @@ struct RuntimeConfig {
 }
 
 impl RuntimeConfig {
+    /// Subcommands are bare words that VRChat never passes as the first argument
+    fn subcommand(&self) -> Option<&str> {
+        self.yt_dlp_args
+            .first()
+            .map(String::as_str)
+            .filter(|arg| matches!(*arg, "daemon" | "prefetch" | "library" | "serve" | "history" | "stats" | "replay"))
+    }
+
     /// Creates configuration from environment
     fn from_env() -> Result<Self> {
         let args: Vec<String> = env::args().collect();
@@ -0,0 +1,337 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures_util::TryStreamExt;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::io::ReaderStream;
+
+use crate::error::{AppError, Result};
+use crate::library::MediaLibrary;
+use crate::logger::Logger;
+use crate::models::LibraryConfig;
+use crate::relay::HlsRelay;
+
+pub(crate) type Body = BoxBody<Bytes, std::io::Error>;
+
+/// Small HTTP server on 127.0.0.1 that lets VRChat play files from the media library.
+///
+/// Runs inside the daemon, or as a `serve` helper started on demand by the shim.
+/// With a relay attached it also serves relayed HLS streams under `/hls/`.
+pub struct MediaServer {
+    library: Arc<MediaLibrary>,
+    relay: Option<Arc<HlsRelay>>,
+    port: u16,
+    /// Exit after this long without requests; only set for the on-demand helper
+    idle_timeout: Option<Duration>,
+    logger: Logger,
+}
+
+impl MediaServer {
+    pub fn new(config: &LibraryConfig, app_dir: &Path, logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
+        Self {
+            library: Arc::new(MediaLibrary::new(config, app_dir, logger.clone())),
+            relay: None,
+            port: config.server_port,
+            idle_timeout: None,
+            logger,
+        }
+    }
+
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn with_relay(mut self, relay: HlsRelay) -> Self {
+        self.relay = Some(Arc::new(relay));
+        self
+    }
+
+    /// URL under which VRChat can fetch a library file
+    pub fn url_for(port: u16, file_name: &str) -> String {
+        format!("http://127.0.0.1:{}/media/{}", port, file_name)
+    }
+
+    /// Makes sure a server is listening on the port, starting the `serve` helper if not
+    pub async fn ensure_running(port: u16, logger: &Logger) -> Result<()> {
+        if Self::is_listening(port).await {
+            return Ok(());
+        }
+
+        let exe = std::env::current_exe()?;
+        logger.log_info(&format!("Starting media server on port {}", port));
+
+        let mut cmd = std::process::Command::new(exe);
+        // Inherited pipes would keep VRChat waiting on our stdout after we exit
+        cmd.arg("serve")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            const DETACHED_PROCESS: u32 = 0x0000_0008;
+            const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+            cmd.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
+        cmd.spawn()
+            .map_err(|e| AppError::Execution(format!("Failed to start the media server: {}", e)))?;
+
+        for _ in 0..20 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if Self::is_listening(port).await {
+                return Ok(());
+            }
+        }
+
+        Err(AppError::Execution(format!("Media server did not start on port {}", port)))
+    }
+
+    async fn is_listening(port: u16) -> bool {
+        let connect = TcpStream::connect(SocketAddr::from(([127, 0, 0, 1], port)));
+        matches!(tokio::time::timeout(Duration::from_millis(200), connect).await, Ok(Ok(_)))
+    }
+
+    /// Serves requests until the process is stopped or the idle timeout passes
+    pub async fn run(self) -> Result<()> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], self.port))).await?;
+        self.logger.log_info(&format!("Media server listening on 127.0.0.1:{}", self.port));
+
+        let started = Instant::now();
+        let last_request = Arc::new(AtomicU64::new(0));
+
+        loop {
+            let idle_check = async {
+                match self.idle_timeout {
+                    Some(timeout) => tokio::time::sleep(timeout / 10).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            let stream = tokio::select! {
+                accepted = listener.accept() => accepted?.0,
+                _ = idle_check => {
+                    let idle = started.elapsed().saturating_sub(Duration::from_secs(last_request.load(Ordering::Relaxed)));
+                    if self.idle_timeout.is_some_and(|timeout| idle >= timeout) {
+                        self.logger.log_info("Media server idle, shutting down");
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+
+            let library = self.library.clone();
+            let relay = self.relay.clone();
+            let last_request = last_request.clone();
+            let logger = self.logger.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    last_request.store(started.elapsed().as_secs(), Ordering::Relaxed);
+                    // Requests are logged under their own ID, as the server's logger is shared
+                    let served = serve(request, library.clone(), relay.clone(), logger.clone());
+                    async move { Logger::scope_invocation(&Logger::new_invocation_id(), served).await }
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    }
+}
+
+/// Routes `/hls/` to the relay and everything else to the library files
+async fn serve(
+    request: Request<Incoming>,
+    library: Arc<MediaLibrary>,
+    relay: Option<Arc<HlsRelay>>,
+    logger: Logger,
+) -> std::result::Result<Response<Body>, Infallible> {
+    let response = match relay.filter(|_| request.uri().path().starts_with("/hls/")) {
+        Some(relay) => relay.respond(&request).await.unwrap_or_else(|e| {
+            logger.log_warning(&format!("Relay failed to serve {}: {}", request.uri(), e));
+            status(StatusCode::BAD_GATEWAY)
+        }),
+        None => respond(&request, &library).await.unwrap_or_else(|e| {
+            logger.log_warning(&format!("Media server failed to serve {}: {}", request.uri(), e));
+            status(StatusCode::INTERNAL_SERVER_ERROR)
+        }),
+    };
+
+    logger.log_trace(&format!(
+        "Media server: {} {} -> {}",
+        request.method(),
+        request.uri(),
+        response.status()
+    ));
+    Ok(response)
+}
+
+/// Serves `GET`/`HEAD /media/<file>` for files in the library index, honouring `Range`
+async fn respond(request: &Request<Incoming>, library: &MediaLibrary) -> std::io::Result<Response<Body>> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    // Only files the library downloaded, so requests can't escape the library directory
+    let Some(file_name) = request.uri().path().strip_prefix("/media/") else {
+        return Ok(status(StatusCode::NOT_FOUND));
+    };
+    let Some(path) = library.file_path(file_name) else {
+        return Ok(status(StatusCode::NOT_FOUND));
+    };
+    let Ok(mut file) = tokio::fs::File::open(&path).await else {
+        return Ok(status(StatusCode::NOT_FOUND));
+    };
+    let len = file.metadata().await?.len();
+
+    let range = request
+        .headers()
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| parse_range(value, len));
+
+    let (code, start, end) = match range {
+        None | Some(RangeRequest::Ignored) => (StatusCode::OK, 0, len.saturating_sub(1)),
+        Some(RangeRequest::Satisfiable(start, end)) => (StatusCode::PARTIAL_CONTENT, start, end),
+        Some(RangeRequest::Unsatisfiable) => {
+            let mut response = status(StatusCode::RANGE_NOT_SATISFIABLE);
+            response
+                .headers_mut()
+                .insert(CONTENT_RANGE, header(&format!("bytes */{}", len)));
+            return Ok(response);
+        }
+    };
+    let length = if len == 0 { 0 } else { end - start + 1 };
+
+    let body = if request.method() == Method::HEAD || length == 0 {
+        Empty::new().map_err(|never| match never {}).boxed()
+    } else {
+        file.seek(std::io::SeekFrom::Start(start)).await?;
+        let stream = ReaderStream::new(file.take(length)).map_ok(Frame::data);
+        BodyExt::boxed(StreamBody::new(stream))
+    };
+
+    let mut response = Response::new(body);
+    *response.status_mut() = code;
+    let headers = response.headers_mut();
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(CONTENT_LENGTH, header(&length.to_string()));
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type(file_name)));
+    if code == StatusCode::PARTIAL_CONTENT {
+        headers.insert(CONTENT_RANGE, header(&format!("bytes {}-{}/{}", start, end, len)));
+    }
+    Ok(response)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RangeRequest {
+    Satisfiable(u64, u64),
+    Unsatisfiable,
+    /// Malformed or multi-range requests get the whole file
+    Ignored,
+}
+
+/// Parses a single `bytes=` range: `a-b`, `a-` or the suffix form `-n`
+fn parse_range(value: &str, len: u64) -> RangeRequest {
+    let Some(spec) = value.trim().strip_prefix("bytes=").filter(|spec| !spec.contains(',')) else {
+        return RangeRequest::Ignored;
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return RangeRequest::Ignored;
+    };
+
+    let (start, end) = match (start.trim().parse::<u64>().ok(), end.trim().parse::<u64>().ok()) {
+        (Some(start), Some(end)) => (start, end.min(len.saturating_sub(1))),
+        (Some(start), None) if end.trim().is_empty() => (start, len.saturating_sub(1)),
+        (None, Some(suffix)) if start.trim().is_empty() => (len.saturating_sub(suffix), len.saturating_sub(1)),
+        _ => return RangeRequest::Ignored,
+    };
+
+    if len == 0 || start >= len || start > end {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Satisfiable(start, end)
+    }
+}
+
+fn content_type(file_name: &str) -> &'static str {
+    let extension = Path::new(file_name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "m4a" => "audio/mp4",
+        "mp3" => "audio/mpeg",
+        "ogg" | "opus" => "audio/ogg",
+        _ => "application/octet-stream",
+    }
+}
+
+pub(crate) fn status(code: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Full::new(Bytes::new()).map_err(|never| match never {}).boxed());
+    *response.status_mut() = code;
+    response
+}
+
+fn header(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).unwrap_or_else(|_| HeaderValue::from_static(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), RangeRequest::Satisfiable(0, 99));
+        assert_eq!(parse_range("bytes=500-", 1000), RangeRequest::Satisfiable(500, 999));
+        assert_eq!(parse_range("bytes=-100", 1000), RangeRequest::Satisfiable(900, 999));
+        assert_eq!(parse_range(" bytes= 10 - 20 ", 1000), RangeRequest::Satisfiable(10, 20));
+    }
+
+    #[test]
+    fn clamps_ranges_past_the_end() {
+        assert_eq!(parse_range("bytes=900-5000", 1000), RangeRequest::Satisfiable(900, 999));
+        assert_eq!(parse_range("bytes=-5000", 1000), RangeRequest::Satisfiable(0, 999));
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=20-10", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_malformed_and_multi_ranges() {
+        for value in ["bytes=0-1,5-9", "items=0-1", "bytes=abc", "bytes=a-b", "bytes=-", "0-99"] {
+            assert_eq!(parse_range(value, 1000), RangeRequest::Ignored, "{}", value);
+        }
+    }
+}
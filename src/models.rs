@@ -1,6 +1,11 @@
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::error::FailureClass;
+use crate::logger::{Level, LevelFilter};
+
 /// Application configuration loaded from config.json
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AppConfig {
@@ -11,6 +16,57 @@ pub struct AppConfig {
     pub cookies_browser: String,
     #[serde(default)]
     pub logging: LoggingConfig,
+    #[serde(default)]
+    pub execution: ExecutionConfig,
+    #[serde(default)]
+    pub rules: Vec<DomainRule>,
+    #[serde(default)]
+    pub probe: ProbeConfig,
+    /// Alternative argument sets tried in order when the default `custom_args` fail
+    #[serde(default)]
+    pub fallback_strategies: Vec<StrategyConfig>,
+    #[serde(default)]
+    pub passthrough: PassthroughConfig,
+    /// Resolvers tried in order for every request
+    #[serde(default = "default_resolvers")]
+    pub resolvers: Vec<String>,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub scripted: ScriptedConfig,
+    #[serde(default)]
+    pub streamlink: StreamlinkConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub prefetch: PrefetchConfig,
+    #[serde(default)]
+    pub quality: QualityConfig,
+    #[serde(default)]
+    pub players: PlayersConfig,
+    #[serde(default)]
+    pub rewrite: RewriteConfig,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    #[serde(default)]
+    pub content_policy: ContentPolicyConfig,
+    #[serde(default)]
+    pub placeholders: PlaceholderConfig,
+    #[serde(default)]
+    pub library: LibraryConfig,
+    #[serde(default)]
+    pub relay: RelayConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+}
+
+fn default_resolvers() -> Vec<String> {
+    vec![
+        "passthrough".to_string(),
+        "library".to_string(),
+        "cache".to_string(),
+        "yt-dlp".to_string(),
+    ]
 }
 
 /// Logging configuration
@@ -20,8 +76,29 @@ pub struct LoggingConfig {
     pub max_file_size_mb: u32,
     /// Maximum number of archived log files to keep (default: 5)
     pub max_archived_logs: u32,
-    /// Enable debug logging (default: false)
+    /// Enable debug logging when no level is set (default: false)
     pub debug_enabled: bool,
+    /// Minimum level written: "error", "warn", "info", "debug" or "trace"
+    /// (default: "debug" with debug_enabled, otherwise "info")
+    #[serde(default)]
+    pub level: Option<Level>,
+    /// Minimum levels for single modules, e.g. {"executor": "trace", "resolver": "debug"} (default: none)
+    #[serde(default)]
+    pub modules: BTreeMap<String, Level>,
+    /// Entry layout: "text" lines or "jsonl" objects with structured fields (default: "text")
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+/// Layout of log entries
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `[timestamp] LEVEL: message`
+    #[default]
+    Text,
+    /// One JSON object per line
+    Jsonl,
 }
 
 impl Default for LoggingConfig {
@@ -30,6 +107,603 @@ impl Default for LoggingConfig {
             max_file_size_mb: crate::constants::defaults::LOG_MAX_SIZE_MB,
             max_archived_logs: crate::constants::defaults::LOG_MAX_ARCHIVED,
             debug_enabled: false,
+            level: None,
+            modules: BTreeMap::new(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Minimum levels from the configuration, before any environment override
+    pub fn level_filter(&self) -> LevelFilter {
+        let default = self
+            .level
+            .unwrap_or(if self.debug_enabled { Level::Debug } else { Level::Info });
+        let mut filter = LevelFilter::new(default);
+        for (module, level) in &self.modules {
+            filter.set_module(module, *level);
+        }
+        filter
+    }
+}
+
+/// Process execution and deadline configuration
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ExecutionConfig {
+    /// Maximum time yt-dlp may run before it is terminated (default: 30s)
+    pub timeout_secs: u64,
+    /// Total time budget for a request, shared by the update check and execution (default: 60s)
+    pub deadline_secs: u64,
+    /// Time to wait after a graceful termination signal before killing the process tree (default: 2000ms)
+    pub termination_grace_ms: u64,
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: crate::constants::defaults::EXECUTION_TIMEOUT_SECS,
+            deadline_secs: crate::constants::defaults::DEADLINE_SECS,
+            termination_grace_ms: crate::constants::defaults::TERMINATION_GRACE_MS,
+        }
+    }
+}
+
+/// Per-domain overrides applied when the requested URL matches one of `domains`
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct DomainRule {
+    /// Name used in logs to identify the rule
+    pub name: String,
+    /// Host patterns, e.g. "twitch.tv" (includes subdomains) or "*.example.com"
+    pub domains: Vec<String>,
+    /// Overrides `execution.timeout_secs` for matching URLs
+    pub timeout_secs: Option<u64>,
+    /// Treat matching URLs as live streams and apply `quality.live` (default: false)
+    pub live: bool,
+    /// Request audio only for matching URLs, e.g. music or karaoke sites, even when
+    /// `custom_args` has its own -f (default: false)
+    pub audio_only: bool,
+}
+
+/// Health check of the resolved media URL before it is handed to VRChat
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ProbeConfig {
+    /// Probe the resolved URL with HEAD/ranged GET (default: false)
+    pub enabled: bool,
+    /// Maximum time a probe may take (default: 3000ms)
+    pub timeout_ms: u64,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout_ms: crate::constants::defaults::PROBE_TIMEOUT_MS,
+        }
+    }
+}
+
+/// A named set of yt-dlp arguments used in place of `custom_args`
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct StrategyConfig {
+    pub name: String,
+    pub custom_args: Vec<String>,
+}
+
+/// Direct media URLs that are echoed back without running yt-dlp
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PassthroughConfig {
+    /// Skip yt-dlp for direct media URLs (default: true)
+    pub enabled: bool,
+    /// Path suffixes treated as direct media
+    pub extensions: Vec<String>,
+    /// Also send a HEAD request to hosts yt-dlp has no extractor for and accept media Content-Types (default: false)
+    pub content_type_probe: bool,
+    /// Maximum time the Content-Type probe may take (default: 1500ms)
+    pub probe_timeout_ms: u64,
+    /// Hosts eligible for passthrough; empty allows all hosts
+    pub allowed_hosts: Vec<String>,
+    /// Hosts that always go through yt-dlp
+    pub blocked_hosts: Vec<String>,
+}
+
+impl Default for PassthroughConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            extensions: vec![
+                ".mp4".to_string(),
+                ".webm".to_string(),
+                ".m3u8".to_string(),
+                ".mpd".to_string(),
+            ],
+            content_type_probe: false,
+            probe_timeout_ms: crate::constants::defaults::PASSTHROUGH_PROBE_TIMEOUT_MS,
+            allowed_hosts: Vec::new(),
+            blocked_hosts: Vec::new(),
+        }
+    }
+}
+
+/// Resolution cache configuration
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Reuse recently resolved URLs (default: true)
+    pub enabled: bool,
+    /// How long a resolution stays valid unless the URL expires sooner (default: 600s)
+    pub ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl_secs: crate::constants::defaults::CACHE_TTL_SECS,
+        }
+    }
+}
+
+/// Canned responses for the scripted test resolver
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ScriptedConfig {
+    pub responses: Vec<ScriptedResponse>,
+}
+
+/// A canned response returned when the requested URL contains `pattern`
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ScriptedResponse {
+    pub pattern: String,
+    pub url: Option<String>,
+    pub error: Option<String>,
+    pub delay_ms: u64,
+}
+
+/// Streamlink backend for live-stream domains
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StreamlinkConfig {
+    /// Location of the streamlink executable, relative to the app directory unless absolute
+    pub location: String,
+    /// Download the portable build on first use and update it daily (default: true).
+    ///
+    /// The build is unpacked two levels above `location`, keeping its `bin/streamlink.exe`
+    /// layout; a streamlink placed there by hand is used as-is and never replaced.
+    pub managed: bool,
+    /// Host patterns handled by streamlink
+    pub domains: Vec<String>,
+    /// Argument template; `{url}`, `{quality}` and `{max_height}` are substituted
+    pub args: Vec<String>,
+    /// Stream name passed as `{quality}` (default: "best")
+    pub quality: String,
+    /// Let the next resolver (normally yt-dlp) try when streamlink fails (default: true)
+    pub fallback_to_ytdlp: bool,
+    /// Maximum time streamlink may run (default: 15s)
+    pub timeout_secs: u64,
+}
+
+impl Default for StreamlinkConfig {
+    fn default() -> Self {
+        Self {
+            location: "tools/streamlink/bin/streamlink.exe".to_string(),
+            managed: true,
+            domains: vec!["twitch.tv".to_string(), "kick.com".to_string()],
+            args: vec![
+                "--stream-url".to_string(),
+                "{url}".to_string(),
+                "{quality}".to_string(),
+            ],
+            quality: "best".to_string(),
+            fallback_to_ytdlp: true,
+            timeout_secs: crate::constants::defaults::STREAMLINK_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// Resident daemon that serves requests without per-request startup cost
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// Forward requests to a running daemon before resolving in-process (default: true)
+    pub enabled: bool,
+    /// Named pipe (Windows) or socket file (Unix) name (default: "vrc-ytdlp")
+    pub pipe_name: String,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            pipe_name: "vrc-ytdlp".to_string(),
+        }
+    }
+}
+
+/// Background resolution of upcoming URLs into the cache
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PrefetchConfig {
+    /// Queue file watched by the daemon, one URL per line (default: "prefetch.txt")
+    pub queue_file: String,
+    /// Watch the queue file while the daemon runs (default: true)
+    pub watch_queue_file: bool,
+    /// How often the queue file is re-read (default: 10s)
+    pub poll_interval_secs: u64,
+    /// Re-resolve cached entries expiring within this window (default: 120s)
+    pub refresh_margin_secs: u64,
+    /// Wait before retrying a URL that failed to resolve (default: 300s)
+    pub retry_after_secs: u64,
+    /// Stop refreshing a queued URL this long after it was first seen (default: 3h).
+    /// URLs are also dropped once history shows they were played.
+    pub max_age_secs: u64,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        Self {
+            queue_file: "prefetch.txt".to_string(),
+            watch_queue_file: true,
+            poll_interval_secs: crate::constants::defaults::PREFETCH_POLL_SECS,
+            refresh_margin_secs: crate::constants::defaults::PREFETCH_REFRESH_MARGIN_SECS,
+            retry_after_secs: crate::constants::defaults::PREFETCH_RETRY_SECS,
+            max_age_secs: crate::constants::defaults::PREFETCH_MAX_AGE_SECS,
+        }
+    }
+}
+
+/// How the media format is chosen
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum FormatSelection {
+    /// yt-dlp applies the `-f` selector from `custom_args`
+    #[serde(rename = "yt-dlp")]
+    YtDlp,
+    /// yt-dlp dumps the format list (`-J`) and the formats are ranked here
+    #[serde(rename = "builtin")]
+    Builtin,
+}
+
+/// Preferred delivery protocol for the built-in selector
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProtocolPreference {
+    Hls,
+    Progressive,
+    Any,
+}
+
+/// Structured quality settings.
+///
+/// With `selection: "yt-dlp"` they are compiled into a `-f` selector unless
+/// `custom_args` already carries one, in which case only the player profiles' constraints
+/// are applied on top of it; with `"builtin"` they drive the built-in selector.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct QualityConfig {
+    /// "yt-dlp" or "builtin" (default: "yt-dlp")
+    pub selection: FormatSelection,
+    /// Formats taller than this are never chosen (default: 1080)
+    pub max_height: Option<u32>,
+    /// Formats above this frame rate are never chosen (default: none)
+    pub max_fps: Option<u32>,
+    /// Video codec prefixes in order of preference; unlisted codecs rank last
+    pub preferred_codecs: Vec<String>,
+    /// "hls", "progressive" or "any" (default: "hls")
+    pub protocol: ProtocolPreference,
+    /// Never fall back to other protocols (default: false)
+    pub protocol_required: bool,
+    /// Request the best playable audio-only format, falling back to a small
+    /// combined format when the site has no standalone audio (default: false)
+    pub audio_only: bool,
+    /// Overrides applied to live streams
+    pub live: QualityOverrides,
+}
+
+impl QualityConfig {
+    /// Returns the settings with the live-stream overrides applied
+    pub fn for_live(&self) -> QualityConfig {
+        self.with_overrides(&self.live)
+    }
+
+    /// Returns the settings with the set fields of `overrides` replaced
+    pub fn with_overrides(&self, overrides: &QualityOverrides) -> QualityConfig {
+        let mut quality = self.clone();
+        if let Some(max_height) = overrides.max_height {
+            quality.max_height = Some(max_height);
+        }
+        if let Some(max_fps) = overrides.max_fps {
+            quality.max_fps = Some(max_fps);
+        }
+        if let Some(protocol) = overrides.protocol {
+            quality.protocol = protocol;
+        }
+        if let Some(required) = overrides.protocol_required {
+            quality.protocol_required = required;
+        }
+        quality
+    }
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        Self {
+            selection: FormatSelection::YtDlp,
+            max_height: Some(1080),
+            max_fps: None,
+            preferred_codecs: vec!["avc1".to_string(), "h264".to_string()],
+            protocol: ProtocolPreference::Hls,
+            protocol_required: false,
+            audio_only: false,
+            live: QualityOverrides::default(),
+        }
+    }
+}
+
+/// Quality settings replaced for a class of requests; unset fields keep the base value
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct QualityOverrides {
+    pub max_height: Option<u32>,
+    pub max_fps: Option<u32>,
+    pub protocol: Option<ProtocolPreference>,
+    pub protocol_required: Option<bool>,
+}
+
+/// Detection of the world's video player and the constraints applied for each
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PlayersConfig {
+    /// Profile used when the player can't be detected (default: "avpro")
+    pub default_profile: String,
+    /// Look up the requested URL in VRChat's output log to find the player (default: true)
+    pub detect_from_log: bool,
+    /// Directory holding VRChat's output_log_*.txt (default: the parent of the app directory)
+    pub vrchat_log_dir: Option<String>,
+    pub profiles: Vec<PlayerProfile>,
+}
+
+impl PlayersConfig {
+    pub fn profile(&self, name: &str) -> Option<&PlayerProfile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+}
+
+impl Default for PlayersConfig {
+    fn default() -> Self {
+        Self {
+            default_profile: "avpro".to_string(),
+            detect_from_log: true,
+            vrchat_log_dir: None,
+            profiles: vec![
+                PlayerProfile {
+                    name: "avpro".to_string(),
+                    arg_patterns: vec!["[protocol^=m3u8]".to_string()],
+                    log_markers: vec!["AVPro".to_string()],
+                    quality: QualityOverrides::default(),
+                },
+                PlayerProfile {
+                    name: "unity".to_string(),
+                    arg_patterns: vec!["[protocol^=http]".to_string(), "[protocol=https]".to_string()],
+                    log_markers: vec!["UnityVideoPlayer".to_string(), "Unity Video".to_string()],
+                    quality: QualityOverrides {
+                        max_height: Some(720),
+                        max_fps: None,
+                        // Unity's player can't play HLS at all
+                        protocol: Some(ProtocolPreference::Progressive),
+                        protocol_required: Some(true),
+                    },
+                },
+            ],
+        }
+    }
+}
+
+/// Format and protocol constraints for one video player
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct PlayerProfile {
+    pub name: String,
+    /// Substrings of VRChat's own arguments that identify this player
+    pub arg_patterns: Vec<String>,
+    /// Substrings of VRChat log lines near the request that identify this player
+    pub log_markers: Vec<String>,
+    /// Quality settings replaced for this player
+    pub quality: QualityOverrides,
+}
+
+/// URL rewrites applied before a request is resolved
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RewriteConfig {
+    /// Apply the built-in YouTube normalisation rules first (default: true)
+    pub builtin_rules: bool,
+    /// Additional rules applied in order after the built-in ones
+    pub rules: Vec<RewriteRule>,
+}
+
+impl Default for RewriteConfig {
+    fn default() -> Self {
+        Self {
+            builtin_rules: true,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// A regex substitution on the requested URL
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct RewriteRule {
+    /// Name used in logs to identify the rule
+    pub name: String,
+    /// Regular expression matched against the whole URL
+    pub pattern: String,
+    /// Replacement; `$1` or `${name}` insert capture groups
+    pub replacement: String,
+    /// Only rewrite URLs on these host patterns; empty means any host
+    pub hosts: Vec<String>,
+    /// Query parameters copied from the original URL onto the rewritten one
+    pub keep_params: Vec<String>,
+}
+
+/// What happens to URLs matching neither list of the domain policy
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    #[default]
+    Allow,
+    Block,
+}
+
+/// Which hosts the proxy will resolve URLs for
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct PolicyConfig {
+    /// "allow" or "block" for hosts on neither list (default: "allow")
+    pub default_action: PolicyAction,
+    /// Host patterns always allowed unless also blocked
+    pub allowed_hosts: Vec<String>,
+    /// Host patterns always refused; takes precedence over `allowed_hosts`
+    pub blocked_hosts: Vec<String>,
+}
+
+impl PolicyConfig {
+    /// Whether any host may be refused; inputs without a host are then refused too
+    pub fn is_restrictive(&self) -> bool {
+        self.default_action == PolicyAction::Block || !self.blocked_hosts.is_empty()
+    }
+}
+
+/// Rules checked against the video's metadata before a URL is returned
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ContentPolicyConfig {
+    /// Fetch yt-dlp's JSON metadata and check it before extracting; direct links,
+    /// library copies and cache hits under the same rules are served unchecked (default: false)
+    pub enabled: bool,
+    /// Refuse videos longer than this many minutes
+    pub max_duration_mins: Option<u64>,
+    /// Refuse live streams (default: false)
+    pub block_live: bool,
+    /// Refuse videos whose age limit is above this, e.g. 0 to refuse all age-restricted content
+    pub max_age_limit: Option<u32>,
+    /// Channel IDs whose videos are refused
+    pub blocked_channels: Vec<String>,
+    /// Uploader names or IDs whose videos are refused (case-insensitive)
+    pub blocked_uploaders: Vec<String>,
+}
+
+/// URLs handed to VRChat instead of an error, e.g. a short clip explaining the failure.
+/// Unset classes fail as before.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct PlaceholderConfig {
+    /// Played when a request is refused by policy
+    pub blocked: Option<String>,
+    /// Played when the video is private, removed or doesn't exist
+    pub unavailable: Option<String>,
+    /// Played when the site demands a sign-in or captcha to prove we aren't a bot
+    pub bot_check: Option<String>,
+    /// Played when resolution ran out of time
+    pub timeout: Option<String>,
+}
+
+impl PlaceholderConfig {
+    pub fn for_class(&self, class: FailureClass) -> Option<&String> {
+        match class {
+            FailureClass::Blocked => self.blocked.as_ref(),
+            FailureClass::Unavailable => self.unavailable.as_ref(),
+            FailureClass::BotCheck => self.bot_check.as_ref(),
+            FailureClass::Timeout => self.timeout.as_ref(),
+            FailureClass::Other => None,
+        }
+    }
+}
+
+/// Media downloaded ahead of time and served to VRChat from this machine
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LibraryConfig {
+    /// Directory holding the downloaded files, relative to the app directory (default: "library")
+    pub directory: String,
+    /// Least recently played files are evicted above this size (default: 20480 MB)
+    pub max_size_mb: u64,
+    /// yt-dlp format used for downloads; progressive MP4 plays in both video players
+    pub format: String,
+    /// Maximum time a single download may take (default: 1800s)
+    pub download_timeout_secs: u64,
+    /// Port of the local media server, bound to 127.0.0.1 (default: 8790)
+    pub server_port: u16,
+    /// A server started on demand exits after this long without requests (default: 3600s)
+    pub server_idle_secs: u64,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            directory: "library".to_string(),
+            max_size_mb: crate::constants::defaults::LIBRARY_MAX_SIZE_MB,
+            format: "best[height<=1080][ext=mp4]/best[ext=mp4]/best".to_string(),
+            download_timeout_secs: crate::constants::defaults::LIBRARY_DOWNLOAD_TIMEOUT_SECS,
+            server_port: crate::constants::defaults::MEDIA_SERVER_PORT,
+            server_idle_secs: crate::constants::defaults::MEDIA_SERVER_IDLE_SECS,
+        }
+    }
+}
+
+/// Local relay that serves HLS streams to VRChat through the media server, rewriting
+/// manifests on the way so AVPro doesn't trip over upstream quirks
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RelayConfig {
+    /// Hand VRChat relay URLs instead of upstream HLS manifests (default: false)
+    pub enabled: bool,
+    /// Only relay streams requested from these host patterns; empty relays every HLS stream
+    pub hosts: Vec<String>,
+    /// Live playlists are trimmed to this many segments, cutting long DVR windows (default: 30)
+    pub max_live_segments: Option<usize>,
+    /// Serve only the best variant within the quality limits instead of the whole master playlist (default: true)
+    pub select_variant: bool,
+    /// Re-resolve through the pipeline once the upstream URL expires within this window (default: 60s)
+    pub refresh_margin_secs: u64,
+    /// Maximum time for a single upstream request (default: 10s)
+    pub upstream_timeout_secs: u64,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            hosts: Vec::new(),
+            max_live_segments: Some(crate::constants::defaults::RELAY_MAX_LIVE_SEGMENTS),
+            select_variant: true,
+            refresh_margin_secs: crate::constants::defaults::RELAY_REFRESH_MARGIN_SECS,
+            upstream_timeout_secs: crate::constants::defaults::RELAY_UPSTREAM_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// Record of past invocations, kept for troubleshooting
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Append a record of every invocation to the history file (default: true)
+    pub enabled: bool,
+    /// Records older than this are dropped (default: 30 days)
+    pub retention_days: u32,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: crate::constants::defaults::HISTORY_RETENTION_DAYS,
         }
     }
 }
@@ -45,12 +719,30 @@ impl Default for AppConfig {
                 "--no-check-certificate".to_string(),
                 "--no-warnings".to_string(),
                 "--no-cache-dir".to_string(),
-                "-f".to_string(),
-                "best[height<=1080][protocol^=m3u8]".to_string(),
             ],
             cookies: false,
             cookies_browser: "firefox".to_string(),
             logging: LoggingConfig::default(),
+            execution: ExecutionConfig::default(),
+            rules: Vec::new(),
+            probe: ProbeConfig::default(),
+            fallback_strategies: Vec::new(),
+            passthrough: PassthroughConfig::default(),
+            resolvers: default_resolvers(),
+            cache: CacheConfig::default(),
+            scripted: ScriptedConfig::default(),
+            streamlink: StreamlinkConfig::default(),
+            daemon: DaemonConfig::default(),
+            prefetch: PrefetchConfig::default(),
+            quality: QualityConfig::default(),
+            players: PlayersConfig::default(),
+            rewrite: RewriteConfig::default(),
+            policy: PolicyConfig::default(),
+            content_policy: ContentPolicyConfig::default(),
+            placeholders: PlaceholderConfig::default(),
+            library: LibraryConfig::default(),
+            relay: RelayConfig::default(),
+            history: HistoryConfig::default(),
         }
     }
 }
@@ -0,0 +1,103 @@
+use crate::error::{AppError, Result};
+use crate::logger::Logger;
+
+/// Validates what yt-dlp printed before anything is handed to VRChat
+pub struct OutputValidator;
+
+impl OutputValidator {
+    /// Checks whether the arguments ask yt-dlp to print media URLs
+    pub fn expects_urls(args: &[String]) -> bool {
+        args.iter().any(|arg| arg == "--get-url" || arg == "-g")
+    }
+
+    /// Picks the single playable URL from yt-dlp's `--get-url` output.
+    ///
+    /// Non-URL lines are discarded. A merged format that resolved to separate video
+    /// and audio streams is rejected: VRChat can only play one URL, and either one
+    /// alone (even an HLS rendition) would play without sound or picture.
+    pub fn select_url(lines: &[String], logger: &Logger) -> Result<String> {
+        let mut urls = Vec::new();
+
+        for line in lines.iter().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            if Self::is_media_url(line) {
+                urls.push(line);
+            } else {
+                logger.log_warning(&format!("Discarding non-URL output line: {}", line));
+            }
+        }
+
+        match urls.as_slice() {
+            [] => Err(AppError::Validation("yt-dlp did not print a URL".to_string())),
+            [url] => Ok(url.to_string()),
+            [_, _] => Err(AppError::Validation(
+                "format resolved to separate video and audio streams; use a combined format in -f".to_string(),
+            )),
+            _ => Err(AppError::Validation(format!(
+                "expected a single URL but yt-dlp printed {}",
+                urls.len()
+            ))),
+        }
+    }
+
+    /// Checks if a line is an absolute http(s) URL
+    fn is_media_url(line: &str) -> bool {
+        reqwest::Url::parse(line)
+            .map(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some())
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logger::LogConfig;
+
+    fn logger() -> Logger {
+        Logger::with_config(std::env::temp_dir().join("vrc-ytdlp-output-test.log"), LogConfig::default())
+    }
+
+    fn lines(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn expects_urls_only_when_printing_them() {
+        assert!(OutputValidator::expects_urls(&lines(&["-f", "best", "-g", "URL"])));
+        assert!(OutputValidator::expects_urls(&lines(&["--get-url", "URL"])));
+        assert!(!OutputValidator::expects_urls(&lines(&["-J", "URL"])));
+    }
+
+    #[test]
+    fn selects_the_single_url_among_noise() {
+        let output = lines(&[
+            "",
+            "WARNING: something odd",
+            "  https://cdn.example.com/video.mp4?sig=1  ",
+            "ftp://example.com/file",
+            "/relative/path.mp4",
+        ]);
+
+        assert_eq!(
+            OutputValidator::select_url(&output, &logger()).unwrap(),
+            "https://cdn.example.com/video.mp4?sig=1"
+        );
+    }
+
+    #[test]
+    fn rejects_missing_or_separate_urls() {
+        let logger = logger();
+
+        for output in [
+            lines(&[]),
+            lines(&["ERROR: nothing here"]),
+            lines(&["https://cdn.example.com/video", "https://cdn.example.com/audio"]),
+            lines(&["https://a.example.com/1", "https://a.example.com/2", "https://a.example.com/3"]),
+        ] {
+            assert!(
+                matches!(OutputValidator::select_url(&output, &logger), Err(AppError::Validation(_))),
+                "{:?}",
+                output
+            );
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::time::Duration;
+
+use reqwest::header::CONTENT_TYPE;
+
+use crate::constants::YT_DLP_SITE_HOSTS;
+use crate::deadline::Deadline;
+use crate::logger::Logger;
+use crate::models::PassthroughConfig;
+use crate::rules::{host_matches, host_matches_any};
+
+/// Recognises URLs that already point at playable media so yt-dlp can be skipped
+pub struct Passthrough<'a> {
+    config: &'a PassthroughConfig,
+}
+
+impl<'a> Passthrough<'a> {
+    pub fn new(config: &'a PassthroughConfig) -> Self {
+        Self { config }
+    }
+
+    /// Checks whether the URL can be handed to VRChat as-is.
+    ///
+    /// The optional Content-Type probe is bounded by the request deadline.
+    pub async fn is_direct_media(&self, url: &str, deadline: &Deadline, logger: &Logger) -> bool {
+        if !self.config.enabled {
+            return false;
+        }
+
+        let Ok(parsed) = reqwest::Url::parse(url) else {
+            return false;
+        };
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return false;
+        }
+
+        let host = parsed.host_str().unwrap_or_default().to_ascii_lowercase();
+        if host_matches_any(&host, &self.config.blocked_hosts) {
+            logger.log_debug(&format!("Passthrough disabled for blocked host: {}", host));
+            return false;
+        }
+        if !self.config.allowed_hosts.is_empty() && !host_matches_any(&host, &self.config.allowed_hosts) {
+            logger.log_debug(&format!("Passthrough disabled for host not in allow list: {}", host));
+            return false;
+        }
+
+        let path = parsed.path().to_ascii_lowercase();
+        if self
+            .config
+            .extensions
+            .iter()
+            .any(|ext| path.ends_with(&ext.to_ascii_lowercase()))
+        {
+            return true;
+        }
+
+        self.config.content_type_probe
+            && !Self::is_site_host(&host)
+            && self.has_media_content_type(url, deadline, logger).await
+    }
+
+    /// Checks whether yt-dlp has an extractor for the host
+    fn is_site_host(host: &str) -> bool {
+        YT_DLP_SITE_HOSTS.iter().any(|pattern| host_matches(host, pattern))
+    }
+
+    /// Sends a quick HEAD request and checks the Content-Type for media
+    async fn has_media_content_type(&self, url: &str, deadline: &Deadline, logger: &Logger) -> bool {
+        let response = reqwest::Client::new()
+            .head(url)
+            .timeout(deadline.clamp(Duration::from_millis(self.config.probe_timeout_ms)))
+            .send()
+            .await;
+
+        let content_type = match response {
+            Ok(response) if response.status().is_success() => response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_ascii_lowercase())
+                .unwrap_or_default(),
+            Ok(response) => {
+                logger.log_debug(&format!("Content-Type probe got HTTP {}", response.status().as_u16()));
+                return false;
+            }
+            Err(e) => {
+                logger.log_debug(&format!("Content-Type probe failed: {}", e));
+                return false;
+            }
+        };
+
+        logger.log_debug(&format!("Content-Type probe: {}", content_type));
+        content_type.starts_with("video/")
+            || content_type.starts_with("audio/")
+            || content_type.contains("mpegurl")
+            || content_type.contains("dash+xml")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::time::Instant;
+
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use hyper::body::Incoming;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response};
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::logger::LogConfig;
+
+    /// Answers every request with the Content-Type named by the path, after `delay`
+    async fn fixture(delay: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let service = service_fn(move |request: Request<Incoming>| async move {
+                        tokio::time::sleep(delay).await;
+                        let content_type = request.uri().path().trim_start_matches('/').replacen('-', "/", 1);
+                        let mut response = Response::new(Full::new(Bytes::new()));
+                        response.headers_mut().insert(CONTENT_TYPE, content_type.parse().unwrap());
+                        Ok::<_, Infallible>(response)
+                    });
+                    let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await;
+                });
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    fn logger() -> Logger {
+        Logger::with_config(std::env::temp_dir().join("vrc-ytdlp-passthrough-test.log"), LogConfig::default())
+    }
+
+    async fn is_direct(config: &PassthroughConfig, url: &str) -> bool {
+        let deadline = Deadline::new(Duration::from_secs(10));
+        Passthrough::new(config).is_direct_media(url, &deadline, &logger()).await
+    }
+
+    #[tokio::test]
+    async fn media_extensions_pass_through() {
+        let config = PassthroughConfig::default();
+
+        assert!(is_direct(&config, "https://cdn.example.com/video.MP4?sig=1").await);
+        assert!(is_direct(&config, "https://cdn.example.com/live/index.m3u8").await);
+        assert!(!is_direct(&config, "https://www.youtube.com/watch?v=x").await);
+        assert!(!is_direct(&config, "https://cdn.example.com/video.mp4/page").await);
+        assert!(!is_direct(&config, "ftp://cdn.example.com/video.mp4").await);
+
+        let disabled = PassthroughConfig {
+            enabled: false,
+            ..Default::default()
+        };
+        assert!(!is_direct(&disabled, "https://cdn.example.com/video.mp4").await);
+    }
+
+    #[tokio::test]
+    async fn host_lists_limit_passthrough() {
+        let config = PassthroughConfig {
+            allowed_hosts: vec!["example.com".to_string()],
+            blocked_hosts: vec!["private.example.com".to_string()],
+            ..Default::default()
+        };
+
+        assert!(is_direct(&config, "https://cdn.example.com/video.mp4").await);
+        assert!(!is_direct(&config, "https://private.example.com/video.mp4").await);
+        assert!(!is_direct(&config, "https://other.net/video.mp4").await);
+    }
+
+    #[tokio::test]
+    async fn content_type_probe_accepts_media() {
+        let base = fixture(Duration::ZERO).await;
+        let config = PassthroughConfig {
+            content_type_probe: true,
+            ..Default::default()
+        };
+
+        assert!(is_direct(&config, &format!("{}/video-mp4", base)).await);
+        assert!(is_direct(&config, &format!("{}/application-vnd.apple.mpegurl", base)).await);
+        assert!(!is_direct(&config, &format!("{}/text-html", base)).await);
+    }
+
+    #[test]
+    fn site_hosts_are_not_probed() {
+        assert!(Passthrough::is_site_host("www.youtube.com"));
+        assert!(Passthrough::is_site_host("youtu.be"));
+        assert!(Passthrough::is_site_host("clips.twitch.tv"));
+        assert!(!Passthrough::is_site_host("notyoutube.com"));
+        assert!(!Passthrough::is_site_host("cdn.example.com"));
+    }
+
+    #[tokio::test]
+    async fn content_type_probe_is_bounded_by_the_deadline() {
+        let base = fixture(Duration::from_secs(5)).await;
+        let config = PassthroughConfig {
+            content_type_probe: true,
+            probe_timeout_ms: 5000,
+            ..Default::default()
+        };
+        let deadline = Deadline::new(Duration::from_millis(300));
+
+        let started = Instant::now();
+        let direct = Passthrough::new(&config)
+            .is_direct_media(&format!("{}/video-mp4", base), &deadline, &logger())
+            .await;
+        assert!(!direct);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
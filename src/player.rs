@@ -0,0 +1,205 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::args::ArgumentParser;
+use crate::constants::defaults::{VRCHAT_LOG_CONTEXT_LINES, VRCHAT_LOG_SCAN_BYTES};
+use crate::constants::VRCHAT_LOG_PREFIX;
+use crate::logger::Logger;
+use crate::models::{PlayerProfile, PlayersConfig};
+
+/// Works out which video player (AVPro or Unity) made a request
+pub struct PlayerDetector {
+    config: PlayersConfig,
+    log_dir: PathBuf,
+    logger: Logger,
+}
+
+impl PlayerDetector {
+    pub fn new(config: PlayersConfig, app_dir: &Path, logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
+        // The app lives in VRChat's Tools folder, next to VRChat's own logs
+        let log_dir = match &config.vrchat_log_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => app_dir.parent().unwrap_or(app_dir).to_path_buf(),
+        };
+
+        Self { config, log_dir, logger }
+    }
+
+    /// Returns the profile name for a request, falling back to the default profile
+    pub fn detect(&self, args: &[String], url: &str) -> String {
+        if let Some(profile) = self.detect_from_args(args) {
+            self.logger.log_debug(&format!("Detected player {} from VRChat's arguments", profile.name));
+            return profile.name.clone();
+        }
+
+        if self.config.detect_from_log {
+            if let Some(profile) = self.detect_from_log(url) {
+                self.logger.log_debug(&format!("Detected player {} from VRChat's log", profile.name));
+                return profile.name.clone();
+            }
+        }
+
+        self.logger.log_debug(&format!(
+            "Player not detected, using default profile {}",
+            self.config.default_profile
+        ));
+        self.config.default_profile.clone()
+    }
+
+    /// Matches the format selector VRChat asked for against each profile's patterns
+    fn detect_from_args(&self, args: &[String]) -> Option<&PlayerProfile> {
+        let selector = ArgumentParser::format_selector_in(args)?;
+        self.config.profiles.iter().find(|profile| {
+            profile
+                .arg_patterns
+                .iter()
+                .any(|pattern| selector.contains(pattern.as_str()))
+        })
+    }
+
+    /// Finds the latest mention of the URL in VRChat's log and looks for a
+    /// player marker in the lines leading up to it
+    fn detect_from_log(&self, url: &str) -> Option<&PlayerProfile> {
+        let log = Self::read_log_tail(&self.latest_log()?)?;
+        let lines: Vec<&str> = log.lines().collect();
+        let position = lines.iter().rposition(|line| line.contains(url))?;
+        let context = &lines[position.saturating_sub(VRCHAT_LOG_CONTEXT_LINES)..=position];
+
+        // The marker closest to the request wins
+        context.iter().rev().find_map(|line| {
+            self.config.profiles.iter().find(|profile| {
+                profile
+                    .log_markers
+                    .iter()
+                    .any(|marker| line.contains(marker.as_str()))
+            })
+        })
+    }
+
+    /// Finds VRChat's most recently modified output log
+    fn latest_log(&self) -> Option<PathBuf> {
+        fs::read_dir(&self.log_dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(VRCHAT_LOG_PREFIX))
+            .max_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+            .map(|entry| entry.path())
+    }
+
+    /// Reads the end of the log; VRChat logs grow large over a session
+    fn read_log_tail(path: &Path) -> Option<String> {
+        let mut file = File::open(path).ok()?;
+        let len = file.metadata().ok()?.len();
+        file.seek(SeekFrom::Start(len.saturating_sub(VRCHAT_LOG_SCAN_BYTES))).ok()?;
+
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer).ok()?;
+        Some(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logger::LogConfig;
+
+    const URL: &str = "https://www.youtube.com/watch?v=abc";
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc-ytdlp-player-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn detector(dir: &Path) -> PlayerDetector {
+        let config = PlayersConfig {
+            vrchat_log_dir: Some(dir.to_string_lossy().into_owned()),
+            ..PlayersConfig::default()
+        };
+        let logger = Logger::with_config(dir.join("test.log"), LogConfig::default());
+        PlayerDetector::new(config, dir, logger)
+    }
+
+    fn args(selector: &str) -> Vec<String> {
+        vec!["--no-check-certificate".to_string(), "-f".to_string(), selector.to_string(), URL.to_string()]
+    }
+
+    #[test]
+    fn detects_player_from_format_selector() {
+        let dir = test_dir("args");
+        let detector = detector(&dir);
+
+        assert_eq!(detector.detect(&args("best[protocol^=m3u8]"), URL), "avpro");
+        assert_eq!(detector.detect(&args("best[height<=1080][protocol^=http]"), URL), "unity");
+    }
+
+    #[test]
+    fn falls_back_to_default_profile() {
+        let dir = test_dir("default");
+        let detector = detector(&dir);
+
+        assert_eq!(detector.detect(&[URL.to_string()], URL), "avpro");
+    }
+
+    #[test]
+    fn detects_player_from_latest_log() {
+        let dir = test_dir("log");
+        fs::write(
+            dir.join("output_log_2026-01-01_00-00-00.txt"),
+            format!("[AVPro] Opening {}\n", URL),
+        )
+        .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(
+            dir.join("output_log_2026-01-02_00-00-00.txt"),
+            format!("[UnityVideoPlayer] Created\nunrelated line\n[Video Playback] Resolving URL '{}'\n", URL),
+        )
+        .unwrap();
+        let detector = detector(&dir);
+
+        assert_eq!(detector.detect(&[URL.to_string()], URL), "unity");
+    }
+
+    #[test]
+    fn closest_log_marker_wins() {
+        let dir = test_dir("closest");
+        fs::write(
+            dir.join("output_log_2026-01-01_00-00-00.txt"),
+            format!("[UnityVideoPlayer] Created\n[AVPro] Created\nResolving URL '{}'\n", URL),
+        )
+        .unwrap();
+        let detector = detector(&dir);
+
+        assert_eq!(detector.detect(&[URL.to_string()], URL), "avpro");
+    }
+
+    #[test]
+    fn ignores_markers_outside_the_context_window() {
+        let dir = test_dir("window");
+        let mut log = String::from("[UnityVideoPlayer] Created\n");
+        for i in 0..VRCHAT_LOG_CONTEXT_LINES {
+            log.push_str(&format!("filler {}\n", i));
+        }
+        log.push_str(&format!("Resolving URL '{}'\n", URL));
+        fs::write(dir.join("output_log_2026-01-01_00-00-00.txt"), log).unwrap();
+        let detector = detector(&dir);
+
+        assert_eq!(detector.detect(&[URL.to_string()], URL), "avpro");
+    }
+
+    #[test]
+    fn arguments_take_precedence_over_log() {
+        let dir = test_dir("precedence");
+        fs::write(
+            dir.join("output_log_2026-01-01_00-00-00.txt"),
+            format!("[AVPro] Opening {}\n", URL),
+        )
+        .unwrap();
+        let detector = detector(&dir);
+
+        assert_eq!(detector.detect(&args("best[protocol^=http]"), URL), "unity");
+    }
+}
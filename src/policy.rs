@@ -0,0 +1,167 @@
+use sha2::{Digest, Sha256};
+
+use crate::error::{AppError, Result};
+use crate::format::MediaInfo;
+use crate::models::{ContentPolicyConfig, PolicyAction, PolicyConfig};
+use crate::rules;
+
+/// Decides whether a requested URL may be resolved at all, based on its host
+pub struct DomainPolicy<'a> {
+    config: &'a PolicyConfig,
+}
+
+impl<'a> DomainPolicy<'a> {
+    pub fn new(config: &'a PolicyConfig) -> Self {
+        Self { config }
+    }
+
+    /// Returns `AppError::Blocked` with the reason when the URL is refused
+    pub fn check(&self, url: &str) -> Result<()> {
+        let host = rules::host_of(url)
+            .ok_or_else(|| AppError::Blocked(format!("could not read the host of {}", url)))?;
+
+        if rules::host_matches_any(&host, &self.config.blocked_hosts) {
+            return Err(AppError::Blocked(format!("{} is on the blocked host list", host)));
+        }
+        if rules::host_matches_any(&host, &self.config.allowed_hosts) {
+            return Ok(());
+        }
+
+        match self.config.default_action {
+            PolicyAction::Allow => Ok(()),
+            PolicyAction::Block => Err(AppError::Blocked(format!("{} is not on the allowed host list", host))),
+        }
+    }
+}
+
+/// Decides whether a video may be played, based on its yt-dlp metadata
+pub struct ContentPolicy<'a> {
+    config: &'a ContentPolicyConfig,
+}
+
+impl<'a> ContentPolicy<'a> {
+    pub fn new(config: &'a ContentPolicyConfig) -> Self {
+        Self { config }
+    }
+
+    /// Identifies the rules in effect, so a resolution checked under different
+    /// rules is checked again. `None` while the policy is disabled.
+    pub fn fingerprint(&self) -> Option<String> {
+        if !self.config.enabled {
+            return None;
+        }
+
+        let rules = serde_json::to_vec(self.config).ok()?;
+        let digest = Sha256::digest(rules);
+        Some(digest[..8].iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// Returns `AppError::Blocked` with the first rule the video breaks
+    pub fn check(&self, info: &MediaInfo) -> Result<()> {
+        if let (Some(max), Some(duration)) = (self.config.max_duration_mins, info.duration) {
+            if duration > (max * 60) as f64 {
+                return Err(AppError::Blocked(format!(
+                    "video is {} minutes long, the limit is {}",
+                    (duration / 60.0).ceil(),
+                    max
+                )));
+            }
+        }
+
+        if self.config.block_live && info.is_live == Some(true) {
+            return Err(AppError::Blocked("live streams are not allowed".to_string()));
+        }
+
+        if let (Some(max), Some(age_limit)) = (self.config.max_age_limit, info.age_limit) {
+            if age_limit > max {
+                return Err(AppError::Blocked(format!("video is age-restricted ({}+)", age_limit)));
+            }
+        }
+
+        if let Some(channel) = info
+            .channel_id
+            .as_ref()
+            .filter(|channel| self.config.blocked_channels.contains(channel))
+        {
+            return Err(AppError::Blocked(format!("channel {} is blocked", channel)));
+        }
+
+        let uploaders = [&info.uploader, &info.uploader_id];
+        if let Some(uploader) = uploaders.iter().filter_map(|u| u.as_deref()).find(|uploader| {
+            self.config
+                .blocked_uploaders
+                .iter()
+                .any(|blocked| blocked.eq_ignore_ascii_case(uploader))
+        }) {
+            return Err(AppError::Blocked(format!("uploader {} is blocked", uploader)));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    #[test]
+    fn default_allows_everything_but_blocked_hosts() {
+        let config = PolicyConfig {
+            blocked_hosts: hosts(&["example.com"]),
+            ..Default::default()
+        };
+        let policy = DomainPolicy::new(&config);
+
+        assert!(policy.check("https://www.youtube.com/watch?v=x").is_ok());
+        assert!(policy.check("https://example.com/v").is_err());
+        assert!(policy.check("https://cdn.EXAMPLE.com/v").is_err());
+        // Suffix matches stop at label boundaries
+        assert!(policy.check("https://notexample.com/v").is_ok());
+    }
+
+    #[test]
+    fn allow_list_with_blocking_default() {
+        let config = PolicyConfig {
+            default_action: PolicyAction::Block,
+            allowed_hosts: hosts(&["youtube.com", "*.twitch.tv"]),
+            blocked_hosts: hosts(&["music.youtube.com"]),
+        };
+        let policy = DomainPolicy::new(&config);
+
+        assert!(policy.check("https://youtube.com/watch?v=x").is_ok());
+        assert!(policy.check("https://www.youtube.com/watch?v=x").is_ok());
+        assert!(policy.check("https://www.twitch.tv/channel").is_ok());
+        // A wildcard only covers subdomains
+        assert!(policy.check("https://twitch.tv/channel").is_err());
+        // Blocking wins over allowing
+        assert!(matches!(
+            policy.check("https://music.youtube.com/watch?v=x"),
+            Err(AppError::Blocked(_))
+        ));
+        assert!(policy.check("https://vimeo.com/1").is_err());
+    }
+
+    #[test]
+    fn url_without_host_is_refused() {
+        let config = PolicyConfig::default();
+
+        assert!(DomainPolicy::new(&config).check("not a url").is_err());
+    }
+
+    #[test]
+    fn content_fingerprint_follows_the_rules() {
+        let mut config = ContentPolicyConfig::default();
+        assert_eq!(ContentPolicy::new(&config).fingerprint(), None);
+
+        config.enabled = true;
+        let first = ContentPolicy::new(&config).fingerprint().unwrap();
+        assert_eq!(ContentPolicy::new(&config).fingerprint().unwrap(), first);
+
+        config.max_duration_mins = Some(10);
+        assert_ne!(ContentPolicy::new(&config).fingerprint().unwrap(), first);
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::logger::Logger;
+use crate::models::PrefetchConfig;
+use crate::service::ResolveService;
+
+/// What the queue watcher remembers about queued URLs between passes
+#[derive(Default)]
+struct WatchState {
+    /// URLs whose last prefetch failed, retried after `retry_after_secs`
+    failures: HashMap<String, Instant>,
+    /// When each URL was first seen in the queue
+    queued: HashMap<String, DateTime<Utc>>,
+    /// URLs that were played or expired and are no longer refreshed
+    dropped: HashSet<String>,
+}
+
+/// Resolves queued URLs in the background so they are cache hits when VRChat asks for them
+pub struct PrefetchQueue {
+    service: Arc<ResolveService>,
+    logger: Logger,
+}
+
+impl PrefetchQueue {
+    pub fn new(service: Arc<ResolveService>, logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
+        Self { service, logger }
+    }
+
+    /// Prefetches each URL in turn, logging the outcome
+    pub async fn prefetch_all(&self, urls: &[String]) -> usize {
+        let mut resolved = 0;
+
+        for url in urls {
+            match self.service.prefetch(url).await {
+                Ok(true) => {
+                    self.logger.log_info(&format!("Prefetched {}", url));
+                    resolved += 1;
+                }
+                Ok(false) => self.logger.log_debug(&format!("Prefetch skipped, cache still fresh: {}", url)),
+                Err(e) => self.logger.log_warning(&format!("Prefetch failed for {}: {}", url, e)),
+            }
+        }
+
+        resolved
+    }
+
+    /// Re-reads the queue file on an interval, resolving new URLs and refreshing
+    /// ones whose cached resolution is about to expire.
+    ///
+    /// A URL stops being refreshed once it was played or has been queued for
+    /// longer than `max_age_secs`; removing it from the file and adding it back
+    /// queues it again.
+    pub async fn watch(self, config: PrefetchConfig, app_dir: &Path) {
+        let path = Self::queue_path(&config, app_dir);
+        let interval = Duration::from_secs(config.poll_interval_secs.max(1));
+        let mut state = WatchState::default();
+
+        self.logger.log_info(&format!("Watching prefetch queue: {}", path.display()));
+
+        loop {
+            let urls = Self::read_queue(&path);
+            // Each pass logs under its own ID, like a request would
+            let pass = self.watch_pass(&urls, &config, &mut state);
+            Logger::scope_invocation(&Logger::new_invocation_id(), pass).await;
+
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Prefetches the queued URLs that are still due, returning how many were resolved
+    async fn watch_pass(&self, urls: &[String], config: &PrefetchConfig, state: &mut WatchState) -> usize {
+        let retry_after = Duration::from_secs(config.retry_after_secs);
+        let max_age = chrono::Duration::seconds(config.max_age_secs as i64);
+        let WatchState { failures, queued, dropped } = state;
+
+        failures.retain(|url, failed_at| urls.contains(url) && failed_at.elapsed() < retry_after);
+        queued.retain(|url, _| urls.contains(url));
+        dropped.retain(|url| urls.contains(url));
+
+        let now = Utc::now();
+        for url in urls {
+            queued.entry(url.clone()).or_insert(now);
+        }
+        let played = match queued.values().min() {
+            Some(oldest) => self.service.last_played(*oldest),
+            None => HashMap::new(),
+        };
+
+        let mut resolved = 0;
+        for url in urls {
+            if failures.contains_key(url) || dropped.contains(url) {
+                continue;
+            }
+
+            let queued_at = queued[url];
+            if now - queued_at > max_age {
+                self.logger.log_debug(&format!("Prefetch expired, no longer refreshing {}", url));
+                dropped.insert(url.clone());
+                continue;
+            }
+            if played
+                .get(&self.service.canonical_url(url))
+                .is_some_and(|played_at| *played_at >= queued_at)
+            {
+                self.logger.log_debug(&format!("Prefetched URL was played, no longer refreshing {}", url));
+                dropped.insert(url.clone());
+                continue;
+            }
+
+            match self.service.prefetch(url).await {
+                Ok(true) => {
+                    self.logger.log_info(&format!("Prefetched {}", url));
+                    resolved += 1;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    self.logger.log_warning(&format!("Prefetch failed for {}: {}", url, e));
+                    failures.insert(url.clone(), Instant::now());
+                }
+            }
+        }
+
+        resolved
+    }
+
+    /// Resolves the queue file location, relative paths being relative to the app directory
+    fn queue_path(config: &PrefetchConfig, app_dir: &Path) -> PathBuf {
+        let path = PathBuf::from(&config.queue_file);
+        if path.is_absolute() {
+            path
+        } else {
+            app_dir.join(path)
+        }
+    }
+
+    /// Reads URLs from the queue file, ignoring blank lines and `#` comments
+    fn read_queue(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::ResolutionCache;
+    use crate::error::AppError;
+    use crate::logger::LogConfig;
+    use crate::models::{AppConfig, ScriptedConfig, ScriptedResponse};
+    use crate::resolver::{CacheResolver, ResolverContext, ScriptedResolver};
+
+    use super::*;
+
+    const URL: &str = "https://example.com/v";
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc-ytdlp-prefetch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config() -> AppConfig {
+        let mut config = AppConfig {
+            resolvers: vec![CacheResolver::NAME.to_string(), ScriptedResolver::NAME.to_string()],
+            scripted: ScriptedConfig {
+                responses: vec![
+                    ScriptedResponse {
+                        pattern: "example.com".to_string(),
+                        url: Some("https://cdn.example.com/v.mp4".to_string()),
+                        ..Default::default()
+                    },
+                    ScriptedResponse {
+                        pattern: "broken.example.net".to_string(),
+                        error: Some("ERROR: Video unavailable".to_string()),
+                        ..Default::default()
+                    },
+                ],
+            },
+            ..Default::default()
+        };
+        config.players.detect_from_log = false;
+        // Resolutions are never fresh enough, so each pass refreshes them
+        config.prefetch.refresh_margin_secs = config.cache.ttl_secs + 60;
+        config
+    }
+
+    fn queue(dir: &Path, config: AppConfig) -> PrefetchQueue {
+        let logger = Logger::with_config(dir.join("test.log"), LogConfig::default());
+        let context = ResolverContext {
+            app_dir: dir.to_path_buf(),
+            ytdlp_path: dir.join("yt-dlp.exe"),
+            streamlink_path: dir.join("streamlink.exe"),
+            concurrent: false,
+            cache: Arc::new(ResolutionCache::new(dir.to_path_buf(), config.cache.ttl_secs)),
+            logger: logger.clone(),
+        };
+        PrefetchQueue::new(Arc::new(ResolveService::new(config, context).unwrap()), logger)
+    }
+
+    fn urls(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn prefetch_resolves_for_every_player() {
+        let dir = test_dir("players");
+        let mut config = config();
+        config.prefetch.refresh_margin_secs = 0;
+        let queue = queue(&dir, config);
+
+        assert_eq!(queue.prefetch_all(&urls(&[URL])).await, 1);
+        let cache = ResolutionCache::new(dir, 600);
+        for player in ["avpro", "unity"] {
+            assert!(cache.get(&format!("{}|{}", player, URL)).is_some(), "{}", player);
+        }
+
+        // Still fresh, nothing to do
+        assert_eq!(queue.prefetch_all(&urls(&[URL])).await, 0);
+    }
+
+    #[tokio::test]
+    async fn prefetch_requires_the_cache() {
+        let dir = test_dir("no-cache");
+        let mut config = config();
+        config.resolvers = vec![ScriptedResolver::NAME.to_string()];
+
+        assert!(matches!(queue(&dir, config).service.prefetch(URL).await, Err(AppError::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn watch_stops_refreshing_played_urls() {
+        let dir = test_dir("played");
+        let queue = queue(&dir, config());
+        let config = PrefetchConfig::default();
+        let mut state = WatchState::default();
+        let queued = urls(&[URL, "https://example.com/other"]);
+
+        assert_eq!(queue.watch_pass(&queued, &config, &mut state).await, 2);
+        assert_eq!(queue.watch_pass(&queued, &config, &mut state).await, 2);
+
+        queue.service.handle(&urls(&["--get-url", URL])).await.unwrap();
+        assert_eq!(queue.watch_pass(&queued, &config, &mut state).await, 1);
+
+        // Queuing it again after removing it starts over
+        queue.watch_pass(&queued[1..], &config, &mut state).await;
+        assert_eq!(queue.watch_pass(&queued, &config, &mut state).await, 2);
+    }
+
+    #[tokio::test]
+    async fn watch_drops_expired_urls_and_retries_failures_later() {
+        let dir = test_dir("expired");
+        let queue = queue(&dir, config());
+        let config = PrefetchConfig::default();
+        let mut state = WatchState::default();
+        let queued = urls(&[URL, "https://broken.example.net/v"]);
+
+        assert_eq!(queue.watch_pass(&queued, &config, &mut state).await, 1);
+        let failed_at = state.failures[&queued[1]];
+
+        state.queued.insert(URL.to_string(), Utc::now() - chrono::Duration::seconds(config.max_age_secs as i64 + 1));
+        assert_eq!(queue.watch_pass(&queued, &config, &mut state).await, 0);
+        assert!(state.dropped.contains(URL));
+
+        // Failures are retried once `retry_after_secs` passed
+        let retry = PrefetchConfig {
+            retry_after_secs: 0,
+            ..config
+        };
+        assert_eq!(state.failures[&queued[1]], failed_at);
+        queue.watch_pass(&queued, &retry, &mut state).await;
+        assert!(state.failures[&queued[1]] > failed_at);
+    }
+}
@@ -0,0 +1,285 @@
+use std::time::{Duration, Instant};
+
+use reqwest::header::{CONTENT_TYPE, RANGE};
+use reqwest::StatusCode;
+
+use crate::deadline::Deadline;
+use crate::error::{AppError, Result};
+use crate::logger::Logger;
+use crate::models::ProbeConfig;
+
+/// Checks that a resolved media URL is actually reachable before VRChat gets it
+pub struct UrlProbe {
+    client: reqwest::Client,
+    timeout: Duration,
+}
+
+impl UrlProbe {
+    /// Creates a new probe from configuration
+    pub fn new(config: &ProbeConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            timeout: Duration::from_millis(config.timeout_ms),
+        }
+    }
+
+    /// Probes the URL with HEAD, falling back to a one-byte ranged GET for servers
+    /// that reject HEAD. HLS manifests are additionally downloaded and parsed.
+    ///
+    /// Each request is bounded by both the probe timeout and the request deadline.
+    pub async fn check(&self, url: &str, deadline: &Deadline, logger: &Logger) -> Result<()> {
+        let start = Instant::now();
+        let result = self.check_inner(url, deadline).await;
+        let elapsed = start.elapsed().as_millis();
+        let host = crate::rules::host_of(url).unwrap_or_default();
+
+        match &result {
+            Ok(status) => logger.log_info(&format!(
+                "Probe of {} passed in {}ms (HTTP {})",
+                host,
+                elapsed,
+                status.as_u16()
+            )),
+            Err(e) => logger.log_warning(&format!(
+                "Probe of {} failed after {}ms: {}",
+                host, elapsed, e
+            )),
+        }
+
+        result.map(|_| ())
+    }
+
+    async fn check_inner(&self, url: &str, deadline: &Deadline) -> Result<StatusCode> {
+        let head = self
+            .client
+            .head(url)
+            .timeout(deadline.clamp(self.timeout))
+            .send()
+            .await
+            .map_err(|e| AppError::HealthCheck(format!("HEAD request failed: {}", e)))?;
+
+        let response = if head.status().is_success() {
+            head
+        } else {
+            self.client
+                .get(url)
+                .header(RANGE, "bytes=0-0")
+                .timeout(deadline.clamp(self.timeout))
+                .send()
+                .await
+                .map_err(|e| AppError::HealthCheck(format!("ranged GET failed: {}", e)))?
+        };
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(AppError::HealthCheck(format!("server answered HTTP {}", status.as_u16())));
+        }
+
+        let is_hls = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|ct| ct.to_ascii_lowercase().contains("mpegurl"))
+            .unwrap_or(false)
+            || Self::looks_like_hls(url);
+
+        if is_hls {
+            self.check_manifest(url, deadline).await?;
+        }
+
+        Ok(status)
+    }
+
+    /// Checks if the URL itself names an HLS playlist
+    fn looks_like_hls(url: &str) -> bool {
+        let url = url.to_ascii_lowercase();
+        url.contains(".m3u8") || url.contains("/hls_playlist/")
+    }
+
+    /// Downloads an HLS manifest and checks that it looks like a playable playlist
+    async fn check_manifest(&self, url: &str, deadline: &Deadline) -> Result<()> {
+        let body = self
+            .client
+            .get(url)
+            .timeout(deadline.clamp(self.timeout))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| AppError::HealthCheck(format!("manifest download failed: {}", e)))?
+            .text()
+            .await
+            .map_err(|e| AppError::HealthCheck(format!("manifest download failed: {}", e)))?;
+
+        if !body.trim_start().starts_with("#EXTM3U") {
+            return Err(AppError::HealthCheck("manifest is missing #EXTM3U header".to_string()));
+        }
+
+        let has_entries = body
+            .lines()
+            .any(|line| line.starts_with("#EXT-X-STREAM-INF") || line.starts_with("#EXTINF"));
+        if !has_entries {
+            return Err(AppError::HealthCheck("manifest lists no variants or segments".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use hyper::body::Incoming;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Method, Request, Response};
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::logger::LogConfig;
+
+    const PLAYLIST: &str = "#EXTM3U\n#EXT-X-TARGETDURATION:2\n#EXTINF:2.0,\ns1.ts\n";
+
+    /// A fixture response: status for HEAD and GET, content type, body and delay
+    struct Route {
+        head: u16,
+        get: u16,
+        content_type: &'static str,
+        body: &'static str,
+        delay: Duration,
+    }
+
+    impl Route {
+        fn ok(content_type: &'static str, body: &'static str) -> Self {
+            Self { head: 200, get: 200, content_type, body, delay: Duration::ZERO }
+        }
+    }
+
+    /// Serves fixed responses by path
+    async fn fixture(routes: Vec<(&'static str, Route)>) -> String {
+        let routes: Arc<HashMap<&str, Route>> = Arc::new(routes.into_iter().collect());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let routes = routes.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request: Request<Incoming>| {
+                        let routes = routes.clone();
+                        async move {
+                            let mut response = Response::new(Full::new(Bytes::new()));
+                            *response.status_mut() = StatusCode::NOT_FOUND;
+                            if let Some(route) = routes.get(request.uri().path()) {
+                                tokio::time::sleep(route.delay).await;
+                                let status = if request.method() == Method::HEAD { route.head } else { route.get };
+                                response = Response::new(Full::new(Bytes::from(route.body)));
+                                *response.status_mut() = StatusCode::from_u16(status).unwrap();
+                                response
+                                    .headers_mut()
+                                    .insert(CONTENT_TYPE, route.content_type.parse().unwrap());
+                            }
+                            Ok::<_, Infallible>(response)
+                        }
+                    });
+                    let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await;
+                });
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    fn logger() -> Logger {
+        Logger::with_config(std::env::temp_dir().join("vrc-ytdlp-probe-test.log"), LogConfig::default())
+    }
+
+    fn probe() -> UrlProbe {
+        UrlProbe::new(&ProbeConfig {
+            enabled: true,
+            timeout_ms: 2000,
+        })
+    }
+
+    async fn check(url: &str, deadline: &Deadline) -> Result<()> {
+        probe().check(url, deadline, &logger()).await
+    }
+
+    #[tokio::test]
+    async fn reachable_media_passes() {
+        let base = fixture(vec![
+            ("/video.mp4", Route::ok("video/mp4", "")),
+            (
+                "/no-head.mp4",
+                Route {
+                    head: 405,
+                    ..Route::ok("video/mp4", "x")
+                },
+            ),
+        ])
+        .await;
+        let deadline = Deadline::new(Duration::from_secs(10));
+
+        assert!(check(&format!("{}/video.mp4", base), &deadline).await.is_ok());
+        // Servers rejecting HEAD get a ranged GET instead
+        assert!(check(&format!("{}/no-head.mp4", base), &deadline).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn failing_status_is_a_health_check_error() {
+        let base = fixture(vec![(
+            "/gone.mp4",
+            Route {
+                head: 403,
+                get: 403,
+                ..Route::ok("text/plain", "")
+            },
+        )])
+        .await;
+        let deadline = Deadline::new(Duration::from_secs(10));
+
+        for path in ["/gone.mp4", "/missing.mp4"] {
+            let result = check(&format!("{}{}", base, path), &deadline).await;
+            assert!(matches!(result, Err(AppError::HealthCheck(_))), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn manifests_are_parsed() {
+        let base = fixture(vec![
+            ("/live.m3u8", Route::ok("application/vnd.apple.mpegurl", PLAYLIST)),
+            ("/playlist", Route::ok("application/x-mpegURL", PLAYLIST)),
+            ("/html.m3u8", Route::ok("text/html", "<html>blocked</html>")),
+            ("/empty", Route::ok("application/vnd.apple.mpegurl", "#EXTM3U\n#EXT-X-ENDLIST\n")),
+        ])
+        .await;
+        let deadline = Deadline::new(Duration::from_secs(10));
+
+        assert!(check(&format!("{}/live.m3u8", base), &deadline).await.is_ok());
+        assert!(check(&format!("{}/playlist", base), &deadline).await.is_ok());
+        assert!(check(&format!("{}/html.m3u8", base), &deadline).await.is_err());
+        assert!(check(&format!("{}/empty", base), &deadline).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn probe_is_bounded_by_the_deadline() {
+        let base = fixture(vec![(
+            "/slow.mp4",
+            Route {
+                delay: Duration::from_secs(5),
+                ..Route::ok("video/mp4", "")
+            },
+        )])
+        .await;
+        let deadline = Deadline::new(Duration::from_millis(300));
+
+        let started = Instant::now();
+        assert!(check(&format!("{}/slow.mp4", base), &deadline).await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,1040 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{HeaderValue, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use hyper::{Method, Request, Response, StatusCode};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::args::ArgumentParser;
+use crate::cache::ResolutionCache;
+use crate::constants::{defaults, RELAY_SESSIONS_FILE_NAME, RELAY_SESSIONS_LOCK_FILE_NAME};
+use crate::error::{AppError, Result};
+use crate::logger::Logger;
+use crate::media_server::{self, Body};
+use crate::models::{AppConfig, QualityConfig};
+use crate::service::ResolveService;
+
+/// Playlist-level tags; every other tag belongs to the segment that follows it
+const PLAYLIST_TAGS: [&str; 10] = [
+    "#EXTM3U",
+    "#EXT-X-VERSION",
+    "#EXT-X-TARGETDURATION",
+    "#EXT-X-MEDIA-SEQUENCE",
+    "#EXT-X-DISCONTINUITY-SEQUENCE",
+    "#EXT-X-PLAYLIST-TYPE",
+    "#EXT-X-INDEPENDENT-SEGMENTS",
+    "#EXT-X-START",
+    "#EXT-X-ALLOW-CACHE",
+    "#EXT-X-I-FRAMES-ONLY",
+];
+
+/// Low-latency HLS tags; AVPro doesn't understand them and their partial segments
+/// would only add upstream requests
+const LOW_LATENCY_TAGS: [&str; 6] = [
+    "#EXT-X-PART",
+    "#EXT-X-PRELOAD-HINT",
+    "#EXT-X-RENDITION-REPORT",
+    "#EXT-X-SKIP",
+    "#EXT-X-SERVER-CONTROL",
+    "#EXT-X-I-FRAME-STREAM-INF",
+];
+
+const MPEGURL: &str = "application/vnd.apple.mpegurl";
+
+/// A relayed stream: the URL VRChat asked for and the manifest it currently resolves to
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RelaySession {
+    pub source_url: String,
+    pub upstream_url: String,
+    pub player: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Relay sessions, stored in the app directory so the process that resolved a request
+/// and the media server relaying it don't have to be the same.
+///
+/// Updates hold an exclusive lock on a sidecar file, like the resolution cache, so
+/// the shim, the daemon and the media server don't drop each other's sessions.
+pub struct RelaySessions {
+    path: PathBuf,
+    lock_path: PathBuf,
+}
+
+impl RelaySessions {
+    pub fn new(app_dir: &Path) -> Self {
+        Self {
+            path: app_dir.join(RELAY_SESSIONS_FILE_NAME),
+            lock_path: app_dir.join(RELAY_SESSIONS_LOCK_FILE_NAME),
+        }
+    }
+
+    /// Records the upstream manifest for a request and returns the session ID.
+    ///
+    /// The ID is derived from the source URL and player, so replaying a video reuses its session.
+    pub fn register(&self, source_url: &str, upstream_url: &str, player: &str) -> Result<String> {
+        // Repeat requests for the same video and player reuse the session, across restarts and builds
+        let digest = Sha256::new()
+            .chain_update(player)
+            .chain_update([0])
+            .chain_update(source_url)
+            .finalize();
+        let id = HlsRelay::hex(&digest[..8]);
+
+        let session = RelaySession {
+            source_url: source_url.to_string(),
+            upstream_url: upstream_url.to_string(),
+            player: player.to_string(),
+            updated_at: Utc::now(),
+        };
+
+        let _lock = self.lock()?;
+        let mut sessions = self.load();
+        sessions.insert(id.clone(), session);
+        self.save(&mut sessions)?;
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<RelaySession> {
+        self.load().remove(id)
+    }
+
+    /// Points a session at a freshly resolved manifest
+    pub fn update(&self, id: &str, upstream_url: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut sessions = self.load();
+        if let Some(session) = sessions.get_mut(id) {
+            session.upstream_url = upstream_url.to_string();
+            session.updated_at = Utc::now();
+            self.save(&mut sessions)?;
+        }
+        Ok(())
+    }
+
+    /// Locks the sessions file against other processes until the handle is dropped
+    fn lock(&self) -> Result<File> {
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.lock_path)?;
+        lock.lock()?;
+        Ok(lock)
+    }
+
+    fn load(&self) -> HashMap<String, RelaySession> {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Saves atomically, dropping sessions nobody has registered or refreshed in a day
+    fn save(&self, sessions: &mut HashMap<String, RelaySession>) -> Result<()> {
+        let cutoff = Utc::now() - chrono::Duration::hours(defaults::RELAY_SESSION_TTL_HOURS);
+        sessions.retain(|_, session| session.updated_at > cutoff);
+
+        let tmp_path = self.path.with_extension(format!("tmp.{}", std::process::id()));
+        fs::write(&tmp_path, serde_json::to_string_pretty(sessions)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// A variant from a master playlist
+struct Variant {
+    tag: String,
+    uri: String,
+    bandwidth: u64,
+    height: Option<u32>,
+}
+
+/// Serves `/hls/<session>/...` on the media server.
+///
+/// Manifests are fetched upstream and rewritten so every URI points back at the relay,
+/// resolved against the manifest it came from. Live windows are trimmed, a single variant
+/// can be picked within the quality limits, and expired upstream URLs are re-resolved
+/// through the pipeline without VRChat noticing.
+pub struct HlsRelay {
+    config: AppConfig,
+    sessions: RelaySessions,
+    service: Arc<ResolveService>,
+    client: reqwest::Client,
+    /// Serialises re-resolutions so concurrent requests don't each spawn yt-dlp
+    refresh: tokio::sync::Mutex<()>,
+    /// Signs the upstream URLs handed out in rewritten playlists
+    key: [u8; 64],
+    logger: Logger,
+}
+
+impl HlsRelay {
+    pub fn new(service: Arc<ResolveService>, app_dir: &Path, logger: Logger) -> Result<Self> {
+        let logger = logger.for_module(module_path!());
+        let config = service.config().clone();
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.relay.upstream_timeout_secs))
+            .build()?;
+        let mut key = [0u8; 64];
+        getrandom::getrandom(&mut key)
+            .map_err(|e| AppError::Config(format!("Failed to generate the relay key: {}", e)))?;
+
+        Ok(Self {
+            config,
+            sessions: RelaySessions::new(app_dir),
+            service,
+            client,
+            refresh: tokio::sync::Mutex::new(()),
+            key,
+            logger,
+        })
+    }
+
+    /// URL VRChat plays for a session
+    pub fn url_for(port: u16, id: &str) -> String {
+        format!("http://127.0.0.1:{}/hls/{}/index.m3u8", port, id)
+    }
+
+    /// Whether a resolved URL is an HLS manifest the relay can handle
+    pub fn is_hls(url: &str) -> bool {
+        let path = Url::parse(url)
+            .map(|u| u.path().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        path.ends_with(".m3u8") || path.contains("/manifest/hls")
+    }
+
+    pub(crate) async fn respond(&self, request: &Request<Incoming>) -> Result<Response<Body>> {
+        let Some((id, resource)) = request
+            .uri()
+            .path()
+            .strip_prefix("/hls/")
+            .and_then(|path| path.split_once('/'))
+        else {
+            return Ok(media_server::status(StatusCode::NOT_FOUND));
+        };
+        let Some(session) = self.sessions.get(id) else {
+            return Ok(media_server::status(StatusCode::NOT_FOUND));
+        };
+
+        // Only URLs this relay signed in a rewritten playlist are fetched, so other local
+        // processes or web pages can't use it to reach arbitrary hosts
+        let query = request
+            .uri()
+            .query()
+            .and_then(|query| Url::parse(&format!("http://127.0.0.1/?{}", query)).ok());
+        let param = |name: &str| {
+            query
+                .as_ref()
+                .and_then(|url| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned()))
+        };
+        let target = param("u");
+        if let Some(target) = &target {
+            if !param("s").is_some_and(|signature| self.verify(target, &signature)) {
+                self.logger.log_warning(&format!("Relay refused an unsigned upstream URL: {}", target));
+                return Ok(media_server::status(StatusCode::FORBIDDEN));
+            }
+        }
+
+        match (resource, target) {
+            ("index.m3u8", _) => self.index(id, session).await,
+            ("playlist.m3u8", Some(target)) => self.playlist(id, session, &target).await,
+            ("segment", Some(target)) => self.segment(id, session, &target, request).await,
+            _ => Ok(media_server::status(StatusCode::NOT_FOUND)),
+        }
+    }
+
+    /// The session's entry point: the upstream manifest, rewritten
+    async fn index(&self, id: &str, mut session: RelaySession) -> Result<Response<Body>> {
+        let margin = chrono::Duration::seconds(self.config.relay.refresh_margin_secs as i64);
+        if ResolutionCache::url_expiry(&session.upstream_url).is_some_and(|expiry| expiry <= Utc::now() + margin) {
+            session = self.refresh(id, &session).await?;
+        }
+
+        let (text, base) = match self.fetch_playlist(&session.upstream_url).await? {
+            Ok(playlist) => playlist,
+            Err(status) if Self::is_expired(status) => {
+                session = self.refresh(id, &session).await?;
+                match self.fetch_playlist(&session.upstream_url).await? {
+                    Ok(playlist) => playlist,
+                    Err(status) => return Ok(media_server::status(status)),
+                }
+            }
+            Err(status) => return Ok(media_server::status(status)),
+        };
+
+        let body = if Self::is_master(&text) {
+            let quality = ArgumentParser::quality_for(
+                &["--get-url".to_string(), session.source_url.clone()],
+                &self.config,
+                Some(&session.player),
+            );
+            self.rewrite_master(&text, &base, &quality).await?
+        } else {
+            self.rewrite_media(&text, &base)
+        };
+        Ok(Self::playlist_response(body))
+    }
+
+    /// A variant or rendition playlist referenced by the master playlist
+    async fn playlist(&self, id: &str, session: RelaySession, target: &str) -> Result<Response<Body>> {
+        let (text, base) = match self.fetch_playlist(target).await? {
+            Ok(playlist) => playlist,
+            Err(status) if Self::is_expired(status) => {
+                // The player keeps polling the stale variant URL, so find its
+                // counterpart in the re-resolved master playlist
+                let session = self.refresh(id, &session).await?;
+                let Ok((master, master_base)) = self.fetch_playlist(&session.upstream_url).await? else {
+                    return Ok(media_server::status(status));
+                };
+                let uris = Self::playlist_uris(&master, &master_base);
+                let Some(fresh) = Self::closest_uri(target, &uris) else {
+                    return Ok(media_server::status(status));
+                };
+                match self.fetch_playlist(fresh).await? {
+                    Ok(playlist) => playlist,
+                    Err(status) => return Ok(media_server::status(status)),
+                }
+            }
+            Err(status) => return Ok(media_server::status(status)),
+        };
+
+        Ok(Self::playlist_response(self.rewrite_media(&text, &base)))
+    }
+
+    /// Proxies a segment, key or init section, passing `Range` through
+    async fn segment(
+        &self,
+        id: &str,
+        session: RelaySession,
+        target: &str,
+        request: &Request<Incoming>,
+    ) -> Result<Response<Body>> {
+        let method = if request.method() == Method::HEAD {
+            reqwest::Method::HEAD
+        } else {
+            reqwest::Method::GET
+        };
+        let mut upstream = self.client.request(method, target);
+        if let Some(range) = request.headers().get(RANGE) {
+            upstream = upstream.header(RANGE, range);
+        }
+        let response = upstream.send().await?;
+
+        let status = response.status();
+        if Self::is_expired(status) {
+            // This segment is lost, but the next playlist poll gets fresh URLs
+            self.logger
+                .log_warning(&format!("Relay segment rejected upstream ({}), re-resolving", status));
+            self.refresh(id, &session).await?;
+        }
+
+        let headers: Vec<_> = [CONTENT_TYPE, CONTENT_LENGTH, CONTENT_RANGE, ACCEPT_RANGES]
+            .into_iter()
+            .filter_map(|name| response.headers().get(&name).cloned().map(|value| (name, value)))
+            .collect();
+        let stream = response.bytes_stream().map_err(std::io::Error::other).map_ok(Frame::data);
+
+        let mut relayed = Response::new(BodyExt::boxed(StreamBody::new(stream)));
+        *relayed.status_mut() = status;
+        relayed.headers_mut().extend(headers);
+        Ok(relayed)
+    }
+
+    /// Re-resolves the session's source URL, unless another request already did
+    async fn refresh(&self, id: &str, stale: &RelaySession) -> Result<RelaySession> {
+        let _guard = self.refresh.lock().await;
+        if let Some(current) = self.sessions.get(id).filter(|s| s.upstream_url != stale.upstream_url) {
+            return Ok(current);
+        }
+
+        let invocation_id = Logger::new_invocation_id();
+        self.logger.log_info(&format!(
+            "Relay upstream for {} expired, re-resolving as {}",
+            stale.source_url, invocation_id
+        ));
+        let resolved = self.service.resolve_upstream(&stale.source_url, &stale.player);
+        let upstream_url = Logger::scope_invocation(&invocation_id, resolved).await?;
+        self.sessions.update(id, &upstream_url)?;
+
+        Ok(RelaySession {
+            upstream_url,
+            updated_at: Utc::now(),
+            ..stale.clone()
+        })
+    }
+
+    /// Fetches a playlist; HTTP errors are returned as the status so callers can react to expiry
+    async fn fetch_playlist(&self, url: &str) -> Result<std::result::Result<(String, Url), StatusCode>> {
+        let response = self.client.get(url).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            self.logger
+                .log_warning(&format!("Relay upstream playlist returned {}: {}", status, url));
+            return Ok(Err(status));
+        }
+
+        // Relative URIs resolve against the final URL, after redirects
+        let base = response.url().clone();
+        let text = response.text().await?;
+        if !text.trim_start().starts_with("#EXTM3U") {
+            return Err(AppError::Validation(format!("upstream response is not an HLS playlist: {}", url)));
+        }
+        Ok(Ok((text, base)))
+    }
+
+    /// Rewrites a master playlist, or flattens it to the selected variant's media playlist
+    async fn rewrite_master(&self, text: &str, base: &Url, quality: &QualityConfig) -> Result<String> {
+        let mut lines = Vec::new();
+        let mut variants = Vec::new();
+        let mut pending_tag: Option<String> = None;
+
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if LOW_LATENCY_TAGS.iter().any(|tag| line.starts_with(tag)) {
+                continue;
+            }
+            if line.starts_with("#EXT-X-STREAM-INF") {
+                pending_tag = Some(line.to_string());
+            } else if line.starts_with('#') {
+                // Renditions are playlists; session keys and the like are fetched as-is
+                let resource = if line.starts_with("#EXT-X-MEDIA") { "playlist.m3u8" } else { "segment" };
+                lines.push(Self::rewrite_uri_attribute(line, |uri| self.relay_uri(base, uri, resource)));
+            } else if let Some(tag) = pending_tag.take() {
+                variants.push(Variant {
+                    bandwidth: Self::attribute(&tag, "BANDWIDTH").and_then(|b| b.parse().ok()).unwrap_or(0),
+                    height: Self::attribute(&tag, "RESOLUTION")
+                        .and_then(|r| r.split_once('x').and_then(|(_, h)| h.parse().ok())),
+                    tag,
+                    uri: line.to_string(),
+                });
+            }
+        }
+
+        let selected = match self.config.relay.select_variant {
+            true => Self::select_variant(std::mem::take(&mut variants), quality),
+            false => None,
+        };
+        if let Some(variant) = selected {
+            self.logger.log_debug(&format!(
+                "Relay selected variant {}",
+                variant.tag.trim_start_matches("#EXT-X-STREAM-INF:")
+            ));
+
+            // Separate audio or subtitle renditions need the master playlist around them
+            let has_renditions =
+                Self::attribute(&variant.tag, "AUDIO").is_some() || Self::attribute(&variant.tag, "SUBTITLES").is_some();
+            if !has_renditions {
+                let url = base.join(&variant.uri).map_err(|e| AppError::Validation(e.to_string()))?;
+                return match self.fetch_playlist(url.as_str()).await? {
+                    Ok((text, base)) => Ok(self.rewrite_media(&text, &base)),
+                    Err(status) => Err(AppError::NetworkError(format!("variant playlist returned {}", status))),
+                };
+            }
+            variants = vec![variant];
+        }
+
+        for variant in variants {
+            lines.push(variant.tag);
+            lines.push(self.relay_uri(base, &variant.uri, "playlist.m3u8"));
+        }
+        Ok(Self::join(lines))
+    }
+
+    /// Highest bandwidth within the height limit, or the smallest variant if none fits
+    fn select_variant(mut variants: Vec<Variant>, quality: &QualityConfig) -> Option<Variant> {
+        let fits = |variant: &Variant| match (quality.max_height, variant.height) {
+            (Some(max), Some(height)) => height <= max,
+            _ => true,
+        };
+
+        let index = variants
+            .iter()
+            .enumerate()
+            .filter(|(_, variant)| fits(variant))
+            .max_by_key(|(_, variant)| variant.bandwidth)
+            .or_else(|| {
+                variants
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, variant)| (variant.height, variant.bandwidth))
+            })
+            .map(|(index, _)| index)?;
+        Some(variants.swap_remove(index))
+    }
+
+    /// Rewrites a media playlist so segments go through the relay, trimming live windows
+    fn rewrite_media(&self, text: &str, base: &Url) -> String {
+        let mut header = Vec::new();
+        let mut segments: Vec<Vec<String>> = Vec::new();
+        let mut current = Vec::new();
+        let mut ended = false;
+
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if LOW_LATENCY_TAGS.iter().any(|tag| line.starts_with(tag)) {
+                continue;
+            }
+            if line == "#EXT-X-ENDLIST" {
+                ended = true;
+            } else if !line.starts_with('#') {
+                current.push(line.to_string());
+                segments.push(std::mem::take(&mut current));
+            } else if current.is_empty() && segments.is_empty() && Self::is_playlist_tag(line) {
+                header.push(line.to_string());
+            } else {
+                current.push(line.to_string());
+            }
+        }
+
+        if !ended {
+            if let Some(max) = self.config.relay.max_live_segments {
+                Self::trim_window(&mut header, &mut segments, max);
+            }
+        }
+
+        let mut lines = header;
+        for line in segments.into_iter().flatten().chain(current) {
+            lines.push(if line.starts_with('#') {
+                Self::rewrite_uri_attribute(&line, |uri| self.relay_uri(base, uri, "segment"))
+            } else {
+                self.relay_uri(base, &line, "segment")
+            });
+        }
+        if ended {
+            lines.push("#EXT-X-ENDLIST".to_string());
+        }
+        Self::join(lines)
+    }
+
+    /// Drops the oldest segments of a live playlist beyond `max`, keeping the sequence
+    /// numbers, key and init section consistent with what was dropped
+    fn trim_window(header: &mut Vec<String>, segments: &mut Vec<Vec<String>>, max: usize) {
+        if max == 0 || segments.len() <= max {
+            return;
+        }
+
+        let dropped: Vec<Vec<String>> = segments.drain(..segments.len() - max).collect();
+        let dropped_lines = || dropped.iter().flatten();
+        let discontinuities = dropped_lines().filter(|line| *line == "#EXT-X-DISCONTINUITY").count();
+
+        // Keys and init sections carry over to later segments until replaced
+        let first = &mut segments[0];
+        for tag in ["#EXT-X-MAP", "#EXT-X-KEY"] {
+            if first.iter().any(|line| line.starts_with(tag)) {
+                continue;
+            }
+            if let Some(carried) = dropped_lines().rev().find(|line| line.starts_with(tag)) {
+                first.insert(0, carried.clone());
+            }
+        }
+
+        Self::bump_sequence(header, "#EXT-X-MEDIA-SEQUENCE:", dropped.len());
+        if discontinuities > 0 {
+            Self::bump_sequence(header, "#EXT-X-DISCONTINUITY-SEQUENCE:", discontinuities);
+        }
+    }
+
+    fn bump_sequence(header: &mut Vec<String>, tag: &str, by: usize) {
+        match header.iter_mut().find(|line| line.starts_with(tag)) {
+            Some(line) => {
+                let current: usize = line[tag.len()..].trim().parse().unwrap_or(0);
+                *line = format!("{}{}", tag, current + by);
+            }
+            None => header.push(format!("{}{}", tag, by)),
+        }
+    }
+
+    /// Every playlist URI in a master playlist, made absolute
+    fn playlist_uris(text: &str, base: &Url) -> Vec<String> {
+        let mut uris = Vec::new();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let uri = if line.starts_with('#') {
+                line.starts_with("#EXT-X-MEDIA").then(|| Self::attribute(line, "URI")).flatten()
+            } else {
+                Some(line.to_string())
+            };
+            if let Some(url) = uri.and_then(|uri| base.join(&uri).ok()) {
+                uris.push(url.to_string());
+            }
+        }
+        uris
+    }
+
+    /// The candidate sharing the most path segments with a stale URL
+    fn closest_uri<'u>(stale: &str, candidates: &'u [String]) -> Option<&'u String> {
+        let segments = |url: &str| -> Vec<String> {
+            Url::parse(url)
+                .ok()
+                .and_then(|u| u.path_segments().map(|s| s.map(str::to_string).collect()))
+                .unwrap_or_default()
+        };
+        let stale = segments(stale);
+
+        candidates
+            .iter()
+            .map(|candidate| {
+                let shared = segments(candidate).iter().filter(|s| stale.contains(s)).count();
+                (shared, candidate)
+            })
+            .filter(|(shared, _)| *shared > 0)
+            .max_by_key(|(shared, _)| *shared)
+            .map(|(_, candidate)| candidate)
+    }
+
+    fn is_master(text: &str) -> bool {
+        text.contains("#EXT-X-STREAM-INF")
+    }
+
+    fn is_playlist_tag(line: &str) -> bool {
+        let name = line.split(':').next().unwrap_or(line);
+        PLAYLIST_TAGS.contains(&name)
+    }
+
+    /// Signed URLs past their expiry are refused with one of these
+    fn is_expired(status: StatusCode) -> bool {
+        matches!(status, StatusCode::FORBIDDEN | StatusCode::GONE)
+    }
+
+    /// Relative relay URL for an upstream URI, resolved against the playlist it appeared in
+    fn relay_uri(&self, base: &Url, uri: &str, resource: &str) -> String {
+        let absolute = base.join(uri).map(|url| url.to_string()).unwrap_or_else(|_| uri.to_string());
+        format!(
+            "{}?u={}&s={}",
+            resource,
+            Self::percent_encode(&absolute),
+            Self::hex(&self.mac(&absolute).finalize().into_bytes())
+        )
+    }
+
+    fn mac(&self, target: &str) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as KeyInit>::new(&self.key.into());
+        mac.update(target.as_bytes());
+        mac
+    }
+
+    /// Checks a signature made by `relay_uri`, in constant time
+    fn verify(&self, target: &str, signature: &str) -> bool {
+        let bytes: Option<Vec<u8>> = (0..signature.len())
+            .step_by(2)
+            .map(|i| signature.get(i..i + 2).and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+            .collect();
+        bytes.is_some_and(|bytes| self.mac(target).verify_slice(&bytes).is_ok())
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Replaces the quoted `URI` attribute of a tag, if it has one
+    fn rewrite_uri_attribute(line: &str, rewrite: impl Fn(&str) -> String) -> String {
+        let Some(start) = line.find("URI=\"").map(|i| i + 5) else {
+            return line.to_string();
+        };
+        let Some(len) = line[start..].find('"') else {
+            return line.to_string();
+        };
+        format!("{}{}{}", &line[..start], rewrite(&line[start..start + len]), &line[start + len..])
+    }
+
+    /// Reads an attribute from a tag's comma-separated list, honouring quoted values
+    fn attribute(tag: &str, name: &str) -> Option<String> {
+        let (_, list) = tag.split_once(':')?;
+        let mut in_quotes = false;
+        list.split(|c| {
+            if c == '"' {
+                in_quotes = !in_quotes;
+            }
+            c == ',' && !in_quotes
+        })
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| key.trim() == name)
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+    }
+
+    fn percent_encode(value: &str) -> String {
+        value
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+                _ => format!("%{:02X}", b),
+            })
+            .collect()
+    }
+
+    fn join(lines: Vec<String>) -> String {
+        let mut text = lines.join("\n");
+        text.push('\n');
+        text
+    }
+
+    fn playlist_response(body: String) -> Response<Body> {
+        let length = body.len();
+        let mut response = Response::new(Full::new(Bytes::from(body)).map_err(|never| match never {}).boxed());
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(MPEGURL));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+        // Live playlists change on every poll
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper_util::rt::TokioIo;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::logger::{LogConfig, Logger};
+    use crate::media_server::MediaServer;
+    use crate::models::ScriptedResponse;
+    use crate::resolver::{ResolverContext, ScriptedResolver};
+
+    const MASTER: &str = "#EXTM3U
+#EXT-X-INDEPENDENT-SEGMENTS
+#EXT-X-STREAM-INF:BANDWIDTH=6000000,RESOLUTION=1920x1080
+v/1080.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=3000000,RESOLUTION=1280x720
+v/720.m3u8
+#EXT-X-I-FRAME-STREAM-INF:BANDWIDTH=100000,URI=\"v/iframe.m3u8\"
+";
+
+    const LIVE: &str = "#EXTM3U
+#EXT-X-VERSION:6
+#EXT-X-TARGETDURATION:2
+#EXT-X-MEDIA-SEQUENCE:100
+#EXT-X-KEY:METHOD=AES-128,URI=\"key1\"
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:2.0,
+s100.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:2.0,
+s101.ts
+#EXTINF:2.0,
+s102.ts
+#EXT-X-PART:DURATION=0.5,URI=\"p103.ts\"
+#EXTINF:2.0,
+s103.ts
+";
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc-ytdlp-relay-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Serves fixed responses by path
+    async fn fixture(routes: &[(&str, u16, &str)]) -> String {
+        let routes: Arc<HashMap<String, (u16, String)>> = Arc::new(
+            routes
+                .iter()
+                .map(|(path, status, body)| (path.to_string(), (*status, body.to_string())))
+                .collect(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let routes = routes.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request: Request<Incoming>| {
+                        let (status, body) = routes
+                            .get(request.uri().path())
+                            .cloned()
+                            .unwrap_or((404, String::new()));
+                        async move {
+                            let mut response = Response::new(Full::new(Bytes::from(body)));
+                            *response.status_mut() = StatusCode::from_u16(status).unwrap();
+                            Ok::<_, Infallible>(response)
+                        }
+                    });
+                    let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await;
+                });
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    /// A relay whose re-resolutions return `fresh_url`
+    fn relay(dir: &Path, max_live_segments: Option<usize>, fresh_url: &str) -> HlsRelay {
+        let mut config = AppConfig {
+            resolvers: vec![ScriptedResolver::NAME.to_string()],
+            ..Default::default()
+        };
+        config.scripted.responses = vec![ScriptedResponse {
+            pattern: "stream.example".to_string(),
+            url: Some(fresh_url.to_string()),
+            ..Default::default()
+        }];
+        config.quality.max_height = Some(720);
+        config.relay.enabled = true;
+        config.relay.max_live_segments = max_live_segments;
+
+        let logger = Logger::with_config(dir.join("test.log"), LogConfig::default());
+        let context = ResolverContext {
+            app_dir: dir.to_path_buf(),
+            ytdlp_path: dir.join("yt-dlp.exe"),
+            streamlink_path: dir.join("streamlink.exe"),
+            concurrent: true,
+            cache: Arc::new(ResolutionCache::new(dir.to_path_buf(), 60)),
+            logger: logger.clone(),
+        };
+        let service = Arc::new(ResolveService::new(config, context).unwrap());
+        HlsRelay::new(service, dir, logger).unwrap()
+    }
+
+    /// Runs a media server with the relay and returns its port
+    async fn serve(dir: &Path, relay: HlsRelay) -> u16 {
+        let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let config = crate::models::LibraryConfig {
+            server_port: port,
+            ..Default::default()
+        };
+        let logger = Logger::with_config(dir.join("test.log"), LogConfig::default());
+        tokio::spawn(MediaServer::new(&config, dir, logger).with_relay(relay).run());
+
+        for _ in 0..50 {
+            if TcpStream::connect(SocketAddr::from(([127, 0, 0, 1], port))).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        port
+    }
+
+    async fn get(url: &str) -> (StatusCode, String) {
+        let response = reqwest::get(url).await.unwrap();
+        (response.status(), response.text().await.unwrap())
+    }
+
+    /// The upstream URL a relay URI points at
+    fn upstream(relay_uri: &str) -> String {
+        Url::parse(&format!("http://relay/{}", relay_uri))
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == "u")
+            .map(|(_, value)| value.into_owned())
+            .unwrap()
+    }
+
+    fn uris(playlist: &str) -> Vec<&str> {
+        playlist.lines().filter(|line| !line.starts_with('#')).collect()
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn trim_window_bumps_sequences_and_carries_key_and_map() {
+        let mut header = lines("#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:100");
+        let mut segments = vec![
+            lines("#EXT-X-KEY:METHOD=AES-128,URI=\"key1\"\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:2.0,\ns100.ts"),
+            lines("#EXT-X-DISCONTINUITY\n#EXTINF:2.0,\ns101.ts"),
+            lines("#EXTINF:2.0,\ns102.ts"),
+            lines("#EXTINF:2.0,\ns103.ts"),
+        ];
+
+        HlsRelay::trim_window(&mut header, &mut segments, 2);
+
+        assert_eq!(
+            header,
+            ["#EXTM3U", "#EXT-X-MEDIA-SEQUENCE:102", "#EXT-X-DISCONTINUITY-SEQUENCE:1"]
+        );
+        assert_eq!(segments.len(), 2);
+        assert_eq!(
+            segments[0],
+            [
+                "#EXT-X-KEY:METHOD=AES-128,URI=\"key1\"",
+                "#EXT-X-MAP:URI=\"init.mp4\"",
+                "#EXTINF:2.0,",
+                "s102.ts"
+            ]
+        );
+    }
+
+    #[test]
+    fn trim_window_keeps_short_playlists() {
+        let mut header = lines("#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:7");
+        let mut segments = vec![lines("#EXTINF:2.0,\ns7.ts")];
+
+        HlsRelay::trim_window(&mut header, &mut segments, 2);
+
+        assert_eq!(header, ["#EXTM3U", "#EXT-X-MEDIA-SEQUENCE:7"]);
+        assert_eq!(segments.len(), 1);
+    }
+
+    fn variant(bandwidth: u64, height: Option<u32>) -> Variant {
+        Variant {
+            tag: format!("#EXT-X-STREAM-INF:BANDWIDTH={}", bandwidth),
+            uri: format!("{}.m3u8", bandwidth),
+            bandwidth,
+            height,
+        }
+    }
+
+    #[test]
+    fn select_variant_prefers_highest_bandwidth_within_height() {
+        let quality = QualityConfig {
+            max_height: Some(720),
+            ..Default::default()
+        };
+        let variants = vec![variant(6000, Some(1080)), variant(3000, Some(720)), variant(1000, Some(480))];
+
+        let selected = HlsRelay::select_variant(variants, &quality).unwrap();
+        assert_eq!(selected.bandwidth, 3000);
+    }
+
+    #[test]
+    fn select_variant_falls_back_to_smallest() {
+        let quality = QualityConfig {
+            max_height: Some(360),
+            ..Default::default()
+        };
+        let variants = vec![variant(6000, Some(1080)), variant(1000, Some(480))];
+
+        let selected = HlsRelay::select_variant(variants, &quality).unwrap();
+        assert_eq!(selected.bandwidth, 1000);
+        assert!(HlsRelay::select_variant(Vec::new(), &quality).is_none());
+    }
+
+    #[test]
+    fn closest_uri_matches_variant_path() {
+        let fresh = vec![
+            "https://cdn.example/token-new/v/1080.m3u8".to_string(),
+            "https://cdn.example/token-new/v/720.m3u8".to_string(),
+        ];
+
+        let closest = HlsRelay::closest_uri("https://cdn.example/token-old/v/720.m3u8?x=1", &fresh);
+        assert_eq!(closest, Some(&fresh[1]));
+        assert_eq!(HlsRelay::closest_uri("https://other.example/", &fresh), None);
+    }
+
+    #[tokio::test]
+    async fn rewrite_media_signs_relative_uris_and_trims_live_windows() {
+        let dir = test_dir("rewrite");
+        let relay = relay(&dir, Some(2), "http://unused.example/");
+        let base = Url::parse("https://cdn.example/live/720.m3u8?token=abc").unwrap();
+
+        let text = relay.rewrite_media(LIVE, &base);
+
+        assert!(text.contains("#EXT-X-MEDIA-SEQUENCE:102"));
+        assert!(text.contains("#EXT-X-DISCONTINUITY-SEQUENCE:1"));
+        assert!(!text.contains("#EXT-X-PART"));
+        assert!(text.contains("#EXT-X-KEY:METHOD=AES-128,URI=\"segment?u=https%3A%2F%2Fcdn.example%2Flive%2Fkey1&s="));
+        assert!(text.contains("#EXT-X-MAP:URI=\"segment?u=https%3A%2F%2Fcdn.example%2Flive%2Finit.mp4&s="));
+
+        let segments = uris(&text);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(upstream(segments[0]), "https://cdn.example/live/s102.ts");
+        let signature = segments[0].rsplit_once("&s=").unwrap().1;
+        assert!(relay.verify("https://cdn.example/live/s102.ts", signature));
+        assert!(!relay.verify("https://cdn.example/live/s103.ts", signature));
+    }
+
+    #[tokio::test]
+    async fn relays_flattened_variant_and_refuses_unsigned_targets() {
+        let upstream_base = fixture(&[("/master.m3u8", 200, MASTER), ("/v/720.m3u8", 200, LIVE), ("/v/s103.ts", 200, "data")]).await;
+        let dir = test_dir("flatten");
+        let relay = relay(&dir, Some(2), "http://unused.example/");
+        let id = relay
+            .sessions
+            .register("https://stream.example/live", &format!("{}/master.m3u8", upstream_base), "avpro")
+            .unwrap();
+        let port = serve(&dir, relay).await;
+        let index = HlsRelay::url_for(port, &id);
+
+        // The 720p variant fits the height limit and has no renditions, so it replaces the master
+        let (status, text) = get(&index).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!text.contains("#EXT-X-STREAM-INF"));
+        let segments = uris(&text);
+        assert_eq!(upstream(segments[1]), format!("{}/v/s103.ts", upstream_base));
+
+        let (status, body) = get(&format!("http://127.0.0.1:{}/hls/{}/{}", port, id, segments[1])).await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "data"));
+
+        let unsigned = format!("http://127.0.0.1:{}/hls/{}/segment?u=http%3A%2F%2F10.0.0.1%2F", port, id);
+        assert_eq!(get(&unsigned).await.0, StatusCode::FORBIDDEN);
+        let forged = format!("{}&s={}", unsigned, "00".repeat(32));
+        assert_eq!(get(&forged).await.0, StatusCode::FORBIDDEN);
+        assert_eq!(get(&HlsRelay::url_for(port, "unknown")).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn re_resolves_expired_manifests() {
+        let upstream_base = fixture(&[
+            ("/old/master.m3u8", 403, ""),
+            ("/old/v/720.m3u8", 403, ""),
+            ("/new/master.m3u8", 200, MASTER),
+            ("/new/v/720.m3u8", 200, LIVE),
+        ])
+        .await;
+        let dir = test_dir("expired");
+        let relay = relay(&dir, None, &format!("{}/new/master.m3u8", upstream_base));
+        let sessions = RelaySessions::new(&dir);
+        let id = relay
+            .sessions
+            .register("https://stream.example/live", &format!("{}/old/master.m3u8", upstream_base), "avpro")
+            .unwrap();
+        let stale_playlist = relay.relay_uri(
+            &Url::parse(&format!("{}/old/master.m3u8", upstream_base)).unwrap(),
+            "v/720.m3u8",
+            "playlist.m3u8",
+        );
+        let port = serve(&dir, relay).await;
+
+        // The expired master is replaced by the re-resolved one
+        let (status, text) = get(&HlsRelay::url_for(port, &id)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(uris(&text).len(), 4);
+        assert_eq!(
+            sessions.get(&id).unwrap().upstream_url,
+            format!("{}/new/master.m3u8", upstream_base)
+        );
+
+        // A player still polling the old variant gets its counterpart from the new master
+        let (status, text) = get(&format!("http://127.0.0.1:{}/hls/{}/{}", port, id, stale_playlist)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(upstream(uris(&text)[0]), format!("{}/new/v/s100.ts", upstream_base));
+    }
+
+    #[test]
+    fn concurrent_registrations_keep_every_session() {
+        let dir = test_dir("sessions");
+
+        // Separate instances stand in for the shim, the daemon and the media server
+        let writers: Vec<_> = (0..16)
+            .map(|i| {
+                let dir = dir.clone();
+                std::thread::spawn(move || {
+                    RelaySessions::new(&dir)
+                        .register(&format!("https://stream.example/{}", i), "https://cdn.example/live.m3u8", "avpro")
+                        .unwrap()
+                })
+            })
+            .collect();
+        let ids: Vec<String> = writers.into_iter().map(|writer| writer.join().unwrap()).collect();
+
+        let sessions = RelaySessions::new(&dir);
+        assert_eq!(sessions.load().len(), 16);
+        assert!(ids.iter().all(|id| sessions.get(id).is_some()));
+    }
+}
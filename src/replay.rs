@@ -0,0 +1,275 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, Utc};
+
+use crate::constants::YT_DLP_EXECUTABLE;
+use crate::downloader::Downloader;
+use crate::error::{AppError, Result};
+use crate::history::HistoryRecord;
+use crate::logger::Level;
+use crate::models::AppConfig;
+use crate::resolver::{CacheResolver, ResolverContext};
+use crate::service::ResolveService;
+
+/// One line of an argument diff
+#[derive(Debug, PartialEq, Eq)]
+pub enum ArgDiff<'a> {
+    Same(&'a str),
+    /// Only in the recorded run
+    Removed(&'a str),
+    /// Only in the replay
+    Added(&'a str),
+}
+
+/// Re-runs a recorded invocation against the current configuration for debugging.
+///
+/// Replays run in-process with debug logging forced on. The cache, history and relay
+/// are turned off, so a replay neither answers from nor leaves behind any state.
+pub struct Replay {
+    config: AppConfig,
+    context: ResolverContext,
+}
+
+impl Replay {
+    pub fn new(config: &AppConfig, context: ResolverContext) -> Self {
+        let mut config = config.clone();
+        let mut context = context;
+        context.logger = context.logger.with_level_at_least(Level::Debug);
+        config.resolvers.retain(|name| name != CacheResolver::NAME);
+        config.history.enabled = false;
+        config.relay.enabled = false;
+
+        Self { config, context }
+    }
+
+    /// Finds a record by `last`, by invocation ID, or by a timestamp as printed by `history`
+    /// or found in the log.
+    ///
+    /// A timestamp covers its whole precision, e.g. `21:04` is that minute today;
+    /// the latest record within it wins.
+    pub fn find(mut records: Vec<HistoryRecord>, selector: &str) -> Result<HistoryRecord> {
+        if selector == "last" {
+            return records
+                .pop()
+                .ok_or_else(|| AppError::Config("The history is empty".to_string()));
+        }
+        if let Some(index) = records
+            .iter()
+            .rposition(|record| record.invocation_id.as_deref() == Some(selector))
+        {
+            return Ok(records.swap_remove(index));
+        }
+
+        let (start, precision) = Self::parse_timestamp(selector)?;
+        records
+            .into_iter()
+            .rev()
+            .find(|record| record.timestamp >= start && record.timestamp < start + precision)
+            .ok_or_else(|| AppError::Config(format!("No history entry at {}", selector)))
+    }
+
+    /// Parses a local timestamp into its start and precision
+    fn parse_timestamp(value: &str) -> Result<(DateTime<Utc>, chrono::Duration)> {
+        // Log lines wrap the timestamp in brackets
+        let value = value.trim().trim_start_matches('[').trim_end_matches(']');
+        let invalid = || AppError::Config(format!("Invalid timestamp: {}", value));
+
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+            return Ok((timestamp.with_timezone(&Utc), chrono::Duration::milliseconds(1)));
+        }
+
+        let second = chrono::Duration::seconds(1);
+        let minute = chrono::Duration::minutes(1);
+        let date_times = [
+            ("%Y-%m-%d %H:%M", minute),
+            ("%Y-%m-%d %H:%M:%S", second),
+            ("%Y-%m-%d %H:%M:%S%.3f", chrono::Duration::milliseconds(1)),
+        ];
+        for (format, precision) in date_times {
+            if let Ok(naive) = NaiveDateTime::parse_from_str(value, format) {
+                return Self::local(naive).map(|start| (start, precision)).ok_or_else(invalid);
+            }
+        }
+
+        // A bare time is the most recent occurrence, today or yesterday
+        for (format, precision) in [("%H:%M", minute), ("%H:%M:%S", second)] {
+            if let Ok(time) = NaiveTime::parse_from_str(value, format) {
+                let today = Local::now().date_naive();
+                let start = Self::local(today.and_time(time)).ok_or_else(invalid)?;
+                let start = if start > Utc::now() {
+                    start - chrono::Duration::days(1)
+                } else {
+                    start
+                };
+                return Ok((start, precision));
+            }
+        }
+
+        Err(invalid())
+    }
+
+    fn local(naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+        naive
+            .and_local_timezone(Local)
+            .earliest()
+            .map(|local| local.with_timezone(&Utc))
+    }
+
+    /// Runs the recorded arguments again, with the current yt-dlp unless another executable is given
+    pub async fn run(
+        &self,
+        record: &HistoryRecord,
+        ytdlp_path: Option<PathBuf>,
+    ) -> Result<(Result<Vec<String>>, HistoryRecord)> {
+        let mut context = self.context.clone();
+        if let Some(path) = ytdlp_path {
+            context.ytdlp_path = path;
+        }
+
+        // The player was detected from VRChat's log at the time, which may have moved on since;
+        // profiles removed from the config since are detected again
+        let player = record
+            .player
+            .clone()
+            .filter(|player| self.config.players.profile(player).is_some());
+        let service = ResolveService::new(self.config.clone(), context)?.with_player(player);
+        Ok(service.handle_recorded(&record.args).await)
+    }
+
+    /// Installs the yt-dlp version that was active when the record was made, alongside the
+    /// current executable, and returns its path
+    pub async fn install_recorded_version(&self, record: &HistoryRecord) -> Result<PathBuf> {
+        let version = record
+            .ytdlp_version
+            .as_deref()
+            .ok_or_else(|| AppError::Config("The history entry has no yt-dlp version".to_string()))?;
+        // The version becomes a directory name
+        if !version.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')) {
+            return Err(AppError::Config(format!("Invalid yt-dlp version: {}", version)));
+        }
+
+        let path = self
+            .context
+            .ytdlp_path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("versions")
+            .join(version)
+            .join(YT_DLP_EXECUTABLE);
+        Downloader::new(path.clone(), self.context.logger.clone())
+            .install_version(version)
+            .await?;
+        Ok(path)
+    }
+
+    /// Line diff of two argument lists, by longest common subsequence
+    pub fn diff_args<'a>(then: &'a [String], now: &'a [String]) -> Vec<ArgDiff<'a>> {
+        // common[i][j]: length of the common subsequence of then[i..] and now[j..]
+        let mut common = vec![vec![0usize; now.len() + 1]; then.len() + 1];
+        for i in (0..then.len()).rev() {
+            for j in (0..now.len()).rev() {
+                common[i][j] = if then[i] == now[j] {
+                    common[i + 1][j + 1] + 1
+                } else {
+                    common[i + 1][j].max(common[i][j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        let mut diff = Vec::new();
+        while i < then.len() || j < now.len() {
+            if i < then.len() && j < now.len() && then[i] == now[j] {
+                diff.push(ArgDiff::Same(&then[i]));
+                i += 1;
+                j += 1;
+            } else if i < then.len() && (j == now.len() || common[i + 1][j] >= common[i][j + 1]) {
+                diff.push(ArgDiff::Removed(&then[i]));
+                i += 1;
+            } else {
+                diff.push(ArgDiff::Added(&now[j]));
+                j += 1;
+            }
+        }
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone};
+
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn diff_keeps_common_args_in_order() {
+        let then = args(&["-f", "best", "--no-warnings", "URL"]);
+        let now = args(&["-f", "bv*+ba", "--no-warnings", "--no-playlist", "URL"]);
+
+        assert_eq!(
+            Replay::diff_args(&then, &now),
+            vec![
+                ArgDiff::Same("-f"),
+                ArgDiff::Removed("best"),
+                ArgDiff::Added("bv*+ba"),
+                ArgDiff::Same("--no-warnings"),
+                ArgDiff::Added("--no-playlist"),
+                ArgDiff::Same("URL"),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_handles_empty_sides() {
+        let some = args(&["-J", "URL"]);
+
+        assert!(Replay::diff_args(&[], &[]).is_empty());
+        assert_eq!(
+            Replay::diff_args(&some, &[]),
+            vec![ArgDiff::Removed("-J"), ArgDiff::Removed("URL")]
+        );
+        assert_eq!(
+            Replay::diff_args(&[], &some),
+            vec![ArgDiff::Added("-J"), ArgDiff::Added("URL")]
+        );
+    }
+
+    #[test]
+    fn timestamps_carry_their_precision() {
+        let (start, precision) = Replay::parse_timestamp("2026-01-02T03:04:05.678Z").unwrap();
+        assert_eq!(start, Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap() + chrono::Duration::milliseconds(678));
+        assert_eq!(precision, chrono::Duration::milliseconds(1));
+
+        let date = NaiveDate::from_ymd_opt(2026, 1, 2).unwrap();
+        for (value, time, expected) in [
+            ("2026-01-02 03:04", (3, 4, 0, 0), chrono::Duration::minutes(1)),
+            ("[2026-01-02 03:04:05]", (3, 4, 5, 0), chrono::Duration::seconds(1)),
+            ("2026-01-02 03:04:05.678", (3, 4, 5, 678), chrono::Duration::milliseconds(1)),
+        ] {
+            let (h, m, s, ms) = time;
+            let naive = date.and_hms_milli_opt(h, m, s, ms).unwrap();
+            let (start, precision) = Replay::parse_timestamp(value).unwrap();
+            assert_eq!(start, Replay::local(naive).unwrap(), "{}", value);
+            assert_eq!(precision, expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn bare_times_are_never_in_the_future() {
+        let (start, precision) = Replay::parse_timestamp("23:59:59").unwrap();
+        assert!(start <= Utc::now());
+        assert!(Utc::now() - start < chrono::Duration::days(1));
+        assert_eq!(precision, chrono::Duration::seconds(1));
+    }
+
+    #[test]
+    fn invalid_timestamps_are_rejected() {
+        for value in ["", "yesterday", "2026-01-02", "25:00", "2026-02-30 10:00"] {
+            assert!(Replay::parse_timestamp(value).is_err(), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::cache::ResolutionCache;
+use crate::error::Result;
+use crate::logger::Logger;
+use crate::models::AppConfig;
+use crate::policy::ContentPolicy;
+
+use super::{ResolveRequest, Resolution, Resolver, ResolverContext};
+
+/// Serves recently resolved URLs and remembers new resolutions from other resolvers
+pub struct CacheResolver {
+    cache: Arc<ResolutionCache>,
+    enabled: bool,
+    /// Recorded with new entries so a changed content policy checks them again
+    content_policy: Option<String>,
+    logger: Logger,
+}
+
+impl CacheResolver {
+    pub const NAME: &'static str = "cache";
+
+    pub fn new(config: &AppConfig, context: &ResolverContext) -> Self {
+        Self {
+            cache: context.cache.clone(),
+            enabled: config.cache.enabled,
+            content_policy: ContentPolicy::new(&config.content_policy).fingerprint(),
+            logger: context.logger.for_module(module_path!()),
+        }
+    }
+}
+
+#[async_trait]
+impl Resolver for CacheResolver {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn needs_content_check(&self) -> bool {
+        false
+    }
+
+    async fn resolve(&self, request: &ResolveRequest) -> Result<Option<Resolution>> {
+        if !self.enabled || request.bypass_cache {
+            return Ok(None);
+        }
+
+        let Some(entry) = self.cache.get(&request.cache_key()) else {
+            self.logger.log_debug("Cache miss");
+            return Ok(None);
+        };
+        // Entries resolved under other content rules must pass the current ones first
+        if self.content_policy.is_some() && entry.content_policy != self.content_policy {
+            self.logger.log_debug("Cached resolution predates the content policy, resolving again");
+            return Ok(None);
+        }
+
+        self.logger.log_info(&format!(
+            "Cache hit (resolved {}, expires {})",
+            entry.resolved_at.format("%H:%M:%S"),
+            entry.expires_at.format("%H:%M:%S")
+        ));
+
+        Ok(Some(Resolution {
+            url: entry.url,
+            resolver: Self::NAME,
+        }))
+    }
+
+    async fn on_resolved(&self, request: &ResolveRequest, resolution: &Resolution) {
+        // Passthrough URLs are free to resolve, cache hits are already stored and
+        // library URLs must stop being served once the file is evicted
+        let skipped = [Self::NAME, super::PassthroughResolver::NAME, super::LibraryResolver::NAME];
+        if !self.enabled || skipped.contains(&resolution.resolver) {
+            return;
+        }
+
+        match self
+            .cache
+            .insert(&request.cache_key(), &resolution.url, self.content_policy.clone()) {
+            Ok(entry) => self.logger.log_debug(&format!(
+                "Cached resolution until {}",
+                entry.expires_at.format("%Y-%m-%d %H:%M:%S")
+            )),
+            Err(e) => self.logger.log_warning(&format!("Failed to write cache: {}", e)),
+        }
+    }
+}
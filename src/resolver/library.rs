@@ -0,0 +1,56 @@
+use async_trait::async_trait;
+
+use crate::error::Result;
+use crate::library::MediaLibrary;
+use crate::logger::Logger;
+use crate::media_server::MediaServer;
+use crate::models::AppConfig;
+
+use super::{ResolveRequest, Resolution, Resolver, ResolverContext};
+
+/// Serves downloaded library copies from the local media server
+pub struct LibraryResolver {
+    library: MediaLibrary,
+    port: u16,
+    logger: Logger,
+}
+
+impl LibraryResolver {
+    pub const NAME: &'static str = "library";
+
+    pub fn new(config: &AppConfig, context: &ResolverContext) -> Self {
+        Self {
+            library: MediaLibrary::new(&config.library, &context.app_dir, context.logger.clone()),
+            port: config.library.server_port,
+            logger: context.logger.for_module(module_path!()),
+        }
+    }
+}
+
+#[async_trait]
+impl Resolver for LibraryResolver {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn needs_content_check(&self) -> bool {
+        false
+    }
+
+    async fn resolve(&self, request: &ResolveRequest) -> Result<Option<Resolution>> {
+        let Some(entry) = self.library.get(&request.url) else {
+            return Ok(None);
+        };
+
+        self.logger.log_info(&format!("Library copy found: {}", entry.file_name));
+        MediaServer::ensure_running(self.port, &self.logger).await?;
+        if let Err(e) = self.library.touch(&request.url) {
+            self.logger.log_warning(&format!("Failed to update library index: {}", e));
+        }
+
+        Ok(Some(Resolution {
+            url: MediaServer::url_for(self.port, &entry.file_name),
+            resolver: Self::NAME,
+        }))
+    }
+}
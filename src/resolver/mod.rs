@@ -0,0 +1,475 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::cache::ResolutionCache;
+use crate::deadline::Deadline;
+use crate::error::{AppError, Result};
+use crate::format::MediaInfo;
+use crate::history::InvocationTrace;
+use crate::logger::Logger;
+use crate::models::AppConfig;
+
+pub mod cache;
+pub mod library;
+pub mod passthrough;
+pub mod scripted;
+pub mod streamlink;
+pub mod ytdlp;
+
+pub use cache::CacheResolver;
+pub use library::LibraryResolver;
+pub use passthrough::PassthroughResolver;
+pub use scripted::ScriptedResolver;
+pub use streamlink::StreamlinkResolver;
+pub use ytdlp::YtDlpResolver;
+
+/// A single resolution request as received from VRChat
+#[derive(Clone)]
+pub struct ResolveRequest {
+    /// Arguments VRChat passed to the shim
+    pub args: Vec<String>,
+    /// Media URL found among the arguments
+    pub url: String,
+    /// Budget shared by every resolver the request passes through
+    pub deadline: Deadline,
+    /// Skip cached resolutions, e.g. to refresh an entry that is about to expire
+    pub bypass_cache: bool,
+    /// Player profile the resolution must be playable by
+    pub player: String,
+    /// Collects strategy, arguments and timings for the history record
+    pub trace: InvocationTrace,
+    /// `-J` metadata already fetched for the content check, reused for format selection
+    pub metadata: Option<Arc<MediaInfo>>,
+}
+
+impl ResolveRequest {
+    /// Key under which the resolution is cached; each player gets its own entry
+    pub fn cache_key(&self) -> String {
+        format!("{}|{}", self.player, self.url)
+    }
+}
+
+/// A playable URL produced by a resolver
+#[derive(Clone)]
+pub struct Resolution {
+    pub url: String,
+    /// Name of the resolver that produced the URL
+    pub resolver: &'static str,
+}
+
+/// A backend that can turn a requested URL into something VRChat can play
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    /// Name used in logs and in the `resolvers` config list
+    fn name(&self) -> &'static str;
+
+    /// Resolves the request, or returns `Ok(None)` when this backend does not handle it
+    async fn resolve(&self, request: &ResolveRequest) -> Result<Option<Resolution>>;
+
+    /// Called on every resolver once the pipeline produced a resolution
+    async fn on_resolved(&self, _request: &ResolveRequest, _resolution: &Resolution) {}
+
+    /// Whether the pipeline may try the next resolver after this one failed
+    fn allows_fallback(&self) -> bool {
+        true
+    }
+
+    /// Whether the content policy must pass before this resolver runs; resolvers
+    /// serving direct links, cached or local copies don't extract anything
+    fn needs_content_check(&self) -> bool {
+        true
+    }
+}
+
+/// Checks a request against the content policy before a resolver extracts it
+#[async_trait]
+pub trait ContentCheck: Send + Sync {
+    /// Returns the metadata fetched for the check, if any, for the resolvers to reuse
+    async fn check(&self, request: &ResolveRequest) -> Result<Option<Arc<MediaInfo>>>;
+}
+
+/// Paths and logging shared by the resolvers built from configuration
+#[derive(Clone)]
+pub struct ResolverContext {
+    pub app_dir: PathBuf,
+    pub ytdlp_path: PathBuf,
+    pub streamlink_path: PathBuf,
+    /// Whether several executions may run at once (daemon mode)
+    pub concurrent: bool,
+    /// Resolution cache shared by the cache resolver and prefetching
+    pub cache: Arc<ResolutionCache>,
+    pub logger: Logger,
+}
+
+/// Ordered list of resolvers; the first one that handles a request wins
+pub struct ResolverPipeline {
+    resolvers: Vec<Box<dyn Resolver>>,
+    logger: Logger,
+}
+
+impl ResolverPipeline {
+    pub fn new(logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
+        Self {
+            resolvers: Vec::new(),
+            logger,
+        }
+    }
+
+    /// Builds the pipeline listed in `config.resolvers`
+    pub fn from_config(config: &AppConfig, context: &ResolverContext) -> Result<Self> {
+        let mut pipeline = Self::new(context.logger.clone());
+
+        for name in &config.resolvers {
+            let resolver: Box<dyn Resolver> = match name.as_str() {
+                PassthroughResolver::NAME => {
+                    Box::new(PassthroughResolver::new(config.passthrough.clone(), context.logger.clone()))
+                }
+                CacheResolver::NAME => Box::new(CacheResolver::new(config, context)),
+                LibraryResolver::NAME => Box::new(LibraryResolver::new(config, context)),
+                ScriptedResolver::NAME => {
+                    Box::new(ScriptedResolver::new(config.scripted.clone(), context.logger.clone()))
+                }
+                StreamlinkResolver::NAME => Box::new(StreamlinkResolver::new(config, context)),
+                YtDlpResolver::NAME => Box::new(YtDlpResolver::new(config.clone(), context)),
+                other => {
+                    return Err(AppError::Config(format!("Unknown resolver in config: {}", other)));
+                }
+            };
+            pipeline.push(resolver);
+        }
+
+        Ok(pipeline)
+    }
+
+    /// Appends a resolver to the end of the pipeline
+    pub fn push(&mut self, resolver: Box<dyn Resolver>) {
+        self.resolvers.push(resolver);
+    }
+
+    /// Runs the request through the resolvers in order
+    pub async fn resolve(&self, request: &ResolveRequest) -> Result<Resolution> {
+        self.resolve_checked(request, None).await
+    }
+
+    /// Runs the request through the resolvers in order, running the content check
+    /// once, just before the first resolver that needs it
+    pub async fn resolve_checked(
+        &self,
+        request: &ResolveRequest,
+        content_check: Option<&dyn ContentCheck>,
+    ) -> Result<Resolution> {
+        let mut last_error = None;
+        let mut checked: Option<ResolveRequest> = None;
+
+        for resolver in &self.resolvers {
+            if request.deadline.is_expired() {
+                return Err(last_error.unwrap_or_else(|| {
+                    AppError::Execution("Request deadline exhausted before resolution".to_string())
+                }));
+            }
+
+            if let (Some(content_check), None) = (content_check, &checked) {
+                if resolver.needs_content_check() {
+                    checked = Some(ResolveRequest {
+                        metadata: content_check.check(request).await?,
+                        ..request.clone()
+                    });
+                }
+            }
+            let request = checked.as_ref().unwrap_or(request);
+
+            match resolver.resolve(request).await {
+                Ok(Some(resolution)) => {
+                    self.logger
+                        .log_info(&format!("Resolved by {}", resolution.resolver));
+                    for other in &self.resolvers {
+                        other.on_resolved(request, &resolution).await;
+                    }
+                    return Ok(resolution);
+                }
+                Ok(None) => {
+                    self.logger
+                        .log_debug(&format!("Resolver {} skipped the request", resolver.name()));
+                }
+                // A policy refusal holds no matter which resolver would try next
+                Err(e @ AppError::Blocked(_)) => return Err(e),
+                Err(e) => {
+                    if !resolver.allows_fallback() {
+                        return Err(e);
+                    }
+                    self.logger
+                        .log_warning(&format!("Resolver {} failed: {}", resolver.name(), e));
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            AppError::Execution("No resolver handled the request".to_string())
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::logger::LogConfig;
+    use crate::models::{ScriptedConfig, ScriptedResponse};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc-ytdlp-pipeline-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn context(dir: PathBuf) -> ResolverContext {
+        ResolverContext {
+            ytdlp_path: dir.join("yt-dlp.exe"),
+            streamlink_path: dir.join("streamlink.exe"),
+            concurrent: false,
+            cache: Arc::new(ResolutionCache::new(dir.clone(), 600)),
+            logger: Logger::with_config(dir.join("test.log"), LogConfig::default()),
+            app_dir: dir,
+        }
+    }
+
+    fn request(url: &str) -> ResolveRequest {
+        ResolveRequest {
+            args: vec!["--get-url".to_string(), url.to_string()],
+            url: url.to_string(),
+            deadline: Deadline::new(Duration::from_secs(10)),
+            bypass_cache: false,
+            player: "unity".to_string(),
+            trace: InvocationTrace::default(),
+            metadata: None,
+        }
+    }
+
+    fn scripted(responses: &[(&str, Option<&str>, Option<&str>)]) -> ScriptedConfig {
+        ScriptedConfig {
+            responses: responses
+                .iter()
+                .map(|(pattern, url, error)| ScriptedResponse {
+                    pattern: pattern.to_string(),
+                    url: url.map(str::to_string),
+                    error: error.map(str::to_string),
+                    delay_ms: 0,
+                })
+                .collect(),
+        }
+    }
+
+    /// Fails every request with a fixed error and counts its calls
+    struct Failing {
+        error: fn() -> AppError,
+        fallback: bool,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Resolver for Failing {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        async fn resolve(&self, _request: &ResolveRequest) -> Result<Option<Resolution>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Err((self.error)())
+        }
+
+        fn allows_fallback(&self) -> bool {
+            self.fallback
+        }
+    }
+
+    fn pipeline(logger: &Logger, first: Failing, responses: &[(&str, Option<&str>, Option<&str>)]) -> ResolverPipeline {
+        let mut pipeline = ResolverPipeline::new(logger.clone());
+        pipeline.push(Box::new(first));
+        pipeline.push(Box::new(ScriptedResolver::new(scripted(responses), logger.clone())));
+        pipeline
+    }
+
+    fn failing(error: fn() -> AppError, fallback: bool) -> (Failing, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        (Failing { error, fallback, calls: calls.clone() }, calls)
+    }
+
+    #[tokio::test]
+    async fn failures_fall_through_to_the_next_resolver() {
+        let context = context(test_dir("fallback"));
+        let (first, calls) = failing(|| AppError::Execution("boom".to_string()), true);
+        let pipeline = pipeline(&context.logger, first, &[("example.com", Some("https://cdn/1.mp4"), None)]);
+
+        let resolution = pipeline.resolve(&request("https://example.com/v")).await.unwrap();
+        assert_eq!(resolution.url, "https://cdn/1.mp4");
+        assert_eq!(resolution.resolver, ScriptedResolver::NAME);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Nobody handles it: the last failure is reported
+        let error = pipeline.resolve(&request("https://other.net/v")).await.err().unwrap();
+        assert_eq!(error.to_string(), AppError::Execution("boom".to_string()).to_string());
+    }
+
+    #[tokio::test]
+    async fn blocked_and_final_failures_stop_the_pipeline() {
+        let context = context(test_dir("stop"));
+        let responses = [("example.com", Some("https://cdn/1.mp4"), None)];
+
+        let (blocked, _) = failing(|| AppError::Blocked("no".to_string()), true);
+        let result = pipeline(&context.logger, blocked, &responses).resolve(&request("https://example.com/v")).await;
+        assert!(matches!(result, Err(AppError::Blocked(_))));
+
+        let (last, _) = failing(|| AppError::Execution("final".to_string()), false);
+        let result = pipeline(&context.logger, last, &responses).resolve(&request("https://example.com/v")).await;
+        assert!(matches!(result, Err(AppError::Execution(message)) if message == "final"));
+    }
+
+    #[tokio::test]
+    async fn expired_deadline_stops_before_the_next_resolver() {
+        let context = context(test_dir("deadline"));
+        let (first, calls) = failing(|| AppError::Execution("boom".to_string()), true);
+        let pipeline = pipeline(&context.logger, first, &[("example.com", Some("https://cdn/1.mp4"), None)]);
+        let mut request = request("https://example.com/v");
+        request.deadline = Deadline::new(Duration::ZERO);
+
+        assert!(pipeline.resolve(&request).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn resolutions_are_cached_per_player() {
+        let context = context(test_dir("cache"));
+        let mut config = AppConfig {
+            resolvers: vec![CacheResolver::NAME.to_string(), ScriptedResolver::NAME.to_string()],
+            scripted: scripted(&[("example.com", Some("https://cdn/1.mp4"), None)]),
+            ..Default::default()
+        };
+        let pipeline = ResolverPipeline::from_config(&config, &context).unwrap();
+        assert_eq!(pipeline.resolve(&request("https://example.com/v")).await.unwrap().resolver, ScriptedResolver::NAME);
+
+        // The scripted answer changed, but the cache answers first
+        config.scripted = scripted(&[("example.com", Some("https://cdn/2.mp4"), None)]);
+        let pipeline = ResolverPipeline::from_config(&config, &context).unwrap();
+        let cached = pipeline.resolve(&request("https://example.com/v")).await.unwrap();
+        assert_eq!((cached.url.as_str(), cached.resolver), ("https://cdn/1.mp4", CacheResolver::NAME));
+
+        let mut other_player = request("https://example.com/v");
+        other_player.player = "avpro".to_string();
+        assert_eq!(pipeline.resolve(&other_player).await.unwrap().url, "https://cdn/2.mp4");
+
+        let mut bypass = request("https://example.com/v");
+        bypass.bypass_cache = true;
+        assert_eq!(pipeline.resolve(&bypass).await.unwrap().resolver, ScriptedResolver::NAME);
+    }
+
+    /// Serves every URL containing `local` without extracting anything
+    struct Local;
+
+    #[async_trait]
+    impl Resolver for Local {
+        fn name(&self) -> &'static str {
+            "local"
+        }
+
+        async fn resolve(&self, request: &ResolveRequest) -> Result<Option<Resolution>> {
+            Ok(request.url.contains("local").then(|| Resolution {
+                url: "file:///media/local.mp4".to_string(),
+                resolver: "local",
+            }))
+        }
+
+        fn needs_content_check(&self) -> bool {
+            false
+        }
+    }
+
+    /// Counts content checks and refuses URLs containing `blocked`
+    #[derive(Default)]
+    struct CountingCheck {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ContentCheck for CountingCheck {
+        async fn check(&self, request: &ResolveRequest) -> Result<Option<Arc<MediaInfo>>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if request.url.contains("blocked") {
+                return Err(AppError::Blocked("not allowed".to_string()));
+            }
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn content_check_runs_once_before_the_first_extracting_resolver() {
+        let context = context(test_dir("content-check"));
+        let (first, calls) = failing(|| AppError::Execution("boom".to_string()), true);
+        let mut pipeline = ResolverPipeline::new(context.logger.clone());
+        pipeline.push(Box::new(Local));
+        pipeline.push(Box::new(first));
+        pipeline.push(Box::new(ScriptedResolver::new(
+            scripted(&[("example.com", Some("https://cdn/1.mp4"), None)]),
+            context.logger.clone(),
+        )));
+        let check = CountingCheck::default();
+
+        // Served before any resolver that needs the metadata
+        let local = pipeline.resolve_checked(&request("https://local/v"), Some(&check)).await.unwrap();
+        assert_eq!(local.resolver, "local");
+        assert_eq!(check.calls.load(Ordering::SeqCst), 0);
+
+        // Checked once, even though two extracting resolvers ran
+        let resolution = pipeline.resolve_checked(&request("https://example.com/v"), Some(&check)).await.unwrap();
+        assert_eq!(resolution.url, "https://cdn/1.mp4");
+        assert_eq!((check.calls.load(Ordering::SeqCst), calls.load(Ordering::SeqCst)), (1, 1));
+
+        // A refusal stops the pipeline before the extracting resolvers run
+        let result = pipeline.resolve_checked(&request("https://example.com/blocked"), Some(&check)).await;
+        assert!(matches!(result, Err(AppError::Blocked(_))));
+        assert_eq!((check.calls.load(Ordering::SeqCst), calls.load(Ordering::SeqCst)), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn cache_entries_from_other_content_rules_are_checked_again() {
+        let context = context(test_dir("content-cache"));
+        let mut config = AppConfig {
+            resolvers: vec![CacheResolver::NAME.to_string(), ScriptedResolver::NAME.to_string()],
+            scripted: scripted(&[("example.com", Some("https://cdn/2.mp4"), None)]),
+            ..Default::default()
+        };
+        config.content_policy.enabled = true;
+        let request = request("https://example.com/v");
+        context.cache.insert(&request.cache_key(), "https://cdn/1.mp4", Some("old".to_string())).unwrap();
+        let pipeline = ResolverPipeline::from_config(&config, &context).unwrap();
+        let check = CountingCheck::default();
+
+        let resolution = pipeline.resolve_checked(&request, Some(&check)).await.unwrap();
+        assert_eq!((resolution.url.as_str(), resolution.resolver), ("https://cdn/2.mp4", ScriptedResolver::NAME));
+        assert_eq!(check.calls.load(Ordering::SeqCst), 1);
+
+        // Stored under the current rules, so the next request skips the check
+        let cached = pipeline.resolve_checked(&request, Some(&check)).await.unwrap();
+        assert_eq!(cached.resolver, CacheResolver::NAME);
+        assert_eq!(check.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn unknown_resolvers_are_a_config_error() {
+        let config = AppConfig {
+            resolvers: vec!["telepathy".to_string()],
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            ResolverPipeline::from_config(&config, &context(test_dir("unknown"))),
+            Err(AppError::Config(_))
+        ));
+    }
+}
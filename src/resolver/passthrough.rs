@@ -0,0 +1,51 @@
+use async_trait::async_trait;
+
+use crate::error::Result;
+use crate::logger::Logger;
+use crate::models::PassthroughConfig;
+use crate::passthrough::Passthrough;
+
+use super::{ResolveRequest, Resolution, Resolver};
+
+/// Echoes direct media URLs back without spawning anything
+pub struct PassthroughResolver {
+    config: PassthroughConfig,
+    logger: Logger,
+}
+
+impl PassthroughResolver {
+    pub const NAME: &'static str = "passthrough";
+
+    pub fn new(config: PassthroughConfig, logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
+        Self { config, logger }
+    }
+}
+
+#[async_trait]
+impl Resolver for PassthroughResolver {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn needs_content_check(&self) -> bool {
+        false
+    }
+
+    async fn resolve(&self, request: &ResolveRequest) -> Result<Option<Resolution>> {
+        let passthrough = Passthrough::new(&self.config);
+        if !passthrough.is_direct_media(&request.url, &request.deadline, &self.logger).await {
+            return Ok(None);
+        }
+
+        self.logger.log_info(&format!(
+            "Direct media URL, passing through without yt-dlp: {}",
+            request.url
+        ));
+
+        Ok(Some(Resolution {
+            url: request.url.clone(),
+            resolver: Self::NAME,
+        }))
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::error::{AppError, Result};
+use crate::logger::Logger;
+use crate::models::ScriptedConfig;
+
+use super::{ResolveRequest, Resolution, Resolver};
+
+/// Returns canned responses from config, for exercising the pipeline without network access
+pub struct ScriptedResolver {
+    config: ScriptedConfig,
+    logger: Logger,
+}
+
+impl ScriptedResolver {
+    pub const NAME: &'static str = "scripted";
+
+    pub fn new(config: ScriptedConfig, logger: Logger) -> Self {
+        let logger = logger.for_module(module_path!());
+        Self { config, logger }
+    }
+}
+
+#[async_trait]
+impl Resolver for ScriptedResolver {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    async fn resolve(&self, request: &ResolveRequest) -> Result<Option<Resolution>> {
+        let Some(response) = self
+            .config
+            .responses
+            .iter()
+            .find(|r| request.url.contains(&r.pattern))
+        else {
+            return Ok(None);
+        };
+
+        self.logger
+            .log_debug(&format!("Scripted response matched pattern: {}", response.pattern));
+
+        if response.delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(response.delay_ms)).await;
+        }
+
+        if let Some(error) = &response.error {
+            return Err(AppError::Execution(error.clone()));
+        }
+
+        Ok(response.url.as_ref().map(|url| Resolution {
+            url: url.clone(),
+            resolver: Self::NAME,
+        }))
+    }
+}
@@ -0,0 +1,253 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::args::ArgumentParser;
+use crate::deadline::Deadline;
+use crate::downloader::StreamlinkDownloader;
+use crate::error::{AppError, Result};
+use crate::executor::Executor;
+use crate::logger::Logger;
+use crate::models::{AppConfig, ProtocolPreference, StreamlinkConfig};
+use crate::output::OutputValidator;
+use crate::rules;
+
+use super::{ResolveRequest, Resolution, Resolver, ResolverContext};
+
+/// Resolves live streams with `streamlink --stream-url`, which is much faster than yt-dlp for them
+pub struct StreamlinkResolver {
+    config: StreamlinkConfig,
+    downloader: StreamlinkDownloader,
+    /// When this process last checked for updates, as for yt-dlp
+    last_update_check: Mutex<Option<Instant>>,
+    max_height: Option<u32>,
+    /// Player profiles that can't play the HLS streams streamlink returns
+    progressive_players: Vec<String>,
+    executor: Executor,
+    logger: Logger,
+}
+
+impl StreamlinkResolver {
+    pub const NAME: &'static str = "streamlink";
+
+    pub fn new(config: &AppConfig, context: &ResolverContext) -> Self {
+        let grace = Duration::from_millis(config.execution.termination_grace_ms);
+        let logger = context.logger.for_module(module_path!());
+
+        Self {
+            config: config.streamlink.clone(),
+            downloader: StreamlinkDownloader::new(context.streamlink_path.clone(), logger.clone()),
+            last_update_check: Mutex::new(None),
+            // Streamlink only handles live streams, so the live overrides apply
+            max_height: match ArgumentParser::format_selector(config) {
+                Some(selector) => ArgumentParser::max_height(selector),
+                None => config.quality.for_live().max_height,
+            },
+            progressive_players: config
+                .players
+                .profiles
+                .iter()
+                .filter(|profile| profile.quality.protocol == Some(ProtocolPreference::Progressive))
+                .map(|profile| profile.name.clone())
+                .collect(),
+            executor: Executor::new(context.app_dir.clone(), grace, context.logger.clone()),
+            logger,
+        }
+    }
+
+    /// Installs streamlink on first use and keeps a managed install up-to-date, within the deadline
+    async fn ensure_available(&self, deadline: &Deadline) -> Result<()> {
+        // Holding the lock serialises concurrent requests so only one download can happen
+        let mut last_check = self.last_update_check.lock().await;
+
+        if !self.downloader.executable_exists() {
+            if !self.config.managed {
+                return Err(AppError::FileNotFound(format!(
+                    "streamlink not found at {} and managed installs are disabled",
+                    self.downloader.get_executable_path().display()
+                )));
+            }
+            self.logger.log_info(&format!(
+                "{} not found, downloading...",
+                self.downloader.get_executable_path().display()
+            ));
+            return tokio::time::timeout(deadline.remaining(), self.downloader.download_latest())
+                .await
+                .map_err(|_| AppError::Execution("Request deadline exhausted while downloading streamlink".to_string()))?;
+        }
+
+        if !self.config.managed || !self.downloader.is_managed() {
+            return Ok(());
+        }
+
+        let check_interval = Duration::from_secs(crate::constants::defaults::UPDATE_CHECK_DAYS as u64 * 24 * 60 * 60);
+        if last_check.is_some_and(|checked| checked.elapsed() < check_interval) {
+            return Ok(());
+        }
+        *last_check = Some(Instant::now());
+
+        match tokio::time::timeout(deadline.remaining(), self.downloader.check_and_update()).await {
+            Ok(Err(e)) => self.logger.log_error(&format!("Failed to check for streamlink updates: {}", e)),
+            Err(_) => self.logger.log_warning("Streamlink update check exceeded the request deadline; skipping"),
+            Ok(Ok(())) => {}
+        }
+
+        Ok(())
+    }
+
+    /// Expands the argument template and maps our height limit onto streamlink's stream sorting.
+    ///
+    /// Without a height limit, template arguments using `{max_height}` are dropped
+    /// along with the option they are the value of.
+    fn build_args(&self, url: &str) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        for arg in &self.config.args {
+            let arg = arg.replace("{url}", url).replace("{quality}", &self.config.quality);
+            match self.max_height {
+                Some(height) => args.push(arg.replace("{max_height}", &height.to_string())),
+                None if arg.contains("{max_height}") => {
+                    if args.last().is_some_and(|option| option.starts_with('-') && !option.contains('=')) {
+                        args.pop();
+                    }
+                }
+                None => args.push(arg),
+            }
+        }
+
+        let template_uses_height = self.config.args.iter().any(|arg| arg.contains("{max_height}"));
+        if let Some(height) = self.max_height.filter(|_| !template_uses_height) {
+            args.push(format!("--stream-sorting-excludes=>{}p", height));
+        }
+
+        args
+    }
+}
+
+#[async_trait]
+impl Resolver for StreamlinkResolver {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    async fn resolve(&self, request: &ResolveRequest) -> Result<Option<Resolution>> {
+        let handles = rules::host_of(&request.url)
+            .map(|host| rules::host_matches_any(&host, &self.config.domains))
+            .unwrap_or(false);
+        if !handles {
+            return Ok(None);
+        }
+        if self.progressive_players.contains(&request.player) {
+            self.logger.log_debug(&format!("Skipping streamlink, player {} can't play HLS", request.player));
+            return Ok(None);
+        }
+
+        self.ensure_available(&request.deadline).await?;
+
+        let args = self.build_args(&request.url);
+        self.logger.log_info(&format!("Streamlink arguments: {:?}", args));
+
+        let timeout = request.deadline.clamp(Duration::from_secs(self.config.timeout_secs));
+        let lines = self.executor.run(&self.downloader.get_executable_path(), &args, timeout).await?;
+        let url = OutputValidator::select_url(&lines, &self.logger)?;
+
+        Ok(Some(Resolution {
+            url,
+            resolver: Self::NAME,
+        }))
+    }
+
+    fn allows_fallback(&self) -> bool {
+        self.config.fallback_to_ytdlp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::cache::ResolutionCache;
+    use crate::history::InvocationTrace;
+    use crate::logger::LogConfig;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc-ytdlp-streamlink-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn resolver(config: &AppConfig, dir: PathBuf) -> StreamlinkResolver {
+        let context = ResolverContext {
+            ytdlp_path: dir.join("yt-dlp.exe"),
+            streamlink_path: dir.join("streamlink/bin/streamlink.exe"),
+            concurrent: false,
+            cache: Arc::new(ResolutionCache::new(dir.clone(), 600)),
+            logger: Logger::with_config(dir.join("test.log"), LogConfig::default()),
+            app_dir: dir,
+        };
+        StreamlinkResolver::new(config, &context)
+    }
+
+    fn config(max_height: Option<u32>, args: &[&str]) -> AppConfig {
+        let mut config = AppConfig::default();
+        config.quality.max_height = max_height;
+        config.quality.live.max_height = None;
+        config.streamlink.args = args.iter().map(|arg| arg.to_string()).collect();
+        config
+    }
+
+    #[test]
+    fn height_limit_maps_to_stream_sorting() {
+        let config = config(Some(720), &["--stream-url", "{url}", "{quality}"]);
+
+        assert_eq!(
+            resolver(&config, test_dir("height")).build_args("https://twitch.tv/x"),
+            vec!["--stream-url", "https://twitch.tv/x", "best", "--stream-sorting-excludes=>720p"]
+        );
+    }
+
+    #[test]
+    fn templated_height_is_substituted_or_dropped() {
+        let args = ["--stream-url", "{url}", "{quality}", "--stream-sorting-excludes", ">{max_height}p"];
+
+        assert_eq!(
+            resolver(&config(Some(480), &args), test_dir("templated")).build_args("https://twitch.tv/x"),
+            vec!["--stream-url", "https://twitch.tv/x", "best", "--stream-sorting-excludes", ">480p"]
+        );
+        assert_eq!(
+            resolver(&config(None, &args), test_dir("untemplated")).build_args("https://twitch.tv/x"),
+            vec!["--stream-url", "https://twitch.tv/x", "best"]
+        );
+
+        let inline = ["--stream-url", "{url}", "--stream-sorting-excludes=>{max_height}p", "{quality}"];
+        assert_eq!(
+            resolver(&config(None, &inline), test_dir("inline")).build_args("https://twitch.tv/x"),
+            vec!["--stream-url", "https://twitch.tv/x", "best"]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_unmanaged_install_is_reported() {
+        let mut config = config(None, &["--stream-url", "{url}", "{quality}"]);
+        config.streamlink.managed = false;
+        let request = ResolveRequest {
+            args: vec!["https://www.twitch.tv/x".to_string()],
+            url: "https://www.twitch.tv/x".to_string(),
+            deadline: Deadline::new(Duration::from_secs(5)),
+            bypass_cache: false,
+            player: "avpro".to_string(),
+            trace: InvocationTrace::default(),
+            metadata: None,
+        };
+        let resolver = resolver(&config, test_dir("missing"));
+
+        assert!(matches!(resolver.resolve(&request).await, Err(AppError::FileNotFound(_))));
+
+        let mut other = request.clone();
+        other.url = "https://www.youtube.com/watch?v=x".to_string();
+        assert!(resolver.resolve(&other).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,397 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::args::ArgumentParser;
+use crate::deadline::Deadline;
+use crate::downloader::Downloader;
+use crate::error::{AppError, Result};
+use crate::executor::Executor;
+use crate::format::{FormatSelector, MediaInfo};
+use crate::logger::{Level, Logger};
+use crate::models::{AppConfig, FormatSelection};
+use crate::output::OutputValidator;
+use crate::probe::UrlProbe;
+use crate::rules;
+
+use super::{ResolveRequest, Resolution, Resolver, ResolverContext};
+
+/// The default backend: filters VRChat's arguments and runs yt-dlp
+pub struct YtDlpResolver {
+    config: AppConfig,
+    downloader: Downloader,
+    executor: Executor,
+    probe: Option<UrlProbe>,
+    /// When this process last checked for updates; keeps a long-running daemon from re-checking per request
+    last_update_check: Mutex<Option<Instant>>,
+    logger: Logger,
+}
+
+impl YtDlpResolver {
+    pub const NAME: &'static str = "yt-dlp";
+
+    pub fn new(config: AppConfig, context: &ResolverContext) -> Self {
+        let logger = context.logger.for_module(module_path!());
+        logger.log_info(&format!("yt-dlp full path: {}", context.ytdlp_path.display()));
+
+        let downloader = Downloader::new(context.ytdlp_path.clone(), logger.clone());
+        let grace = Duration::from_millis(config.execution.termination_grace_ms);
+        let executor = Executor::new(context.app_dir.clone(), grace, logger.clone())
+            .with_concurrency(context.concurrent);
+        let probe = config.probe.enabled.then(|| UrlProbe::new(&config.probe));
+
+        Self {
+            config,
+            downloader,
+            executor,
+            probe,
+            last_update_check: Mutex::new(None),
+            logger,
+        }
+    }
+
+    /// Ensures yt-dlp is available and up-to-date, bounding the update check by the deadline
+    pub async fn ensure_available(&self, deadline: &Deadline) -> Result<()> {
+        // Holding the lock serialises concurrent requests so only one download can happen
+        let mut last_check = self.last_update_check.lock().await;
+
+        if !self.downloader.executable_exists() {
+            self.logger.log_info(&format!(
+                "{} not found, downloading...",
+                self.downloader.get_executable_path().display()
+            ));
+            // Downloads are buffered before the file is written, so giving up leaves nothing behind
+            return tokio::time::timeout(deadline.remaining(), self.downloader.download_latest())
+                .await
+                .map_err(|_| AppError::Execution("Request deadline exhausted while downloading yt-dlp".to_string()))?;
+        }
+
+        let check_interval = Duration::from_secs(crate::constants::defaults::UPDATE_CHECK_DAYS as u64 * 24 * 60 * 60);
+        if last_check.is_some_and(|checked| checked.elapsed() < check_interval) {
+            return Ok(());
+        }
+        *last_check = Some(Instant::now());
+
+        match tokio::time::timeout(deadline.remaining(), self.downloader.check_and_update()).await {
+            Ok(Err(e)) => {
+                self.logger.log_error(&format!("Failed to check for updates: {}", e));
+                // Continue anyway, we have a working version
+            }
+            Err(_) => self.logger.log_warning("Update check exceeded the request deadline; skipping"),
+            Ok(Ok(())) => {}
+        }
+
+        Ok(())
+    }
+
+    /// Version of the yt-dlp executable in use, if known
+    pub fn version(&self) -> Option<String> {
+        self.downloader.current_version()
+    }
+
+    /// Runs yt-dlp for requests that aren't URL lookups and returns its stdout unchanged
+    pub async fn run_raw(&self, input_args: &[String], deadline: &Deadline) -> Result<Vec<String>> {
+        self.ensure_available(deadline).await?;
+
+        let yt_dlp_args = self.build_args(input_args, &self.config, None);
+        let timeout = deadline.clamp(Duration::from_secs(self.config.execution.timeout_secs));
+        self.executor
+            .execute(&self.downloader.get_executable_path(), &yt_dlp_args, timeout)
+            .await
+    }
+
+    /// Dumps the video's metadata with `-J` for checks made before resolution
+    pub async fn fetch_metadata(
+        &self,
+        input_args: &[String],
+        player: Option<&str>,
+        deadline: &Deadline,
+    ) -> Result<MediaInfo> {
+        self.ensure_available(deadline).await?;
+
+        let yt_dlp_args = FormatSelector::metadata_args(&self.build_args(input_args, &self.config, player));
+        let timeout = match ArgumentParser::requested_url(input_args) {
+            Some(url) => deadline.clamp(self.timeout_for(url)),
+            None => deadline.clamp(Duration::from_secs(self.config.execution.timeout_secs)),
+        };
+
+        let lines = self
+            .executor
+            .execute(&self.downloader.get_executable_path(), &yt_dlp_args, timeout)
+            .await?;
+        MediaInfo::parse(&lines)
+    }
+
+    /// Downloads the full media file for a URL and returns the path yt-dlp wrote it to
+    pub async fn download(&self, url: &str, format: &str, output_template: &Path, timeout: Duration) -> Result<PathBuf> {
+        self.ensure_available(&Deadline::new(timeout)).await?;
+
+        let mut args = ArgumentParser::without_format_selector(&self.config.custom_args);
+        args.extend([
+            "--no-playlist".to_string(),
+            "-f".to_string(),
+            format.to_string(),
+            "-o".to_string(),
+            output_template.to_string_lossy().to_string(),
+            // Printing after the move also keeps the download from being simulated
+            "--print".to_string(),
+            "after_move:filepath".to_string(),
+        ]);
+        if self.config.cookies {
+            args.push(format!("--cookies-from-browser={}", self.config.cookies_browser));
+        }
+        args.push(url.to_string());
+
+        self.logger.log_info(&format!("Download arguments: {:?}", args));
+        let lines = self
+            .executor
+            .run(&self.downloader.get_executable_path(), &args, timeout)
+            .await?;
+
+        let path = lines
+            .iter()
+            .rev()
+            .map(|line| PathBuf::from(line.trim()))
+            .find(|path| path.is_file())
+            .ok_or_else(|| AppError::Execution("yt-dlp did not report the downloaded file".to_string()))?;
+        Ok(path)
+    }
+
+    /// Resolves the timeout for a URL from the matching domain rule
+    fn timeout_for(&self, url: &str) -> Duration {
+        let timeout_secs = match rules::find_rule(&self.config.rules, url) {
+            Some(rule) => {
+                self.logger.log_debug(&format!("Matched rule: {}", rule.name));
+                rule.timeout_secs.unwrap_or(self.config.execution.timeout_secs)
+            }
+            None => self.config.execution.timeout_secs,
+        };
+
+        Duration::from_secs(timeout_secs)
+    }
+
+    /// Lists the strategies to try as (name, config) pairs, default first
+    fn strategy_configs(&self) -> Vec<(String, AppConfig)> {
+        let mut strategies = vec![("default".to_string(), self.config.clone())];
+
+        for strategy in &self.config.fallback_strategies {
+            let mut config = self.config.clone();
+            config.custom_args = strategy.custom_args.clone();
+            strategies.push((strategy.name.clone(), config));
+        }
+
+        strategies
+    }
+
+    /// Builds the complete argument list for yt-dlp
+    fn build_args(&self, input_args: &[String], config: &AppConfig, player: Option<&str>) -> Vec<String> {
+        let yt_dlp_args = if self.logger.enabled(Level::Debug) {
+            ArgumentParser::filter_arguments_with_logger(input_args, config, player, Some(&self.logger))
+        } else {
+            ArgumentParser::filter_arguments(input_args, config, player)
+        };
+
+        // Log the actual arguments that will be passed to yt-dlp
+        self.logger.log_info(&format!("Arguments: {:?}", yt_dlp_args));
+        yt_dlp_args
+    }
+
+    /// Runs yt-dlp once with the given configuration and validates its output
+    async fn run_strategy(
+        &self,
+        request: &ResolveRequest,
+        config: &AppConfig,
+        metadata: Option<&MediaInfo>,
+        timeout: Duration,
+    ) -> Result<String> {
+        if let (FormatSelection::Builtin, Some(info)) = (config.quality.selection, metadata) {
+            self.logger
+                .log_debug("Built-in format selection, reusing the metadata from the content check");
+            let quality = ArgumentParser::quality_for(&request.args, config, Some(&request.player));
+            let url = FormatSelector::new(&quality).select_media(info, &self.logger)?;
+            if let Some(probe) = &self.probe {
+                probe.check(&url, &request.deadline, &self.logger).await?;
+            }
+            return Ok(url);
+        }
+
+        let mut yt_dlp_args = self.build_args(&request.args, config, Some(&request.player));
+        if config.quality.selection == FormatSelection::Builtin {
+            yt_dlp_args = FormatSelector::metadata_args(&yt_dlp_args);
+            self.logger.log_debug(&format!("Built-in format selection, metadata arguments: {:?}", yt_dlp_args));
+        }
+        request.trace.set_ytdlp_args(&yt_dlp_args);
+
+        let started = Instant::now();
+        let output = self
+            .executor
+            .execute(&self.downloader.get_executable_path(), &yt_dlp_args, timeout)
+            .await;
+        request.trace.add_phase("yt-dlp", started.elapsed());
+        request.trace.set_exit_code(match &output {
+            Ok(_) => Some(0),
+            Err(e) => e.exit_code(),
+        });
+        let lines = output?;
+
+        // Validate what yt-dlp printed so VRChat only ever sees a single playable URL
+        let url = match config.quality.selection {
+            FormatSelection::YtDlp => OutputValidator::select_url(&lines, &self.logger)?,
+            FormatSelection::Builtin => {
+                let quality = ArgumentParser::quality_for(&request.args, config, Some(&request.player));
+                FormatSelector::new(&quality).select_from_output(&lines, &self.logger)?
+            }
+        };
+        if let Some(probe) = &self.probe {
+            probe.check(&url, &request.deadline, &self.logger).await?;
+        }
+
+        Ok(url)
+    }
+}
+
+#[async_trait]
+impl Resolver for YtDlpResolver {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    async fn resolve(&self, request: &ResolveRequest) -> Result<Option<Resolution>> {
+        let started = Instant::now();
+        self.ensure_available(&request.deadline).await?;
+        request.trace.add_phase("update_check", started.elapsed());
+
+        // Try the default arguments first, then each fallback strategy in turn
+        let strategies = self.strategy_configs();
+        let phase_timeout = self.timeout_for(&request.url);
+        let mut result = Err(AppError::Execution("No strategy was attempted".to_string()));
+
+        for (index, (name, strategy_config)) in strategies.iter().enumerate() {
+            if request.deadline.is_expired() {
+                result = Err(AppError::Execution("Request deadline exhausted before execution".to_string()));
+                break;
+            }
+
+            self.logger.log_info(&format!("Using strategy: {}", name));
+            request.trace.set_strategy(name);
+            let timeout = request.deadline.clamp(phase_timeout);
+            self.logger.log_debug(&format!(
+                "Execution timeout: {:.1}s ({:.1}s of deadline used)",
+                timeout.as_secs_f32(),
+                request.deadline.elapsed().as_secs_f32()
+            ));
+
+            // The metadata was dumped with the default arguments, so fallbacks fetch their own
+            let metadata = request.metadata.as_deref().filter(|_| index == 0);
+            result = self.run_strategy(request, strategy_config, metadata, timeout).await;
+
+            match &result {
+                Ok(_) => break,
+                Err(e) if index + 1 < strategies.len() => {
+                    self.logger.log_warning(&format!("Strategy {} failed: {}; trying next", name, e));
+                }
+                Err(_) => {}
+            }
+        }
+
+        result.map(|url| {
+            Some(Resolution {
+                url,
+                resolver: Self::NAME,
+            })
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::cache::ResolutionCache;
+    use crate::constants::VERSION_FILE_NAME;
+    use crate::history::InvocationTrace;
+    use crate::logger::LogConfig;
+    use crate::models::ProtocolPreference;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc-ytdlp-ytdlp-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Installs a fake yt-dlp that records its arguments and prints one URL per
+    /// requested stream, as the real one does for merged formats
+    fn fake_ytdlp(dir: &Path) -> ResolverContext {
+        let path = dir.join("yt-dlp.exe");
+        fs::write(
+            &path,
+            "#!/bin/sh\necho \"$@\" > args.txt\ncase \"$*\" in\n  *+*) echo https://cdn.example.com/video; echo https://cdn.example.com/audio ;;\n  *) echo https://cdn.example.com/combined.mp4 ;;\nesac\n",
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        // Recently checked, so no update is attempted
+        fs::write(
+            dir.join(VERSION_FILE_NAME),
+            serde_json::json!({ "version": "2025.01.01", "last_check": chrono::Utc::now() }).to_string(),
+        )
+        .unwrap();
+
+        ResolverContext {
+            app_dir: dir.to_path_buf(),
+            ytdlp_path: path,
+            streamlink_path: dir.join("streamlink.exe"),
+            concurrent: true,
+            cache: Arc::new(ResolutionCache::new(dir.to_path_buf(), 600)),
+            logger: Logger::with_config(dir.join("test.log"), LogConfig::default()),
+        }
+    }
+
+    fn request(url: &str) -> ResolveRequest {
+        ResolveRequest {
+            args: vec!["--get-url".to_string(), url.to_string()],
+            url: url.to_string(),
+            deadline: Deadline::new(Duration::from_secs(10)),
+            bypass_cache: false,
+            player: "unity".to_string(),
+            trace: InvocationTrace::default(),
+            metadata: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn quality_settings_resolve_to_a_single_url() {
+        let dir = test_dir("quality");
+        let mut config = AppConfig::default();
+        config.quality.protocol = ProtocolPreference::Progressive;
+        config.quality.protocol_required = true;
+        let resolver = YtDlpResolver::new(config, &fake_ytdlp(&dir));
+
+        let resolution = resolver.resolve(&request("https://example.com/v")).await.unwrap().unwrap();
+        assert_eq!(resolution.url, "https://cdn.example.com/combined.mp4");
+        let args = fs::read_to_string(dir.join("args.txt")).unwrap();
+        // The unity profile caps the height at 720p
+        assert!(args.contains("-f best[height<=720][protocol^=http]"), "{}", args);
+        assert!(!args.contains('+'), "{}", args);
+    }
+
+    #[tokio::test]
+    async fn merged_formats_are_rejected() {
+        let dir = test_dir("merged");
+        let config = AppConfig {
+            custom_args: vec!["-f".to_string(), "bv*+ba".to_string()],
+            ..Default::default()
+        };
+        let resolver = YtDlpResolver::new(config, &fake_ytdlp(&dir));
+
+        assert!(matches!(
+            resolver.resolve(&request("https://example.com/v")).await,
+            Err(AppError::Validation(_))
+        ));
+    }
+}
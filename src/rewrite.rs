@@ -0,0 +1,330 @@
+use regex::Regex;
+
+use crate::args::ArgumentParser;
+use crate::error::{AppError, Result};
+use crate::logger::Logger;
+use crate::models::{RewriteConfig, RewriteRule};
+use crate::rules;
+
+/// Built-in rules as (name, pattern, replacement, kept parameters), normalising YouTube
+/// links to `https://www.youtube.com/watch?v=<id>` so the same video always shares a
+/// cache key; the start time `t` is carried over so links still start where they should
+const BUILTIN_RULES: [(&str, &str, &str, &[&str]); 4] = [
+    (
+        "youtube-shorts",
+        r"(?i)^https?://(?:www\.|m\.)?youtube\.com/shorts/([\w-]{11})(?:[?#/].*)?$",
+        "https://www.youtube.com/watch?v=$1",
+        &["t"],
+    ),
+    (
+        "youtube-short-link",
+        r"(?i)^https?://youtu\.be/([\w-]{11})(?:[?#/].*)?$",
+        "https://www.youtube.com/watch?v=$1",
+        &["t"],
+    ),
+    (
+        "youtube-mobile-music",
+        r"(?i)^https?://(?:m|music)\.youtube\.com/watch\?(.*)$",
+        "https://www.youtube.com/watch?$1",
+        &[],
+    ),
+    // Drops `list=` mixes, `si=` tracking and any other parameter besides the video id and start time
+    (
+        "youtube-watch-params",
+        r"(?i)^https?://(?:www\.)?youtube\.com/watch\?(?:.*&)?v=([\w-]{11})(?:[&#].*)?$",
+        "https://www.youtube.com/watch?v=$1",
+        &["t"],
+    ),
+];
+
+struct CompiledRule {
+    name: String,
+    pattern: Regex,
+    replacement: String,
+    hosts: Vec<String>,
+    keep_params: Vec<String>,
+}
+
+/// Rewrites requested URLs before they reach the resolvers
+pub struct UrlRewriter {
+    rules: Vec<CompiledRule>,
+    logger: Logger,
+}
+
+impl UrlRewriter {
+    pub fn new(config: &RewriteConfig, logger: Logger) -> Result<Self> {
+        let logger = logger.for_module(module_path!());
+        let builtin = BUILTIN_RULES
+            .iter()
+            .filter(|_| config.builtin_rules)
+            .map(|(name, pattern, replacement, keep_params)| RewriteRule {
+                name: name.to_string(),
+                pattern: pattern.to_string(),
+                replacement: replacement.to_string(),
+                hosts: Vec::new(),
+                keep_params: keep_params.iter().map(|param| param.to_string()).collect(),
+            });
+
+        let rules = builtin
+            .chain(config.rules.iter().cloned())
+            .map(|rule| {
+                let pattern = Regex::new(&rule.pattern).map_err(|e| {
+                    AppError::Config(format!("Invalid pattern in rewrite rule {}: {}", rule.name, e))
+                })?;
+                Ok(CompiledRule {
+                    name: rule.name,
+                    pattern,
+                    replacement: rule.replacement,
+                    hosts: rule.hosts,
+                    keep_params: rule.keep_params,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { rules, logger })
+    }
+
+    /// Applies every matching rule in order and returns the final URL
+    pub fn rewrite(&self, url: &str) -> String {
+        let mut rewritten = url.to_string();
+
+        for rule in &self.rules {
+            if !rule.hosts.is_empty() {
+                let on_host = rules::host_of(&rewritten)
+                    .is_some_and(|host| rules::host_matches_any(&host, &rule.hosts));
+                if !on_host {
+                    continue;
+                }
+            }
+
+            if rule.pattern.is_match(&rewritten) {
+                let replaced = rule.pattern.replace(&rewritten, rule.replacement.as_str());
+                let next = Self::carry_params(&rewritten, &replaced, &rule.keep_params);
+                if next != rewritten {
+                    self.logger.log_debug(&format!("Rewrite rule {}: {} -> {}", rule.name, rewritten, next));
+                    rewritten = next;
+                }
+            }
+        }
+
+        rewritten
+    }
+
+    /// Rewrites the URL among VRChat's arguments, leaving everything else untouched
+    pub fn rewrite_args(&self, args: &[String]) -> Vec<String> {
+        args.iter()
+            .map(|arg| {
+                if ArgumentParser::is_http_url(arg) {
+                    self.rewrite(arg)
+                } else {
+                    arg.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// Appends the kept query parameters of the original URL that the rewritten one lacks
+    fn carry_params(original: &str, rewritten: &str, keep: &[String]) -> String {
+        let query_of = |url: &str| -> Vec<(String, String)> {
+            let Some((_, query)) = url.split('#').next().unwrap_or_default().split_once('?') else {
+                return Vec::new();
+            };
+            query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (key.to_string(), value.to_string())
+                })
+                .collect()
+        };
+
+        let present = query_of(rewritten);
+        let (base, fragment) = match rewritten.split_once('#') {
+            Some((base, fragment)) => (base, Some(fragment)),
+            None => (rewritten, None),
+        };
+
+        let mut result = base.to_string();
+        for (key, value) in query_of(original) {
+            if !keep.contains(&key) || present.iter().any(|(present, _)| *present == key) {
+                continue;
+            }
+            result.push(if result.contains('?') { '&' } else { '?' });
+            result.push_str(&key);
+            if !value.is_empty() {
+                result.push('=');
+                result.push_str(&value);
+            }
+        }
+
+        if let Some(fragment) = fragment {
+            result.push('#');
+            result.push_str(fragment);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logger::LogConfig;
+
+    fn logger() -> Logger {
+        Logger::with_config(std::env::temp_dir().join("vrc-ytdlp-rewrite-test.log"), LogConfig::default())
+    }
+
+    fn rewriter(config: &RewriteConfig) -> UrlRewriter {
+        UrlRewriter::new(config, logger()).unwrap()
+    }
+
+    #[test]
+    fn builtin_rules_canonicalise_youtube_links() {
+        let rewriter = rewriter(&RewriteConfig::default());
+        let canonical = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+
+        for url in [
+            "https://youtu.be/dQw4w9WgXcQ",
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+            "https://m.youtube.com/shorts/dQw4w9WgXcQ?feature=share",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&list=RDAMVM",
+            "https://m.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?list=RDdQw4w9WgXcQ&v=dQw4w9WgXcQ&index=2",
+            "http://youtube.com/watch?v=dQw4w9WgXcQ#t=10",
+            canonical,
+        ] {
+            assert_eq!(rewriter.rewrite(url), canonical, "{}", url);
+        }
+    }
+
+    #[test]
+    fn builtin_rules_keep_the_start_time() {
+        let rewriter = rewriter(&RewriteConfig::default());
+        let canonical = "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42";
+
+        for url in [
+            "https://youtu.be/dQw4w9WgXcQ?si=tracking&t=42",
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ?t=42",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&list=RDAMVM&t=42",
+            "https://www.youtube.com/watch?t=42&list=RDdQw4w9WgXcQ&v=dQw4w9WgXcQ&index=2",
+            canonical,
+        ] {
+            assert_eq!(rewriter.rewrite(url), canonical, "{}", url);
+        }
+    }
+
+    #[test]
+    fn builtin_rules_leave_other_urls_alone() {
+        let rewriter = rewriter(&RewriteConfig::default());
+
+        for url in [
+            "https://www.youtube.com/playlist?list=PL123",
+            "https://www.youtube.com/@channel/live",
+            "https://example.com/watch?v=dQw4w9WgXcQ",
+            "https://www.twitch.tv/somechannel",
+        ] {
+            assert_eq!(rewriter.rewrite(url), url);
+        }
+    }
+
+    #[test]
+    fn builtin_rules_can_be_disabled() {
+        let rewriter = rewriter(&RewriteConfig {
+            builtin_rules: false,
+            ..Default::default()
+        });
+
+        assert_eq!(rewriter.rewrite("https://youtu.be/dQw4w9WgXcQ"), "https://youtu.be/dQw4w9WgXcQ");
+    }
+
+    #[test]
+    fn custom_rules_respect_hosts_and_run_after_builtin() {
+        let rewriter = rewriter(&RewriteConfig {
+            builtin_rules: true,
+            rules: vec![
+                RewriteRule {
+                    name: "strip-query".to_string(),
+                    pattern: r"^(https://[^?]+)\?.*$".to_string(),
+                    replacement: "$1".to_string(),
+                    hosts: vec!["example.com".to_string()],
+                    keep_params: Vec::new(),
+                },
+                RewriteRule {
+                    name: "no-www".to_string(),
+                    pattern: r"^https://www\.youtube\.com/(.*)$".to_string(),
+                    replacement: "https://youtube.com/$1".to_string(),
+                    hosts: Vec::new(),
+                    keep_params: Vec::new(),
+                },
+            ],
+        });
+
+        assert_eq!(rewriter.rewrite("https://cdn.example.com/v.mp4?token=1"), "https://cdn.example.com/v.mp4");
+        assert_eq!(rewriter.rewrite("https://other.org/v.mp4?token=1"), "https://other.org/v.mp4?token=1");
+        assert_eq!(
+            rewriter.rewrite("https://youtu.be/dQw4w9WgXcQ"),
+            "https://youtube.com/watch?v=dQw4w9WgXcQ"
+        );
+    }
+
+    #[test]
+    fn rewrite_args_only_touches_urls() {
+        let rewriter = rewriter(&RewriteConfig::default());
+        let args: Vec<String> = ["--get-url", "https://youtu.be/dQw4w9WgXcQ", "-f", "best"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+
+        assert_eq!(
+            rewriter.rewrite_args(&args),
+            ["--get-url", "https://www.youtube.com/watch?v=dQw4w9WgXcQ", "-f", "best"]
+        );
+    }
+
+    #[test]
+    fn rewrite_args_matches_scheme_case_insensitively() {
+        let rewriter = rewriter(&RewriteConfig::default());
+        let args = vec!["--get-url".to_string(), "HTTPS://youtu.be/dQw4w9WgXcQ".to_string()];
+
+        assert_eq!(
+            rewriter.rewrite_args(&args),
+            ["--get-url", "https://www.youtube.com/watch?v=dQw4w9WgXcQ"]
+        );
+    }
+
+    #[test]
+    fn custom_rules_carry_kept_params() {
+        let rewriter = rewriter(&RewriteConfig {
+            builtin_rules: false,
+            rules: vec![RewriteRule {
+                name: "mirror".to_string(),
+                pattern: r"^https://video\.example\.com/v/(\w+).*$".to_string(),
+                replacement: "https://mirror.example.com/v/$1".to_string(),
+                hosts: Vec::new(),
+                keep_params: vec!["start".to_string(), "autoplay".to_string()],
+            }],
+        });
+
+        assert_eq!(
+            rewriter.rewrite("https://video.example.com/v/abc?ref=feed&start=10&autoplay"),
+            "https://mirror.example.com/v/abc?start=10&autoplay"
+        );
+    }
+
+    #[test]
+    fn invalid_pattern_is_a_config_error() {
+        let config = RewriteConfig {
+            builtin_rules: false,
+            rules: vec![RewriteRule {
+                name: "broken".to_string(),
+                pattern: "(".to_string(),
+                replacement: String::new(),
+                hosts: Vec::new(),
+                keep_params: Vec::new(),
+            }],
+        };
+
+        assert!(matches!(UrlRewriter::new(&config, logger()), Err(AppError::Config(_))));
+    }
+}
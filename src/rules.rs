@@ -0,0 +1,52 @@
+use crate::models::DomainRule;
+
+/// Extracts the lowercase host from a URL
+pub fn host_of(url: &str) -> Option<String> {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_ascii_lowercase()))
+}
+
+/// Checks whether a host matches a pattern.
+///
+/// A plain pattern such as "youtube.com" matches the host itself and any subdomain,
+/// "*.youtube.com" matches subdomains only.
+pub fn host_matches(host: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim().to_ascii_lowercase();
+
+    if let Some(suffix) = pattern.strip_prefix("*.") {
+        return host.ends_with(&format!(".{}", suffix));
+    }
+
+    host == pattern || host.ends_with(&format!(".{}", pattern))
+}
+
+/// Checks whether a host matches any of the given patterns
+pub fn host_matches_any(host: &str, patterns: &[String]) -> bool {
+    patterns.iter().any(|p| host_matches(host, p))
+}
+
+/// Finds the rule whose domains match the URL's host most specifically.
+///
+/// A rule for "music.youtube.com" wins over one for "youtube.com" wherever it is listed;
+/// between equally specific rules the first one wins.
+pub fn find_rule<'a>(rules: &'a [DomainRule], url: &str) -> Option<&'a DomainRule> {
+    let host = host_of(url)?;
+    let mut best: Option<(usize, &DomainRule)> = None;
+
+    for rule in rules {
+        let specificity = rule
+            .domains
+            .iter()
+            .filter(|pattern| host_matches(&host, pattern))
+            .map(|pattern| pattern.trim().split('.').count())
+            .max();
+        if let Some(specificity) = specificity {
+            if best.is_none_or(|(current, _)| specificity > current) {
+                best = Some((specificity, rule));
+            }
+        }
+    }
+
+    best.map(|(_, rule)| rule)
+}
@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+
+use crate::args::ArgumentParser;
+use crate::deadline::Deadline;
+use crate::error::{AppError, Result};
+use crate::format::MediaInfo;
+use crate::history::{HistoryRecord, HistoryStore, InvocationTrace};
+use crate::logger::{Level, Logger};
+use crate::media_server::MediaServer;
+use crate::models::AppConfig;
+use crate::output::OutputValidator;
+use crate::player::PlayerDetector;
+use crate::policy::{ContentPolicy, DomainPolicy};
+use crate::relay::{HlsRelay, RelaySessions};
+use crate::rewrite::UrlRewriter;
+use crate::resolver::{
+    CacheResolver, ContentCheck, Resolution, ResolveRequest, ResolverContext, ResolverPipeline, YtDlpResolver,
+};
+use crate::rules;
+
+/// Handles VRChat requests end to end, either in the shim process or inside the daemon
+pub struct ResolveService {
+    config: AppConfig,
+    context: ResolverContext,
+    pipeline: ResolverPipeline,
+    players: PlayerDetector,
+    rewriter: UrlRewriter,
+    relay_sessions: RelaySessions,
+    history: HistoryStore,
+    /// Runs requests that bypass the pipeline: raw yt-dlp calls and metadata checks
+    ytdlp: YtDlpResolver,
+    /// Player profile used instead of detecting one, e.g. the one recorded for a replay
+    forced_player: Option<String>,
+    logger: Logger,
+}
+
+impl ResolveService {
+    pub fn new(config: AppConfig, context: ResolverContext) -> Result<Self> {
+        let pipeline = ResolverPipeline::from_config(&config, &context)?;
+        let logger = context.logger.for_module(module_path!());
+        let players = PlayerDetector::new(config.players.clone(), &context.app_dir, logger.clone());
+        let rewriter = UrlRewriter::new(&config.rewrite, logger.clone())?;
+        let ytdlp = YtDlpResolver::new(config.clone(), &context);
+        let relay_sessions = RelaySessions::new(&context.app_dir);
+        let history = HistoryStore::new(&config.history, &context.app_dir, logger.clone());
+
+        Ok(Self {
+            config,
+            context,
+            pipeline,
+            players,
+            rewriter,
+            relay_sessions,
+            history,
+            ytdlp,
+            forced_player: None,
+            logger,
+        })
+    }
+
+    /// Resolves every request for the given player profile instead of detecting it
+    pub fn with_player(mut self, player: Option<String>) -> Self {
+        self.forced_player = player;
+        self
+    }
+
+    /// Handles one request and returns the lines to print for VRChat
+    pub async fn handle(&self, args: &[String]) -> Result<Vec<String>> {
+        let (result, record) = self.handle_recorded(args).await;
+        if self.config.history.enabled {
+            self.history.append(&record);
+        }
+        result
+    }
+
+    /// Handles one request and also returns its history record, leaving storing it to the caller
+    pub async fn handle_recorded(&self, args: &[String]) -> (Result<Vec<String>>, HistoryRecord) {
+        let started = Instant::now();
+        // Every phase below draws from the same request budget
+        let deadline = Deadline::new(Duration::from_secs(self.config.execution.deadline_secs));
+        let trace = InvocationTrace::default();
+        let mut record = HistoryRecord {
+            timestamp: Utc::now(),
+            invocation_id: self.logger.invocation_id().map(|id| id.to_string()),
+            args: args.to_vec(),
+            requested_url: ArgumentParser::requested_url(args).map(str::to_string),
+            ..Default::default()
+        };
+
+        // VRChat's log mentions the URL as requested, so the player is detected before rewriting
+        let player = Self::resolve_target(args).map(|url| match &self.forced_player {
+            Some(player) => player.clone(),
+            None => self.players.detect(args, url),
+        });
+        let args = &self.rewriter.rewrite_args(args);
+
+        let result = match (self.check_policy(args), Self::resolve_target(args)) {
+            (Err(e), _) => Err(e),
+            (Ok(()), Some(url)) => {
+                let request = ResolveRequest {
+                    args: args.to_vec(),
+                    url: url.to_string(),
+                    deadline,
+                    bypass_cache: false,
+                    player: player.unwrap_or_else(|| self.config.players.default_profile.clone()),
+                    trace: trace.clone(),
+                    metadata: None,
+                };
+                record.player = Some(request.player.clone());
+                self.resolve(&request, &mut record).await
+            }
+            // Requests that aren't URL lookups go straight to yt-dlp and print whatever it prints
+            (Ok(()), None) => self.ytdlp.run_raw(args, &deadline).await,
+        };
+
+        let elapsed = started.elapsed();
+        let fields = [
+            ("url", json!(record.requested_url)),
+            ("resolver", json!(record.resolver)),
+            ("duration_ms", json!(elapsed.as_millis() as u64)),
+        ];
+        match &result {
+            Ok(_) => self.logger.event(Level::Info, "Success", &fields),
+            Err(e) => {
+                let class = e.failure_class().as_str();
+                let mut fields = fields.to_vec();
+                fields.push(("failure_class", json!(class)));
+                fields.push(("exit_code", json!(e.exit_code())));
+                self.logger.event(Level::Error, &format!("Failed ({}): {}", class, e), &fields);
+            }
+        }
+
+        record.canonical_url = ArgumentParser::requested_url(args).map(str::to_string);
+        record.rule = record
+            .canonical_url
+            .as_deref()
+            .and_then(|url| rules::find_rule(&self.config.rules, url))
+            .map(|rule| rule.name.clone());
+        record.ytdlp_version = self.ytdlp.version();
+        record.success = result.is_ok();
+        if let Err(e) = &result {
+            record.failure_class = Some(e.failure_class().as_str().to_string());
+            record.error = Some(e.to_string());
+        }
+        trace.fill(&mut record);
+        record.total_ms = elapsed.as_millis() as u64;
+
+        (self.substitute_placeholder(result, args), record)
+    }
+
+    /// Runs a URL lookup through the pipeline, with its content check, and the relay
+    async fn resolve(&self, request: &ResolveRequest, record: &mut HistoryRecord) -> Result<Vec<String>> {
+        let started = Instant::now();
+        let resolution = self.pipeline.resolve_checked(request, Some(self)).await?;
+        request.trace.add_phase("resolve", started.elapsed());
+        record.resolver = Some(resolution.resolver.to_string());
+        record.resolved_host = rules::host_of(&resolution.url);
+
+        Ok(vec![self.relay(request, resolution).await])
+    }
+
+    pub fn config(&self) -> &AppConfig {
+        &self.config
+    }
+
+    /// Swaps an HLS manifest for a relay URL when the relay is enabled for the request.
+    ///
+    /// Falls back to the upstream manifest if the relay can't be set up.
+    async fn relay(&self, request: &ResolveRequest, resolution: Resolution) -> String {
+        let relay = &self.config.relay;
+        if !relay.enabled || !HlsRelay::is_hls(&resolution.url) {
+            return resolution.url;
+        }
+        if !relay.hosts.is_empty()
+            && !rules::host_of(&request.url).is_some_and(|host| rules::host_matches_any(&host, &relay.hosts))
+        {
+            return resolution.url;
+        }
+
+        let port = self.config.library.server_port;
+        let started = Instant::now();
+        let relayed = async {
+            let id = self.relay_sessions.register(&request.url, &resolution.url, &request.player)?;
+            MediaServer::ensure_running(port, &self.logger).await?;
+            Ok::<_, AppError>(HlsRelay::url_for(port, &id))
+        };
+
+        let relayed = relayed.await;
+        request.trace.add_phase("relay", started.elapsed());
+        match relayed {
+            Ok(url) => {
+                self.logger.log_info(&format!("Relaying {} through {}", request.url, url));
+                url
+            }
+            Err(e) => {
+                self.logger
+                    .log_warning(&format!("Relay unavailable, returning the upstream manifest: {}", e));
+                resolution.url
+            }
+        }
+    }
+
+    /// Resolves a URL again, skipping the cache, so the relay can replace an expired manifest
+    pub async fn resolve_upstream(&self, url: &str, player: &str) -> Result<String> {
+        let request = ResolveRequest {
+            args: vec!["--get-url".to_string(), url.to_string()],
+            url: url.to_string(),
+            deadline: Deadline::new(Duration::from_secs(self.config.execution.deadline_secs)),
+            bypass_cache: true,
+            player: player.to_string(),
+            trace: InvocationTrace::default(),
+            metadata: None,
+        };
+
+        self.pipeline.resolve(&request).await.map(|resolution| resolution.url)
+    }
+
+    /// Refuses requests for hosts the domain policy doesn't allow, before anything is spawned.
+    ///
+    /// Every input that reaches yt-dlp is checked, not just the URL being resolved.
+    fn check_policy(&self, args: &[String]) -> Result<()> {
+        let policy = DomainPolicy::new(&self.config.policy);
+        let inputs = ArgumentParser::requested_url(args)
+            .into_iter()
+            .chain(ArgumentParser::forwarded_values(args, &self.config));
+
+        for input in inputs {
+            let checked = if ArgumentParser::is_http_url(input) {
+                policy.check(input)
+            } else if self.config.policy.is_restrictive() {
+                // Search queries, bare IDs and other schemes have no host to check
+                Err(AppError::Blocked(format!("{} is not an http(s) URL", input)))
+            } else {
+                Ok(())
+            };
+            checked.inspect_err(|e| self.logger.log_warning(&format!("Refusing {}: {}", input, e)))?;
+        }
+
+        Ok(())
+    }
+
+    fn cache_enabled(&self) -> bool {
+        self.config.cache.enabled && self.config.resolvers.iter().any(|r| r == CacheResolver::NAME)
+    }
+
+    /// Hands VRChat the placeholder configured for the failure class instead of an error
+    fn substitute_placeholder(&self, result: Result<Vec<String>>, args: &[String]) -> Result<Vec<String>> {
+        let Err(e) = &result else { return result };
+        if Self::resolve_target(args).is_none() {
+            return result;
+        }
+
+        let class = e.failure_class();
+        match self.config.placeholders.for_class(class) {
+            Some(placeholder) => {
+                self.logger
+                    .log_info(&format!("Serving {} placeholder: {}", class.as_str(), placeholder));
+                Ok(vec![placeholder.clone()])
+            }
+            None => result,
+        }
+    }
+
+    /// Resolves a URL ahead of time so the real request is a cache hit.
+    ///
+    /// Nothing is playing yet, so the URL is resolved for every player profile.
+    /// Returns `false` when each cached resolution is still fresh enough to be used.
+    pub async fn prefetch(&self, url: &str) -> Result<bool> {
+        let url = &self.canonical_url(url);
+        DomainPolicy::new(&self.config.policy).check(url)?;
+        if !self.cache_enabled() {
+            return Err(AppError::Config("Prefetching requires the cache resolver to be enabled".to_string()));
+        }
+
+        let players = &self.config.players;
+        let mut profiles: Vec<&str> = players.profiles.iter().map(|p| p.name.as_str()).collect();
+        if !profiles.contains(&players.default_profile.as_str()) {
+            profiles.push(&players.default_profile);
+        }
+
+        let mut resolved = false;
+        for player in profiles {
+            resolved |= self.prefetch_for(url, player).await?;
+        }
+        Ok(resolved)
+    }
+
+    async fn prefetch_for(&self, url: &str, player: &str) -> Result<bool> {
+        let request = ResolveRequest {
+            args: vec!["--get-url".to_string(), url.to_string()],
+            url: url.to_string(),
+            deadline: Deadline::new(Duration::from_secs(self.config.execution.deadline_secs)),
+            bypass_cache: true,
+            player: player.to_string(),
+            trace: InvocationTrace::default(),
+            metadata: None,
+        };
+
+        // Entries about to expire are refreshed so they are still valid when played
+        let margin = chrono::Duration::seconds(self.config.prefetch.refresh_margin_secs as i64);
+        if let Some(entry) = self.context.cache.get(&request.cache_key()) {
+            if entry.is_fresh(margin) {
+                return Ok(false);
+            }
+        }
+
+        self.logger.log_info(&format!("Prefetching {} for {}", url, player));
+        self.pipeline.resolve_checked(&request, Some(self)).await?;
+        Ok(true)
+    }
+
+    /// The URL after rewrite rules, as requests for it are cached and recorded
+    pub fn canonical_url(&self, url: &str) -> String {
+        self.rewriter.rewrite(url)
+    }
+
+    /// When each canonical URL was last served since the given time, read from the history
+    pub fn last_played(&self, since: DateTime<Utc>) -> HashMap<String, DateTime<Utc>> {
+        let mut played = HashMap::new();
+        for record in self.history.load() {
+            if let Some(url) = record.canonical_url.filter(|_| record.success && record.timestamp >= since) {
+                played.insert(url, record.timestamp);
+            }
+        }
+        played
+    }
+
+    /// Finds the URL to resolve when the arguments ask for a media URL
+    fn resolve_target(args: &[String]) -> Option<&str> {
+        ArgumentParser::requested_url(args).filter(|_| OutputValidator::expects_urls(args))
+    }
+}
+
+#[async_trait]
+impl ContentCheck for ResolveService {
+    /// Checks the video's metadata against the content policy before a resolver extracts it.
+    ///
+    /// Returns the fetched metadata so the resolver doesn't have to dump it again.
+    async fn check(&self, request: &ResolveRequest) -> Result<Option<Arc<MediaInfo>>> {
+        if !self.config.content_policy.enabled {
+            return Ok(None);
+        }
+        let policy = ContentPolicy::new(&self.config.content_policy);
+
+        let started = Instant::now();
+        let info = self
+            .ytdlp
+            .fetch_metadata(&request.args, Some(&request.player), &request.deadline)
+            .await;
+        request.trace.add_phase("content_check", started.elapsed());
+        let info = info?;
+        policy
+            .check(&info)
+            .inspect_err(|e| self.logger.log_warning(&format!("Refusing {}: {}", request.url, e)))?;
+        Ok(Some(Arc::new(info)))
+    }
+}